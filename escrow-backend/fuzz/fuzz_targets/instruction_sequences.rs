@@ -0,0 +1,798 @@
+#![no_main]
+
+//feeds arbitrary instruction sequences to the processor against an in-memory runtime: one
+//escrow, its vault, two mints and token accounts for a maker, a taker and a stranger. token
+//program cpis run in process through the syscall stubs and a failing instruction rolls its
+//accounts back like a failed transaction. after every instruction, failed or not, the token
+//ledger has to agree with the escrow state. run it from escrow-backend with
+//cargo fuzz run instruction_sequences -- -close_fd_mask=1
+//the fd mask silences the program logs, which otherwise dominate the run time
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use solana_escrow::{
+    instructions::EscrowInstruction, processor::process_instruction, Escrow, EscrowStatus,
+};
+use solana_program::{
+    account_info::AccountInfo,
+    clock::Clock,
+    entrypoint::{ProgramResult, SUCCESS},
+    instruction::{AccountMeta, Instruction},
+    program_error::ProgramError,
+    program_option::COption,
+    program_pack::Pack,
+    program_stubs::{set_syscall_stubs, SyscallStubs},
+    pubkey::Pubkey,
+    rent::Rent,
+    sysvar,
+};
+use solana_system_interface::program as system_program;
+use spl_associated_token_account_client::address::get_associated_token_address_with_program_id;
+use spl_token::state::{Account as TokenAccount, AccountState, Mint};
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Once;
+
+const PROGRAM_ID: Pubkey = Pubkey::new_from_array([7; 32]);
+const START_TIME: i64 = 1_700_000_000;
+const STARTING_BALANCE: u64 = 100_000;
+
+static NOW: AtomicI64 = AtomicI64::new(START_TIME);
+static STUBS: Once = Once::new();
+
+//account slots, token accounts follow the parties in TOKEN_ACCOUNTS
+const MAKER: usize = 0;
+const TAKER: usize = 1;
+const STRANGER: usize = 2;
+const ESCROW: usize = 3;
+const VAULT: usize = 4;
+const VAULT_A: usize = 5;
+const VAULT_B: usize = 6;
+const TOKEN_ACCOUNTS: usize = 7;
+const MINT_A: usize = 13;
+const MINT_B: usize = 14;
+const CONFIG: usize = 15;
+const RENT: usize = 16;
+const TOKEN_PROGRAM: usize = 17;
+const SYSTEM_PROGRAM: usize = 18;
+const ACCOUNT_COUNT: usize = 19;
+
+#[derive(Arbitrary, Debug, Clone, Copy, PartialEq)]
+enum Party {
+    Maker,
+    Taker,
+    Stranger,
+}
+
+impl Party {
+    const ALL: [Party; 3] = [Party::Maker, Party::Taker, Party::Stranger];
+
+    fn slot(self) -> usize {
+        match self {
+            Party::Maker => MAKER,
+            Party::Taker => TAKER,
+            Party::Stranger => STRANGER,
+        }
+    }
+}
+
+#[derive(Arbitrary, Debug, Clone, Copy, PartialEq)]
+enum Side {
+    A,
+    B,
+}
+
+fn token_account(party: Party, side: Side) -> usize {
+    TOKEN_ACCOUNTS + party.slot() * 2 + side as usize
+}
+
+fn vault_token(side: Side) -> usize {
+    match side {
+        Side::A => VAULT_A,
+        Side::B => VAULT_B,
+    }
+}
+
+#[derive(Arbitrary, Debug)]
+enum Step {
+    InitEscrow {
+        caller: Party,
+        amount_a: u16,
+        amount_b: u16,
+        permissionless_settlement: bool,
+        keeper_tip: u8,
+    },
+    Deposit {
+        caller: Party,
+        side: Side,
+        vault: Side,
+        amount: u16,
+        //settle in the same instruction, paying b to the first party and a to the second
+        payout: Option<(Party, Party)>,
+    },
+    CompleteSwap {
+        caller: Party,
+        pay_b_to: Party,
+        pay_a_to: Party,
+    },
+    Cancel {
+        caller: Party,
+        refund_a_to: Party,
+        refund_b_to: Party,
+    },
+    UpdateTerms {
+        caller: Party,
+        amount_a: u16,
+        amount_b: u16,
+        refund_to: Party,
+    },
+    ProposeCounter {
+        caller: Party,
+        amount_a: u16,
+        amount_b: u16,
+    },
+    AcceptCounter {
+        caller: Party,
+        refund_to: Party,
+    },
+    AdvanceClock {
+        seconds: u32,
+    },
+    //any instruction data against any accounts, the flag marks a party as signer
+    Raw {
+        data: Vec<u8>,
+        accounts: Vec<(u8, bool)>,
+    },
+}
+
+//how the expected account list is handed to the program
+#[derive(Arbitrary, Debug)]
+enum AccountList {
+    Expected,
+    Truncated(u8),
+    //each byte picks an account of the expected list, repeats allowed
+    Shuffled(Vec<u8>),
+}
+
+#[derive(Arbitrary, Debug)]
+struct Call {
+    step: Step,
+    accounts: AccountList,
+}
+
+#[derive(Clone)]
+struct Account {
+    key: Pubkey,
+    owner: Pubkey,
+    lamports: u64,
+    data: Vec<u8>,
+    executable: bool,
+}
+
+struct Runtime {
+    accounts: Vec<Account>,
+    initial_balances: Vec<u64>,
+    initial_lamports: u64,
+}
+
+fn token_account_data(mint: Pubkey, owner: Pubkey, amount: u64) -> Vec<u8> {
+    let mut data = vec![0; TokenAccount::LEN];
+    TokenAccount::pack(
+        TokenAccount {
+            mint,
+            owner,
+            amount,
+            state: AccountState::Initialized,
+            ..TokenAccount::default()
+        },
+        &mut data,
+    )
+    .unwrap();
+    data
+}
+
+fn mint_data(supply: u64) -> Vec<u8> {
+    let mut data = vec![0; Mint::LEN];
+    Mint::pack(
+        Mint {
+            mint_authority: COption::None,
+            supply,
+            decimals: 0,
+            is_initialized: true,
+            freeze_authority: COption::None,
+        },
+        &mut data,
+    )
+    .unwrap();
+    data
+}
+
+//bincode layout of Rent: lamports_per_byte_year, exemption_threshold, burn_percent
+fn rent_data() -> Vec<u8> {
+    let rent = Rent::default();
+    let mut data = rent.lamports_per_byte_year.to_le_bytes().to_vec();
+    data.extend_from_slice(&rent.exemption_threshold.to_le_bytes());
+    data.push(rent.burn_percent);
+    data
+}
+
+impl Runtime {
+    fn new() -> Self {
+        let rent = Rent::default();
+        let escrow_key = Pubkey::new_from_array([3; 32]);
+        let vault_key =
+            Pubkey::find_program_address(&[b"vault", escrow_key.as_ref()], &PROGRAM_ID).0;
+        let config_key = Pubkey::find_program_address(&[b"config"], &PROGRAM_ID).0;
+        let mint_keys = [
+            Pubkey::new_from_array([13; 32]),
+            Pubkey::new_from_array([14; 32]),
+        ];
+
+        let system_account = |key: Pubkey, lamports: u64| Account {
+            key,
+            owner: system_program::id(),
+            lamports,
+            data: Vec::new(),
+            executable: false,
+        };
+        let token = |key: Pubkey, data: Vec<u8>| Account {
+            key,
+            owner: spl_token::id(),
+            lamports: rent.minimum_balance(data.len()),
+            data,
+            executable: false,
+        };
+
+        let mut accounts = Vec::with_capacity(ACCOUNT_COUNT);
+        for party in Party::ALL {
+            accounts.push(system_account(
+                Pubkey::new_from_array([party.slot() as u8 + 100; 32]),
+                1_000_000_000,
+            ));
+        }
+        accounts.push(Account {
+            key: escrow_key,
+            owner: PROGRAM_ID,
+            lamports: rent.minimum_balance(Escrow::LEN),
+            data: vec![0; Escrow::LEN],
+            executable: false,
+        });
+        accounts.push(system_account(vault_key, 0));
+        for (index, mint) in mint_keys.iter().enumerate() {
+            accounts.push(token(
+                Pubkey::new_from_array([VAULT_A as u8 + index as u8; 32]),
+                token_account_data(*mint, vault_key, 0),
+            ));
+        }
+        //settlement only pays into associated token accounts, so the parties hold those
+        for party in Party::ALL {
+            for mint in mint_keys {
+                let owner = accounts[party.slot()].key;
+                accounts.push(token(
+                    get_associated_token_address_with_program_id(&owner, &mint, &spl_token::id()),
+                    token_account_data(mint, owner, STARTING_BALANCE),
+                ));
+            }
+        }
+        for mint in mint_keys {
+            accounts.push(token(mint, mint_data(STARTING_BALANCE * 3)));
+        }
+        accounts.push(system_account(config_key, 0));
+        accounts.push(Account {
+            key: sysvar::rent::id(),
+            owner: sysvar::id(),
+            lamports: 1,
+            data: rent_data(),
+            executable: false,
+        });
+        for program in [spl_token::id(), system_program::id()] {
+            accounts.push(Account {
+                key: program,
+                owner: solana_program::bpf_loader::id(),
+                lamports: 1,
+                data: Vec::new(),
+                executable: true,
+            });
+        }
+        assert_eq!(accounts.len(), ACCOUNT_COUNT);
+
+        let mut runtime = Runtime {
+            accounts,
+            initial_balances: Vec::new(),
+            initial_lamports: 0,
+        };
+        runtime.initial_balances = (0..ACCOUNT_COUNT)
+            .map(|slot| runtime.balance(slot))
+            .collect();
+        runtime.initial_lamports = runtime.total_lamports();
+        runtime
+    }
+
+    fn key(&self, slot: usize) -> Pubkey {
+        self.accounts[slot].key
+    }
+
+    //token balance, 0 for anything that is not a token account
+    fn balance(&self, slot: usize) -> u64 {
+        let account = &self.accounts[slot];
+        if account.owner != spl_token::id() || account.data.len() != TokenAccount::LEN {
+            return 0;
+        }
+        TokenAccount::unpack(&account.data).map_or(0, |token| token.amount)
+    }
+
+    fn total_lamports(&self) -> u64 {
+        self.accounts.iter().map(|account| account.lamports).sum()
+    }
+
+    //runs `instruction` as its own transaction, a failure leaves every account untouched
+    fn process(&mut self, instruction: &Instruction) -> ProgramResult {
+        let slots: Vec<usize> = instruction
+            .accounts
+            .iter()
+            .map(|meta| {
+                self.accounts
+                    .iter()
+                    .position(|account| account.key == meta.pubkey)
+                    .expect("instructions only use runtime accounts")
+            })
+            .collect();
+        let snapshot = self.accounts.clone();
+
+        let result = {
+            //duplicates share one AccountInfo, the way the runtime aliases them
+            let infos: Vec<AccountInfo> = self
+                .accounts
+                .iter_mut()
+                .map(|account| {
+                    AccountInfo::new(
+                        &account.key,
+                        false,
+                        false,
+                        &mut account.lamports,
+                        &mut account.data[..],
+                        &account.owner,
+                        account.executable,
+                        0,
+                    )
+                })
+                .collect();
+            let metas: Vec<AccountInfo> = slots
+                .iter()
+                .zip(&instruction.accounts)
+                .map(|(slot, meta)| {
+                    let mut info = infos[*slot].clone();
+                    info.is_signer = meta.is_signer;
+                    info.is_writable = meta.is_writable;
+                    info
+                })
+                .collect();
+
+            process_instruction(&PROGRAM_ID, &metas, &instruction.data)
+        };
+
+        if result.is_err() {
+            self.accounts = snapshot;
+        }
+        result
+    }
+
+    fn escrow(&self) -> Escrow {
+        *Escrow::load(&self.accounts[ESCROW].data).expect("escrow account holds invalid state")
+    }
+
+    fn party_of(&self, key: &Pubkey) -> Option<Party> {
+        Party::ALL
+            .into_iter()
+            .find(|party| &self.key(party.slot()) == key)
+    }
+
+    fn gain(&self, party: Party, side: Side) -> i128 {
+        let slot = token_account(party, side);
+        self.balance(slot) as i128 - self.initial_balances[slot] as i128
+    }
+
+    fn check_invariants(&self) {
+        //nothing is minted or burned, tokens only move between accounts
+        for side in [Side::A, Side::B] {
+            let total: u64 = Party::ALL
+                .iter()
+                .map(|party| self.balance(token_account(*party, side)))
+                .sum::<u64>()
+                + self.balance(vault_token(side));
+            assert_eq!(
+                total,
+                STARTING_BALANCE * 3,
+                "token {:?} supply changed",
+                side
+            );
+        }
+        assert_eq!(
+            self.total_lamports(),
+            self.initial_lamports,
+            "lamports created or burned"
+        );
+
+        let escrow = self.escrow();
+
+        //only a closed escrow holds the payout lock, and it stays closed
+        assert_eq!(
+            escrow.settlement_locked,
+            escrow.status != EscrowStatus::Active,
+            "settlement lock does not match the escrow {:?}",
+            escrow
+        );
+
+        //vault balance equals the sum of what is deposited and not yet paid out
+        let held = |deposited: bool, amount: u64| if deposited { amount } else { 0 };
+        assert_eq!(
+            self.balance(VAULT_A),
+            held(escrow.token_a_deposited, escrow.amount_a.get()),
+            "vault a does not match the escrow {:?}",
+            escrow
+        );
+        assert_eq!(
+            self.balance(VAULT_B),
+            held(escrow.token_b_deposited, escrow.amount_b.get()),
+            "vault b does not match the escrow {:?}",
+            escrow
+        );
+
+        //no party ever receives more than the counterparty deposited, and only by settling
+        let maker = self.party_of(&escrow.user_a);
+        let taker = self.party_of(&escrow.user_b);
+        let loss = |party: Option<Party>, side: Side| {
+            party.map_or(0, |party| -self.gain(party, side).min(0))
+        };
+        for party in Party::ALL {
+            for side in [Side::A, Side::B] {
+                let gain = self.gain(party, side);
+                if gain <= 0 {
+                    continue;
+                }
+
+                let (receiver, counterparty) = match side {
+                    Side::A => (taker, maker),
+                    Side::B => (maker, taker),
+                };
+                assert_eq!(
+                    receiver,
+                    Some(party),
+                    "{:?} gained {} of token {:?} without being its receiver",
+                    party,
+                    gain,
+                    side
+                );
+                assert_eq!(
+                    escrow.status,
+                    EscrowStatus::Completed,
+                    "{:?} gained token {:?} before settlement",
+                    party,
+                    side
+                );
+                assert!(
+                    gain <= loss(counterparty, side),
+                    "{:?} received {} of token {:?}, more than the counterparty deposited",
+                    party,
+                    gain,
+                    side
+                );
+            }
+        }
+    }
+}
+
+struct Stubs;
+
+impl SyscallStubs for Stubs {
+    fn sol_log(&self, _message: &str) {}
+
+    fn sol_log_data(&self, _fields: &[&[u8]]) {}
+
+    //the token program runs in process, the system program only moves lamports
+    fn sol_invoke_signed(
+        &self,
+        instruction: &Instruction,
+        account_infos: &[AccountInfo],
+        signers_seeds: &[&[&[u8]]],
+    ) -> ProgramResult {
+        let pda_signers = signers_seeds
+            .iter()
+            .map(|seeds| Pubkey::create_program_address(seeds, &PROGRAM_ID))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| ProgramError::InvalidSeeds)?;
+
+        let mut infos = Vec::with_capacity(instruction.accounts.len());
+        for meta in &instruction.accounts {
+            let info = account_infos
+                .iter()
+                .find(|info| info.key == &meta.pubkey)
+                .ok_or(ProgramError::NotEnoughAccountKeys)?;
+            if meta.is_signer && !info.is_signer && !pda_signers.contains(&meta.pubkey) {
+                return Err(ProgramError::MissingRequiredSignature);
+            }
+            if meta.is_writable && !info.is_writable {
+                return Err(ProgramError::InvalidArgument);
+            }
+
+            let mut info = info.clone();
+            info.is_signer = meta.is_signer;
+            info.is_writable = meta.is_writable;
+            infos.push(info);
+        }
+
+        if instruction.program_id == spl_token::id() {
+            spl_token::processor::Processor::process(
+                &instruction.program_id,
+                &infos,
+                &instruction.data,
+            )
+        } else if instruction.program_id == system_program::id() {
+            system_transfer(&infos, &instruction.data)
+        } else {
+            Err(ProgramError::IncorrectProgramId)
+        }
+    }
+
+    fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+        let clock = Clock {
+            unix_timestamp: NOW.load(Ordering::Relaxed),
+            ..Clock::default()
+        };
+        unsafe { *(var_addr as *mut Clock) = clock };
+        SUCCESS
+    }
+
+    fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+        unsafe { *(var_addr as *mut Rent) = Rent::default() };
+        SUCCESS
+    }
+}
+
+//SystemInstruction::Transfer is bincode: u32 variant 2 then the lamports
+fn system_transfer(infos: &[AccountInfo], data: &[u8]) -> ProgramResult {
+    let [from, to, ..] = infos else {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    };
+    if data.len() != 12 || data[..4] != 2u32.to_le_bytes() {
+        return Err(ProgramError::InvalidInstructionData);
+    }
+    let lamports = u64::from_le_bytes(data[4..].try_into().unwrap());
+
+    if !from.is_signer || from.owner != &system_program::id() {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let remaining = from
+        .lamports()
+        .checked_sub(lamports)
+        .ok_or(ProgramError::InsufficientFunds)?;
+    **from.try_borrow_mut_lamports()? = remaining;
+    **to.try_borrow_mut_lamports()? += lamports;
+    Ok(())
+}
+
+fn signer(runtime: &Runtime, slot: usize) -> AccountMeta {
+    AccountMeta::new(runtime.key(slot), true)
+}
+
+fn writable(runtime: &Runtime, slot: usize) -> AccountMeta {
+    AccountMeta::new(runtime.key(slot), false)
+}
+
+fn readonly(runtime: &Runtime, slot: usize) -> AccountMeta {
+    AccountMeta::new_readonly(runtime.key(slot), false)
+}
+
+//maker, escrow, vault_authority, vault_token_a, user_a_token_a, token_program
+fn terms_accounts(runtime: &Runtime, caller: Party, refund_to: Party) -> Vec<AccountMeta> {
+    vec![
+        signer(runtime, caller.slot()),
+        writable(runtime, ESCROW),
+        readonly(runtime, VAULT),
+        writable(runtime, VAULT_A),
+        writable(runtime, token_account(refund_to, Side::A)),
+        readonly(runtime, TOKEN_PROGRAM),
+    ]
+}
+
+fn build(runtime: &Runtime, step: &Step) -> Option<(EscrowInstruction, Vec<AccountMeta>)> {
+    let built = match *step {
+        Step::InitEscrow {
+            caller,
+            amount_a,
+            amount_b,
+            permissionless_settlement,
+            keeper_tip,
+        } => (
+            EscrowInstruction::InitEscrow {
+                token_a_mint: runtime.key(MINT_A),
+                token_b_mint: runtime.key(MINT_B),
+                amount_a: amount_a.into(),
+                amount_b: amount_b.into(),
+                hash_lock: None,
+                delegated_source: None,
+                maker_multisig: None,
+                permissionless_settlement,
+                keeper_tip: keeper_tip.into(),
+                auction: None,
+                oracle: None,
+                condition: None,
+                credential_issuer: None,
+                reference: None,
+            },
+            vec![
+                signer(runtime, caller.slot()),
+                writable(runtime, ESCROW),
+                readonly(runtime, RENT),
+                readonly(runtime, CONFIG),
+                readonly(runtime, SYSTEM_PROGRAM),
+            ],
+        ),
+        Step::Deposit {
+            caller,
+            side,
+            vault,
+            amount,
+            payout,
+        } => {
+            let mut accounts = vec![
+                signer(runtime, caller.slot()),
+                writable(runtime, ESCROW),
+                writable(runtime, token_account(caller, side)),
+                writable(runtime, vault_token(vault)),
+                readonly(runtime, TOKEN_PROGRAM),
+                readonly(runtime, CONFIG),
+            ];
+            if let Some((pay_b_to, pay_a_to)) = payout {
+                let other_vault = match vault {
+                    Side::A => VAULT_B,
+                    Side::B => VAULT_A,
+                };
+                accounts.extend([
+                    readonly(runtime, VAULT),
+                    writable(runtime, other_vault),
+                    writable(runtime, token_account(pay_b_to, Side::B)),
+                    writable(runtime, token_account(pay_a_to, Side::A)),
+                ]);
+            }
+            (
+                EscrowInstruction::Deposit {
+                    amount: amount.into(),
+                    expected_amount_a_min: 0,
+                    expected_mint_a: runtime.key(MINT_A),
+                    settle: payout.is_some(),
+                },
+                accounts,
+            )
+        }
+        Step::CompleteSwap {
+            caller,
+            pay_b_to,
+            pay_a_to,
+        } => (
+            EscrowInstruction::CompleteSwap,
+            vec![
+                signer(runtime, caller.slot()),
+                writable(runtime, ESCROW),
+                readonly(runtime, VAULT),
+                writable(runtime, VAULT_A),
+                writable(runtime, VAULT_B),
+                writable(runtime, token_account(pay_b_to, Side::B)),
+                writable(runtime, token_account(pay_a_to, Side::A)),
+                readonly(runtime, TOKEN_PROGRAM),
+            ],
+        ),
+        Step::Cancel {
+            caller,
+            refund_a_to,
+            refund_b_to,
+        } => (
+            EscrowInstruction::Cancel,
+            vec![
+                signer(runtime, caller.slot()),
+                writable(runtime, ESCROW),
+                readonly(runtime, VAULT),
+                writable(runtime, VAULT_A),
+                writable(runtime, VAULT_B),
+                writable(runtime, token_account(refund_a_to, Side::A)),
+                writable(runtime, token_account(refund_b_to, Side::B)),
+                readonly(runtime, TOKEN_PROGRAM),
+            ],
+        ),
+        Step::UpdateTerms {
+            caller,
+            amount_a,
+            amount_b,
+            refund_to,
+        } => (
+            EscrowInstruction::UpdateTerms {
+                amount_a: amount_a.into(),
+                amount_b: amount_b.into(),
+            },
+            terms_accounts(runtime, caller, refund_to),
+        ),
+        Step::ProposeCounter {
+            caller,
+            amount_a,
+            amount_b,
+        } => (
+            EscrowInstruction::ProposeCounter {
+                amount_a: amount_a.into(),
+                amount_b: amount_b.into(),
+            },
+            vec![signer(runtime, caller.slot()), writable(runtime, ESCROW)],
+        ),
+        //the maker accepts the counter it currently sees
+        Step::AcceptCounter { caller, refund_to } => {
+            let escrow = runtime.escrow();
+            (
+                EscrowInstruction::AcceptCounter {
+                    proposer: escrow.counter_proposer,
+                    amount_a: escrow.counter_amount_a.get(),
+                    amount_b: escrow.counter_amount_b.get(),
+                },
+                terms_accounts(runtime, caller, refund_to),
+            )
+        }
+        Step::AdvanceClock { .. } | Step::Raw { .. } => return None,
+    };
+    Some(built)
+}
+
+fn shape(accounts: Vec<AccountMeta>, list: &AccountList) -> Vec<AccountMeta> {
+    match list {
+        AccountList::Expected => accounts,
+        AccountList::Truncated(len) => {
+            let len = *len as usize % (accounts.len() + 1);
+            accounts[..len].to_vec()
+        }
+        AccountList::Shuffled(picks) => picks
+            .iter()
+            .map(|pick| accounts[*pick as usize % accounts.len()].clone())
+            .collect(),
+    }
+}
+
+fn instruction(runtime: &Runtime, call: &Call) -> Option<Instruction> {
+    if let Step::Raw { data, accounts } = &call.step {
+        let accounts = accounts
+            .iter()
+            .map(|(slot, sign)| {
+                let slot = *slot as usize % ACCOUNT_COUNT;
+                //only the parties hold keypairs
+                let is_signer = *sign && slot <= STRANGER;
+                AccountMeta::new(runtime.key(slot), is_signer)
+            })
+            .collect();
+        return Some(Instruction {
+            program_id: PROGRAM_ID,
+            accounts,
+            data: data.clone(),
+        });
+    }
+
+    let (escrow_instruction, accounts) = build(runtime, &call.step)?;
+    Some(Instruction::new_with_borsh(
+        PROGRAM_ID,
+        &escrow_instruction,
+        shape(accounts, &call.accounts),
+    ))
+}
+
+fuzz_target!(|calls: Vec<Call>| {
+    STUBS.call_once(|| {
+        set_syscall_stubs(Box::new(Stubs));
+    });
+    NOW.store(START_TIME, Ordering::Relaxed);
+
+    let mut runtime = Runtime::new();
+    for call in calls.iter().take(64) {
+        if let Step::AdvanceClock { seconds } = call.step {
+            NOW.fetch_add(seconds as i64, Ordering::Relaxed);
+            continue;
+        }
+
+        if let Some(instruction) = instruction(&runtime, call) {
+            let _ = runtime.process(&instruction);
+        }
+        runtime.check_invariants();
+    }
+});
@@ -1,12 +1,39 @@
 use solana_program::program_error::ProgramError;
 // use thiserror::Error
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum EscrowError {
     AlreadyDeposited,
     EscrowNotReady,
     InvalidAmount,
     UnauthorizedCancel,
+    InvalidEscrowKind,
+    InvalidPreimage,
+    HashLockExpired,
+    HashLockNotExpired,
+    InvalidRecipient,
+    InvalidSignature,
+    OfferExpired,
+    OfferAlreadyFilled,
+    InvalidTokenAccount,
+    TermsLocked,
+    NoCounterOffer,
+    ProgramPaused,
+    InvalidMultisig,
+    MultisigThresholdNotMet,
+    InvalidParticipants,
+    InvalidAuction,
+    AuctionNotLive,
+    MarketFull,
+    InvalidOracle,
+    OracleStale,
+    OraclePriceOutOfBand,
+    SlippageExceeded,
+    MathOverflow,
+    InvalidCondition,
+    InvalidCredential,
+    CredentialExpired,
+    SettlementLocked,
 }
 
 impl From<EscrowError> for ProgramError {
@@ -16,6 +43,33 @@ impl From<EscrowError> for ProgramError {
             EscrowError::EscrowNotReady => ProgramError::Custom(1001),
             EscrowError::InvalidAmount => ProgramError::Custom(1002),
             EscrowError::UnauthorizedCancel => ProgramError::Custom(1003),
+            EscrowError::InvalidEscrowKind => ProgramError::Custom(1004),
+            EscrowError::InvalidPreimage => ProgramError::Custom(1005),
+            EscrowError::HashLockExpired => ProgramError::Custom(1006),
+            EscrowError::HashLockNotExpired => ProgramError::Custom(1007),
+            EscrowError::InvalidRecipient => ProgramError::Custom(1008),
+            EscrowError::InvalidSignature => ProgramError::Custom(1009),
+            EscrowError::OfferExpired => ProgramError::Custom(1010),
+            EscrowError::OfferAlreadyFilled => ProgramError::Custom(1011),
+            EscrowError::InvalidTokenAccount => ProgramError::Custom(1012),
+            EscrowError::TermsLocked => ProgramError::Custom(1013),
+            EscrowError::NoCounterOffer => ProgramError::Custom(1014),
+            EscrowError::ProgramPaused => ProgramError::Custom(1015),
+            EscrowError::InvalidMultisig => ProgramError::Custom(1016),
+            EscrowError::MultisigThresholdNotMet => ProgramError::Custom(1017),
+            EscrowError::InvalidParticipants => ProgramError::Custom(1018),
+            EscrowError::InvalidAuction => ProgramError::Custom(1019),
+            EscrowError::AuctionNotLive => ProgramError::Custom(1020),
+            EscrowError::MarketFull => ProgramError::Custom(1021),
+            EscrowError::InvalidOracle => ProgramError::Custom(1022),
+            EscrowError::OracleStale => ProgramError::Custom(1023),
+            EscrowError::OraclePriceOutOfBand => ProgramError::Custom(1024),
+            EscrowError::SlippageExceeded => ProgramError::Custom(1025),
+            EscrowError::MathOverflow => ProgramError::Custom(1026),
+            EscrowError::InvalidCondition => ProgramError::Custom(1027),
+            EscrowError::InvalidCredential => ProgramError::Custom(1028),
+            EscrowError::CredentialExpired => ProgramError::Custom(1029),
+            EscrowError::SettlementLocked => ProgramError::Custom(1030),
         }
     }
 }
@@ -1,9 +1,16 @@
+use crate::errors::EscrowError;
+use crate::{Auction, Condition, HashLock, MakerAction, PriceOracle};
 use borsh::{BorshDeserialize, BorshSerialize};
 use solana_program::{
     account_info::AccountInfo,
+    ed25519_program,
     program_error::ProgramError,
     pubkey::Pubkey,
-    sysvar::{Sysvar, rent::Rent},
+    sysvar::{
+        instructions::{load_current_index_checked, load_instruction_at_checked},
+        rent::Rent,
+        Sysvar,
+    },
 };
 
 #[derive(BorshDeserialize, BorshSerialize, Debug)]
@@ -14,12 +21,177 @@ pub enum EscrowInstruction {
         token_b_mint: Pubkey,
         amount_a: u64,
         amount_b: u64,
+        //makes this an htlc escrow paying hash_lock.recipient
+        hash_lock: Option<HashLock>,
+        //user_a's token a account approved to the vault pda, makes this an approval escrow
+        delegated_source: Option<Pubkey>,
+        //user_a becomes an m-of-n multisig, any listed signer can create the escrow
+        maker_multisig: Option<MultisigDescriptor>,
+        //lets any signer settle the funded escrow, not allowed for htlc escrows
+        permissionless_settlement: bool,
+        //lamports moved from user_a to the escrow account and paid to the settling keeper,
+        //needs permissionless_settlement, user_a's signature and the system program account
+        keeper_tip: u64,
+        //makes this an auction of amount_a, amount_b is ignored
+        auction: Option<Auction>,
+        //prices amount_b from an oracle feed, amount_b is ignored
+        oracle: Option<Box<PriceOracle>>,
+        //settlement also needs the condition program to accept the swap
+        condition: Option<Box<Condition>>,
+        //only takers holding a credential from this issuer can deposit or bid,
+        //not allowed for htlc escrows
+        credential_issuer: Option<Pubkey>,
+        //stored and echoed in the logs for reconciliation, settlement also writes it as an
+        //spl memo when the memo program account is passed
+        reference: Option<[u8; 32]>,
     },
+    //with `settle` the completing deposit also settles the swap, the payout accounts then
+    //follow the config account: vault_authority, the other side's vault token account
+    //(user_a's delegated source for approval escrows), user_a_token_b, user_b_token_a.
+    //for dutch auctions the buyer's amount is the most they pay and the current price is taken.
+    //oracle priced takers work the same way, they also pass the feed and must settle.
+    //takers of a credential gated escrow also pass their credential pda.
+    //missing payout accounts are created like in CompleteSwap
+    //expected_* are the depositor's view of the terms, the deposit fails if the escrow
+    //no longer matches them
     Deposit {
         amount: u64,
+        expected_amount_a_min: u64,
+        expected_mint_a: Pubkey,
+        settle: bool,
     },
+    //a conditional escrow also needs the condition program account, every account after it
+    //is passed on to the condition program.
+    //payouts go to the associated token accounts of the recipients, one that does not exist
+    //yet is created at the caller's expense when its owner, its mint, the system program and
+    //the associated token account program are passed after the token program
     CompleteSwap,
     Cancel,
+    //pays the htlc amount to user_b, the preimage is logged for the counterpart chain
+    ClaimHashLock {
+        preimage: [u8; 32],
+    },
+    //swaps against an offer the maker signed off-chain, the previous instruction
+    //must be the ed25519 verification of borsh(offer) by offer.maker
+    FillSignedOffer {
+        offer: SignedOffer,
+    },
+    //maker changes the amounts while no taker has deposited
+    UpdateTerms {
+        amount_a: u64,
+        amount_b: u64,
+    },
+    //prospective taker stores alternative amounts for the maker to accept
+    ProposeCounter {
+        amount_a: u64,
+        amount_b: u64,
+    },
+    //maker takes the pending counter offer, reserving the escrow for its proposer.
+    //proposer and amounts are the counter the maker saw, the accept fails if it was replaced
+    AcceptCounter {
+        proposer: Pubkey,
+        amount_a: u64,
+        amount_b: u64,
+    },
+    //creates the [b"config"] pda with the program's upgrade authority as admin,
+    //accounts: admin, config, system_program, the program's programdata account
+    InitConfig,
+    //admin kill switch for new escrows and deposits
+    SetPaused {
+        paused: bool,
+    },
+    //one multisig signer's approval of a user_a action, executed once the threshold is met.
+    //it replaces the signer's earlier approval, MakerActionKind::None withdraws it
+    Approve {
+        action: MakerAction,
+    },
+    //settles many funded escrows, accounts: caller, token_program, then per escrow
+    //escrow, vault_authority, vault_token_a, vault_token_b, user_a_token_b, user_b_token_a,
+    //market (any account, e.g. the program id, when the escrow is not listed)
+    //unready escrows are skipped and every outcome is logged, so are escrows whose payout
+    //accounts do not exist yet, CompleteSwap settles those
+    CompleteSwapBatch,
+    //creates a ring trade between 3 to MAX_RING_PARTICIPANTS parties, the vault is the
+    //usual [b"vault", ring] pda
+    InitRing {
+        participants: Vec<RingLeg>,
+    },
+    //funds the caller's leg of a ring trade
+    DepositRing {
+        amount: u64,
+    },
+    //pays every leg to the next participant, after caller, ring, vault_authority and
+    //token_program it takes per leg: vault token account, recipient token account
+    CompleteRing,
+    //refunds the funded legs while the ring is incomplete, per leg: vault token account,
+    //the leg owner's token account
+    CancelRing,
+    //raises the caller's english auction bid to `amount`, only the difference is transferred.
+    //accounts: bidder, escrow, bid pda, bidder_token_b, vault_token_b, token_program,
+    //system_program, config, then the bidder's credential pda for credential gated auctions
+    PlaceBid {
+        amount: u64,
+    },
+    //returns an outbid bidder's tokens from the vault
+    //accounts: bidder, escrow, bid pda, vault_authority, vault_token_b, bidder_token_b, token_program
+    RefundBid,
+    //creates the [b"market", token_a_mint, token_b_mint] pda, anyone can open a market
+    //accounts: payer, market, system_program. user_a's Deposit lists an open swap on it when
+    //the market account is passed, InitEscrow does the same for an approval escrow whose
+    //delegation already covers amount_a. settling or cancelling the escrow then needs it too
+    InitMarket {
+        token_a_mint: Pubkey,
+        token_b_mint: Pubkey,
+    },
+    //creates or updates the [b"credential", issuer, subject] pda, issuing with an expiry in
+    //the past revokes it. accounts: issuer, credential, system_program
+    IssueCredential {
+        subject: Pubkey,
+        expires_at: i64,
+    },
+}
+
+impl EscrowInstruction {
+    //instructions that open new positions, these need the config account and fail while paused
+    pub fn is_pausable(&self) -> bool {
+        matches!(
+            self,
+            EscrowInstruction::InitEscrow { .. }
+                | EscrowInstruction::Deposit { .. }
+                | EscrowInstruction::FillSignedOffer { .. }
+                | EscrowInstruction::InitRing { .. }
+                | EscrowInstruction::DepositRing { .. }
+                | EscrowInstruction::PlaceBid { .. }
+        )
+    }
+}
+
+//the message a maker signs off-chain, tokens are pulled through the [b"delegate"] pda
+#[derive(BorshDeserialize, BorshSerialize, Debug, Clone, PartialEq)]
+pub struct SignedOffer {
+    pub program_id: Pubkey,
+    pub maker: Pubkey,
+    pub token_a_mint: Pubkey,
+    pub token_b_mint: Pubkey,
+    pub amount_a: u64,
+    pub amount_b: u64,
+    //Pubkey::default() lets anyone take the offer
+    pub taker: Pubkey,
+    pub expiry: i64,
+    pub nonce: u64,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Debug, Clone, PartialEq)]
+pub struct MultisigDescriptor {
+    pub signers: Vec<Pubkey>,
+    pub threshold: u8,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Debug, Clone, PartialEq)]
+pub struct RingLeg {
+    pub owner: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
 }
 
 pub fn check_rent_exempt(
@@ -32,3 +204,45 @@ pub fn check_rent_exempt(
     }
     Ok(())
 }
+
+//checks that the instruction right before this one verified `signer`'s signature over `message`
+pub fn check_ed25519_signature(
+    instructions_sysvar: &AccountInfo,
+    signer: &Pubkey,
+    message: &[u8],
+) -> Result<(), ProgramError> {
+    let current_index = load_current_index_checked(instructions_sysvar)?;
+    if current_index == 0 {
+        return Err(EscrowError::InvalidSignature.into());
+    }
+
+    let ed25519_ix = load_instruction_at_checked(current_index as usize - 1, instructions_sysvar)?;
+    if ed25519_ix.program_id != ed25519_program::id() {
+        return Err(EscrowError::InvalidSignature.into());
+    }
+
+    //header: num_signatures, padding, then one 14 byte offsets struct
+    let data = &ed25519_ix.data;
+    if data.len() < 16 || data[0] != 1 {
+        return Err(EscrowError::InvalidSignature.into());
+    }
+
+    let read_u16 = |at: usize| u16::from_le_bytes([data[at], data[at + 1]]);
+    let public_key_offset = read_u16(6) as usize;
+    let message_offset = read_u16(10) as usize;
+    let message_size = read_u16(12) as usize;
+
+    //signature, public key and message must all live in the ed25519 instruction itself
+    if read_u16(4) != u16::MAX || read_u16(8) != u16::MAX || read_u16(14) != u16::MAX {
+        return Err(EscrowError::InvalidSignature.into());
+    }
+
+    let public_key = data.get(public_key_offset..public_key_offset + 32);
+    let signed_message = data.get(message_offset..message_offset + message_size);
+
+    if public_key != Some(signer.as_ref()) || signed_message != Some(message) {
+        return Err(EscrowError::InvalidSignature.into());
+    }
+
+    Ok(())
+}
@@ -1,28 +1,640 @@
 use borsh::{BorshDeserialize, BorshSerialize};
-use solana_program::pubkey::Pubkey;
+use bytemuck::{CheckedBitPattern, NoUninit, Pod, Zeroable};
+use errors::EscrowError;
+use math::Rounding;
+use pod::{PodI64, PodU16, PodU64};
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
 
 pub mod entrypoint;
 pub mod errors;
 pub mod instructions;
+pub mod math;
+pub mod oracle;
+pub mod pod;
 pub mod processor;
 
-#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+//same cap as spl-token multisig accounts
+pub const MAX_MULTISIG_SIGNERS: usize = 11;
+//largest cycle a ring trade can settle in one transaction
+pub const MAX_RING_PARTICIPANTS: usize = 8;
+//open offers a market keeps, new offers are rejected once it is full
+pub const MAX_MARKET_OFFERS: usize = 32;
+//opaque bytes a condition program is invoked with
+pub const MAX_CONDITION_DATA: usize = 32;
+
+#[derive(
+    BorshSerialize,
+    BorshDeserialize,
+    CheckedBitPattern,
+    NoUninit,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Default,
+)]
+//fixed size and cast straight from the account data, the byte layout is the same as its
+//borsh encoding so off-chain readers can keep using either
+#[repr(C)]
 pub struct Escrow {
     pub user_a: Pubkey,
     pub user_b: Pubkey,
     pub token_a_mint: Pubkey,
     pub token_b_mint: Pubkey,
-    pub amount_a: u64,
-    pub amount_b: u64,
+    pub amount_a: PodU64,
+    pub amount_b: PodU64,
     pub token_a_deposited: bool,
     pub token_b_deposited: bool,
     pub vault_pda: Pubkey,
     pub status: EscrowStatus,
+    pub kind: EscrowKind,
+    //only meaningful when kind == HashLock
+    pub hash_lock: HashLock,
+    //only meaningful when kind == Approval
+    pub delegated_source: Pubkey,
+    //pending counter offer, Pubkey::default() when there is none
+    pub counter_proposer: Pubkey,
+    pub counter_amount_a: PodU64,
+    pub counter_amount_b: PodU64,
+    //when enabled, user_a's actions need threshold approvals instead of user_a's signature
+    pub multisig: Multisig,
+    //any signer may call CompleteSwap once both sides are funded
+    pub permissionless_settlement: bool,
+    //lamports user_a left on the escrow account for whoever settles it
+    pub keeper_tip: PodU64,
+    //only meaningful for the auction kinds
+    pub auction: Auction,
+    //on the [b"market", token_a_mint, token_b_mint] book until completed or cancelled
+    pub listed: bool,
+    //only meaningful when kind == OraclePriced
+    pub oracle: PriceOracle,
+    //only meaningful when kind == Conditional
+    pub condition: Condition,
+    //takers need a live [b"credential", issuer, taker] account from this issuer, default = anyone
+    pub credential_issuer: Pubkey,
+    //caller's trade id / invoice number, logged with every event, all zeroes = none
+    pub reference: [u8; 32],
+    //bump of vault_pda, the vault signs with it instead of searching for it again
+    pub vault_bump: u8,
+    //taken by the first instruction paying out of the vault for good and never released,
+    //so an escrow is settled, refunded or claimed at most once
+    pub settlement_locked: bool,
+}
+
+impl Escrow {
+    pub const LEN: usize = std::mem::size_of::<Escrow>();
+
+    //checks the enum and bool bytes and views the account data as an escrow, nothing is decoded
+    pub fn load(data: &[u8]) -> Result<&Escrow, ProgramError> {
+        data.get(..Self::LEN)
+            .and_then(|bytes| bytemuck::checked::try_from_bytes(bytes).ok())
+            .ok_or(ProgramError::InvalidAccountData)
+    }
+
+    //load for handlers that change the escrow, the changes land in the account data directly
+    pub fn load_mut(data: &mut [u8]) -> Result<&mut Escrow, ProgramError> {
+        data.get_mut(..Self::LEN)
+            .and_then(|bytes| bytemuck::checked::try_from_bytes_mut(bytes).ok())
+            .ok_or(ProgramError::InvalidAccountData)
+    }
+
+    pub fn store(&self, data: &mut [u8]) -> Result<(), ProgramError> {
+        data.get_mut(..Self::LEN)
+            .ok_or(ProgramError::AccountDataTooSmall)?
+            .copy_from_slice(bytemuck::bytes_of(self));
+        Ok(())
+    }
 }
 
-#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+#[derive(
+    BorshSerialize,
+    BorshDeserialize,
+    CheckedBitPattern,
+    NoUninit,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Default,
+)]
+#[repr(u8)]
 pub enum EscrowStatus {
+    #[default]
     Active,
     Completed,
     Cancelled,
 }
+
+#[derive(
+    BorshSerialize,
+    BorshDeserialize,
+    CheckedBitPattern,
+    NoUninit,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Default,
+)]
+#[repr(u8)]
+pub enum EscrowKind {
+    //two sided swap, both users deposit into the vault
+    #[default]
+    Swap,
+    //one sided htlc, user_b is paid by revealing the preimage before the timeout
+    HashLock,
+    //user_a approves the vault pda on delegated_source instead of depositing,
+    //the tokens are pulled at settlement
+    Approval,
+    //user_a auctions amount_a, user_b / amount_b track the highest bid held in the vault
+    EnglishAuction,
+    //user_a sells amount_a at a price falling over time, the first buyer's deposit wins
+    DutchAuction,
+    //amount_b is priced from an oracle feed when the taker's deposit settles the swap
+    OraclePriced,
+    //a swap that only settles if the condition program accepts it
+    Conditional,
+}
+
+#[derive(
+    BorshSerialize,
+    BorshDeserialize,
+    CheckedBitPattern,
+    NoUninit,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Default,
+)]
+#[repr(u8)]
+pub enum HashAlgorithm {
+    #[default]
+    Sha256,
+    Keccak256,
+}
+
+#[derive(
+    BorshSerialize,
+    BorshDeserialize,
+    CheckedBitPattern,
+    NoUninit,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Default,
+)]
+#[repr(C)]
+pub struct HashLock {
+    pub recipient: Pubkey,
+    pub hash: [u8; 32],
+    pub algorithm: HashAlgorithm,
+    //unix timestamp after which user_a can refund
+    pub timeout: PodI64,
+}
+
+impl HashLock {
+    pub fn matches(&self, preimage: &[u8]) -> bool {
+        let digest = match self.algorithm {
+            HashAlgorithm::Sha256 => solana_program::hash::hash(preimage).to_bytes(),
+            HashAlgorithm::Keccak256 => solana_program::keccak::hash(preimage).to_bytes(),
+        };
+        digest == self.hash
+    }
+}
+
+#[derive(
+    BorshSerialize,
+    BorshDeserialize,
+    CheckedBitPattern,
+    NoUninit,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Default,
+)]
+#[repr(u8)]
+pub enum AuctionStyle {
+    #[default]
+    English,
+    Dutch,
+}
+
+#[derive(
+    BorshSerialize,
+    BorshDeserialize,
+    CheckedBitPattern,
+    NoUninit,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Default,
+)]
+#[repr(C)]
+pub struct Auction {
+    pub style: AuctionStyle,
+    //english: reserve for the first bid, dutch: price at start_time
+    pub start_price: PodU64,
+    //dutch: price reached at end_time
+    pub end_price: PodU64,
+    //english: smallest raise over the highest bid
+    pub min_increment: PodU64,
+    pub start_time: PodI64,
+    //bidding or buying closes at this unix timestamp
+    pub end_time: PodI64,
+}
+
+impl Auction {
+    pub fn is_live(&self, now: i64) -> bool {
+        now >= self.start_time.get() && now < self.end_time.get()
+    }
+
+    //decays linearly from start_price to end_price between start_time and end_time,
+    //the drop is rounded down so the seller never gets less than the line
+    pub fn dutch_price(&self, now: i64) -> Result<u64, EscrowError> {
+        let (start_time, end_time) = (self.start_time.get(), self.end_time.get());
+        let (start_price, end_price) = (self.start_price.get(), self.end_price.get());
+
+        if now <= start_time {
+            return Ok(start_price);
+        }
+        if now >= end_time {
+            return Ok(end_price);
+        }
+
+        let elapsed = now.abs_diff(start_time);
+        let duration = end_time.abs_diff(start_time);
+        let drop = math::sub(start_price, end_price)?;
+
+        math::sub(
+            start_price,
+            math::mul_div(drop, elapsed, duration, Rounding::Down)?,
+        )
+    }
+}
+
+#[derive(
+    BorshSerialize,
+    BorshDeserialize,
+    CheckedBitPattern,
+    NoUninit,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Default,
+)]
+#[repr(u8)]
+pub enum OracleSource {
+    #[default]
+    Pyth,
+    Switchboard,
+}
+
+#[derive(
+    BorshSerialize,
+    BorshDeserialize,
+    CheckedBitPattern,
+    NoUninit,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Default,
+)]
+#[repr(C)]
+pub struct PriceOracle {
+    //price account quoting token b per token a
+    pub feed: Pubkey,
+    pub source: OracleSource,
+    //premium on top of the oracle price the taker pays, in basis points
+    pub spread_bps: PodU16,
+    //oldest accepted publish time, in seconds before now
+    pub max_staleness: PodI64,
+    //band the oracle price has to be in, with oracle::PRICE_DECIMALS decimals
+    pub min_price: PodU64,
+    pub max_price: PodU64,
+    //mint decimals, the feed quotes whole tokens
+    pub token_a_decimals: u8,
+    pub token_b_decimals: u8,
+}
+
+//release rule of a conditional escrow, settlement invokes `program` with `data()` and the
+//escrow account, an error from the program stops the payout
+#[derive(
+    BorshSerialize,
+    BorshDeserialize,
+    CheckedBitPattern,
+    NoUninit,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Default,
+)]
+#[repr(C)]
+pub struct Condition {
+    pub program: Pubkey,
+    pub data: [u8; MAX_CONDITION_DATA],
+    pub data_len: u8,
+}
+
+impl Condition {
+    pub fn data(&self) -> &[u8] {
+        &self.data[..(self.data_len as usize).min(MAX_CONDITION_DATA)]
+    }
+}
+
+//an issuer's attestation about `subject`, lives at [b"credential", issuer, subject]
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Default)]
+pub struct Credential {
+    pub issuer: Pubkey,
+    pub subject: Pubkey,
+    //unix timestamp the credential stops being accepted at
+    pub expires_at: i64,
+}
+
+//one bidder's tokens in an english auction vault, lives at [b"bid", escrow, bidder]
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Default)]
+pub struct Bid {
+    pub escrow: Pubkey,
+    pub bidder: Pubkey,
+    //0 once refunded
+    pub amount: u64,
+}
+
+//marks a signed offer nonce as used, lives at [b"offer", maker, nonce]
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Default)]
+pub struct OfferRecord {
+    pub maker: Pubkey,
+    pub taker: Pubkey,
+    pub nonce: u64,
+}
+
+//global admin settings, lives at [b"config"]
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Default)]
+pub struct Config {
+    pub admin: Pubkey,
+    //blocks new escrows and deposits, refunds keep working
+    pub paused: bool,
+}
+
+#[derive(
+    BorshSerialize,
+    BorshDeserialize,
+    CheckedBitPattern,
+    NoUninit,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Default,
+)]
+#[repr(C)]
+pub struct Multisig {
+    pub signers: [Pubkey; MAX_MULTISIG_SIGNERS],
+    pub signer_count: u8,
+    //0 means no multisig
+    pub threshold: u8,
+    //the action signers[i] currently approves, kind None when it approves nothing
+    pub approvals: [Approval; MAX_MULTISIG_SIGNERS],
+}
+
+impl Multisig {
+    pub fn is_enabled(&self) -> bool {
+        self.threshold > 0
+    }
+
+    pub fn position(&self, signer: &Pubkey) -> Option<usize> {
+        self.signers[..(self.signer_count as usize).min(MAX_MULTISIG_SIGNERS)]
+            .iter()
+            .position(|key| key == signer)
+    }
+
+    //a signer backs one action at a time, approving another one moves its approval,
+    //so no signer can wipe or block the others' approvals
+    pub fn approve(&mut self, index: usize, action: MakerAction) {
+        self.approvals[index] = action.into();
+    }
+
+    pub fn approval_count(&self, action: &MakerAction) -> u32 {
+        if action.kind == MakerActionKind::None {
+            return 0;
+        }
+        let approval = Approval::from(*action);
+        self.approvals[..(self.signer_count as usize).min(MAX_MULTISIG_SIGNERS)]
+            .iter()
+            .filter(|approved| **approved == approval)
+            .count() as u32
+    }
+
+    //true when `action` reached the threshold, its approvals are used up then
+    pub fn consume(&mut self, action: &MakerAction) -> bool {
+        let approved = self.approval_count(action) >= self.threshold as u32;
+        if approved {
+            let approval = Approval::from(*action);
+            for approved in self
+                .approvals
+                .iter_mut()
+                .filter(|approved| **approved == approval)
+            {
+                *approved = Approval::default();
+            }
+        }
+        approved
+    }
+}
+
+#[derive(
+    BorshSerialize,
+    BorshDeserialize,
+    CheckedBitPattern,
+    NoUninit,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Default,
+)]
+#[repr(u8)]
+pub enum MakerActionKind {
+    #[default]
+    None,
+    Deposit,
+    Cancel,
+    UpdateTerms,
+    AcceptCounter,
+}
+
+//a user_a action the multisig signs off on, amounts bind UpdateTerms and AcceptCounter
+//to the exact terms that were approved
+#[derive(
+    BorshSerialize,
+    BorshDeserialize,
+    CheckedBitPattern,
+    NoUninit,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Default,
+)]
+#[repr(C)]
+pub struct MakerAction {
+    pub kind: MakerActionKind,
+    pub amount_a: PodU64,
+    pub amount_b: PodU64,
+}
+
+//a MakerAction as plain bytes so every signer's approval fits in the zero-copy escrow,
+//kind 0 is MakerActionKind::None
+#[derive(
+    BorshSerialize, BorshDeserialize, Pod, Zeroable, Debug, Clone, Copy, PartialEq, Default,
+)]
+#[repr(C)]
+pub struct Approval {
+    pub kind: u8,
+    pub amount_a: PodU64,
+    pub amount_b: PodU64,
+}
+
+impl From<MakerAction> for Approval {
+    fn from(action: MakerAction) -> Self {
+        Approval {
+            kind: action.kind as u8,
+            amount_a: action.amount_a,
+            amount_b: action.amount_b,
+        }
+    }
+}
+
+impl MakerAction {
+    pub fn new(kind: MakerActionKind) -> Self {
+        MakerAction {
+            kind,
+            amount_a: 0.into(),
+            amount_b: 0.into(),
+        }
+    }
+
+    pub fn with_amounts(kind: MakerActionKind, amount_a: u64, amount_b: u64) -> Self {
+        MakerAction {
+            kind,
+            amount_a: amount_a.into(),
+            amount_b: amount_b.into(),
+        }
+    }
+}
+
+//one leg of a ring trade, the asset `owner` puts in
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub struct RingParticipant {
+    pub owner: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub deposited: bool,
+}
+
+//cycle trade, participant i receives the asset of participant i - 1 and the first one
+//receives the last one's, settles only once every leg is funded
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Default)]
+pub struct RingTrade {
+    pub participants: [RingParticipant; MAX_RING_PARTICIPANTS],
+    pub participant_count: u8,
+    pub vault_pda: Pubkey,
+    pub vault_bump: u8,
+    pub status: EscrowStatus,
+}
+
+impl RingTrade {
+    pub fn legs(&self) -> &[RingParticipant] {
+        &self.participants[..(self.participant_count as usize).min(MAX_RING_PARTICIPANTS)]
+    }
+
+    pub fn position(&self, owner: &Pubkey) -> Option<usize> {
+        self.legs().iter().position(|leg| &leg.owner == owner)
+    }
+
+    pub fn is_funded(&self) -> bool {
+        self.legs().iter().all(|leg| leg.deposited)
+    }
+
+    //the participant who receives leg `index`'s asset
+    pub fn recipient_of(&self, index: usize) -> &RingParticipant {
+        &self.legs()[(index + 1) % self.legs().len()]
+    }
+}
+
+//an open escrow on a market, priced as amount_b per amount_a
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub struct MarketOffer {
+    pub escrow: Pubkey,
+    pub amount_a: u64,
+    pub amount_b: u64,
+}
+
+impl MarketOffer {
+    //cheaper for the taker, compared by cross multiplying so no precision is lost
+    pub fn is_better_than(&self, other: &MarketOffer) -> bool {
+        (self.amount_b as u128) * (other.amount_a as u128)
+            < (other.amount_b as u128) * (self.amount_a as u128)
+    }
+}
+
+//order book of open escrows for one mint pair, lives at [b"market", token_a_mint, token_b_mint]
+//offers are kept best price first, equal prices in listing order
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Default)]
+pub struct Market {
+    pub token_a_mint: Pubkey,
+    pub token_b_mint: Pubkey,
+    pub offer_count: u8,
+    pub offers: [MarketOffer; MAX_MARKET_OFFERS],
+}
+
+impl Market {
+    pub fn offers(&self) -> &[MarketOffer] {
+        &self.offers[..(self.offer_count as usize).min(MAX_MARKET_OFFERS)]
+    }
+
+    //a full book drops its worst offer for a better one and is false when the offer does not
+    //beat it. the dropped escrow keeps its listed flag, delisting it later finds nothing to remove
+    pub fn insert(&mut self, offer: MarketOffer) -> bool {
+        let mut count = self.offers().len();
+        if count == MAX_MARKET_OFFERS {
+            if !offer.is_better_than(&self.offers[count - 1]) {
+                return false;
+            }
+            count -= 1;
+        }
+
+        let index = self.offers[..count]
+            .iter()
+            .position(|listed| offer.is_better_than(listed))
+            .unwrap_or(count);
+
+        self.offers.copy_within(index..count, index + 1);
+        self.offers[index] = offer;
+        self.offer_count = count as u8 + 1;
+        true
+    }
+
+    //false when `escrow` is not listed
+    pub fn remove(&mut self, escrow: &Pubkey) -> bool {
+        let count = self.offers().len();
+        let Some(index) = self
+            .offers()
+            .iter()
+            .position(|offer| &offer.escrow == escrow)
+        else {
+            return false;
+        };
+
+        self.offers.copy_within(index + 1..count, index);
+        self.offers[count - 1] = MarketOffer::default();
+        self.offer_count = count as u8 - 1;
+        true
+    }
+}
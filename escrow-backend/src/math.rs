@@ -0,0 +1,47 @@
+use crate::errors::EscrowError;
+
+//which way a division that does not come out even goes, pick the one that favours the
+//vault or the party that is not choosing the numbers
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rounding {
+    Down,
+    Up,
+}
+
+pub fn add(a: u64, b: u64) -> Result<u64, EscrowError> {
+    a.checked_add(b).ok_or(EscrowError::MathOverflow)
+}
+
+pub fn sub(a: u64, b: u64) -> Result<u64, EscrowError> {
+    a.checked_sub(b).ok_or(EscrowError::MathOverflow)
+}
+
+//a * b / c with a u128 intermediate, so only a result above u64::MAX fails
+pub fn mul_div(a: u64, b: u64, c: u64, rounding: Rounding) -> Result<u64, EscrowError> {
+    let result = mul_div_u128(a as u128, b as u128, c as u128, rounding)?;
+    u64::try_from(result).map_err(|_| EscrowError::MathOverflow)
+}
+
+//mul_div for operands that are already scaled past u64, e.g. prices times decimals
+pub fn mul_div_u128(a: u128, b: u128, c: u128, rounding: Rounding) -> Result<u128, EscrowError> {
+    if c == 0 {
+        return Err(EscrowError::MathOverflow);
+    }
+
+    let product = a.checked_mul(b).ok_or(EscrowError::MathOverflow)?;
+
+    Ok(match rounding {
+        Rounding::Down => product / c,
+        Rounding::Up => product.div_ceil(c),
+    })
+}
+
+pub fn mul_u128(a: u128, b: u128) -> Result<u128, EscrowError> {
+    a.checked_mul(b).ok_or(EscrowError::MathOverflow)
+}
+
+pub fn pow10(exponent: u32) -> Result<u128, EscrowError> {
+    10u128
+        .checked_pow(exponent)
+        .ok_or(EscrowError::MathOverflow)
+}
@@ -0,0 +1,134 @@
+use crate::errors::EscrowError;
+use crate::math::{mul_div_u128, mul_u128, pow10, Rounding};
+use crate::{OracleSource, PriceOracle};
+use solana_program::program_error::ProgramError;
+
+//oracle prices are normalized to this many decimals
+pub const PRICE_DECIMALS: i32 = 9;
+const BPS_DENOMINATOR: u128 = 10_000;
+//highest mint decimals InitEscrow accepts, keeps every power of ten in taker_amount in a u128
+pub const MAX_TOKEN_DECIMALS: u8 = 18;
+
+//pyth v2 price account, see SolanaPriceAccount in pyth-sdk-solana
+const PYTH_MAGIC: u32 = 0xa1b2_c3d4;
+const PYTH_PRICE_ACCOUNT_TYPE: u32 = 3;
+const PYTH_STATUS_TRADING: u32 = 1;
+const PYTH_EXPO_OFFSET: usize = 20;
+const PYTH_TIMESTAMP_OFFSET: usize = 96;
+const PYTH_AGG_PRICE_OFFSET: usize = 208;
+const PYTH_AGG_STATUS_OFFSET: usize = 224;
+
+//switchboard v2 AggregatorAccountData, packed after the 8 byte anchor discriminator
+const SWITCHBOARD_ROUND_OPEN_TIMESTAMP_OFFSET: usize = 358;
+const SWITCHBOARD_RESULT_MANTISSA_OFFSET: usize = 366;
+const SWITCHBOARD_RESULT_SCALE_OFFSET: usize = 382;
+
+//price = mantissa * 10^expo
+#[derive(Debug, Clone, PartialEq)]
+pub struct OraclePrice {
+    pub mantissa: i128,
+    pub expo: i32,
+    pub publish_time: i64,
+}
+
+impl OraclePrice {
+    //the price with PRICE_DECIMALS decimals, None when it is not positive or too large
+    pub fn normalized(&self) -> Option<u64> {
+        if self.mantissa <= 0 {
+            return None;
+        }
+
+        let mantissa = self.mantissa as u128;
+        let shift = self.expo.checked_add(PRICE_DECIMALS)?;
+        let scaled = if shift >= 0 {
+            mantissa.checked_mul(10u128.checked_pow(shift as u32)?)?
+        } else {
+            mantissa / 10u128.checked_pow(shift.unsigned_abs())?
+        };
+
+        u64::try_from(scaled).ok()
+    }
+}
+
+fn read_bytes<const N: usize>(data: &[u8], offset: usize) -> Result<[u8; N], ProgramError> {
+    data.get(offset..offset + N)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or(EscrowError::InvalidOracle.into())
+}
+
+pub fn read_price(source: &OracleSource, data: &[u8]) -> Result<OraclePrice, ProgramError> {
+    match source {
+        OracleSource::Pyth => {
+            let magic = u32::from_le_bytes(read_bytes(data, 0)?);
+            let account_type = u32::from_le_bytes(read_bytes(data, 8)?);
+            if magic != PYTH_MAGIC || account_type != PYTH_PRICE_ACCOUNT_TYPE {
+                return Err(EscrowError::InvalidOracle.into());
+            }
+
+            //a halted or unknown aggregate is not a usable price
+            let status = u32::from_le_bytes(read_bytes(data, PYTH_AGG_STATUS_OFFSET)?);
+            if status != PYTH_STATUS_TRADING {
+                return Err(EscrowError::InvalidOracle.into());
+            }
+
+            Ok(OraclePrice {
+                mantissa: i64::from_le_bytes(read_bytes(data, PYTH_AGG_PRICE_OFFSET)?) as i128,
+                expo: i32::from_le_bytes(read_bytes(data, PYTH_EXPO_OFFSET)?),
+                publish_time: i64::from_le_bytes(read_bytes(data, PYTH_TIMESTAMP_OFFSET)?),
+            })
+        }
+        OracleSource::Switchboard => {
+            let scale = u32::from_le_bytes(read_bytes(data, SWITCHBOARD_RESULT_SCALE_OFFSET)?);
+
+            Ok(OraclePrice {
+                mantissa: i128::from_le_bytes(read_bytes(
+                    data,
+                    SWITCHBOARD_RESULT_MANTISSA_OFFSET,
+                )?),
+                expo: -i32::try_from(scale).map_err(|_| EscrowError::InvalidOracle)?,
+                publish_time: i64::from_le_bytes(read_bytes(
+                    data,
+                    SWITCHBOARD_ROUND_OPEN_TIMESTAMP_OFFSET,
+                )?),
+            })
+        }
+    }
+}
+
+//the normalized feed price once it is fresh and inside the maker's band
+pub fn checked_price(terms: &PriceOracle, data: &[u8], now: i64) -> Result<u64, ProgramError> {
+    let price = read_price(&terms.source, data)?;
+
+    if now.saturating_sub(price.publish_time) > terms.max_staleness.get() {
+        return Err(EscrowError::OracleStale.into());
+    }
+
+    let normalized = price.normalized().ok_or(EscrowError::InvalidOracle)?;
+    if normalized < terms.min_price.get() || normalized > terms.max_price.get() {
+        return Err(EscrowError::OraclePriceOutOfBand.into());
+    }
+
+    Ok(normalized)
+}
+
+//amount_b for `amount_a` at `price` plus the spread, rounded up so the maker is never short
+pub fn taker_amount(terms: &PriceOracle, amount_a: u64, price: u64) -> Result<u64, EscrowError> {
+    //only the difference in decimals matters, cancelling the rest keeps the product small
+    let (decimals_a, decimals_b) = (terms.token_a_decimals, terms.token_b_decimals);
+    let numerator = mul_u128(
+        BPS_DENOMINATOR + terms.spread_bps.get() as u128,
+        pow10(decimals_b.saturating_sub(decimals_a) as u32)?,
+    )?;
+    let denominator = mul_u128(
+        BPS_DENOMINATOR * pow10(PRICE_DECIMALS as u32)?,
+        pow10(decimals_a.saturating_sub(decimals_b) as u32)?,
+    )?;
+    let value = mul_div_u128(
+        amount_a as u128 * price as u128,
+        numerator,
+        denominator,
+        Rounding::Up,
+    )?;
+
+    u64::try_from(value).map_err(|_| EscrowError::MathOverflow)
+}
@@ -0,0 +1,49 @@
+use borsh::{BorshDeserialize, BorshSerialize};
+use bytemuck::{Pod, Zeroable};
+use std::fmt;
+
+//little endian integers with alignment 1, state structs built from them have no padding and
+//keep the exact byte layout borsh gives them, so they can be cast straight from account data
+macro_rules! pod_int {
+    ($name:ident, $int:ty) => {
+        #[derive(
+            BorshSerialize, BorshDeserialize, Pod, Zeroable, Clone, Copy, PartialEq, Eq, Default,
+        )]
+        #[repr(transparent)]
+        pub struct $name([u8; std::mem::size_of::<$int>()]);
+
+        impl $name {
+            pub fn get(self) -> $int {
+                <$int>::from_le_bytes(self.0)
+            }
+        }
+
+        impl From<$int> for $name {
+            fn from(value: $int) -> Self {
+                $name(value.to_le_bytes())
+            }
+        }
+
+        impl From<$name> for $int {
+            fn from(value: $name) -> Self {
+                value.get()
+            }
+        }
+
+        impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                self.get().fmt(f)
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                self.get().fmt(f)
+            }
+        }
+    };
+}
+
+pod_int!(PodU16, u16);
+pod_int!(PodU64, u64);
+pod_int!(PodI64, i64);
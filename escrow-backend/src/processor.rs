@@ -1,12 +1,46 @@
 use crate::errors::EscrowError;
-use crate::instructions::{check_rent_exempt, EscrowInstruction};
-use crate::{Escrow, EscrowStatus};
+use crate::instructions::{
+    check_ed25519_signature, check_rent_exempt, EscrowInstruction, MultisigDescriptor,
+};
+use crate::{
+    math, oracle, Auction, AuctionStyle, Bid, Condition, Config, Credential, Escrow, EscrowKind,
+    EscrowStatus, HashLock, MakerAction, MakerActionKind, Market, MarketOffer, Multisig,
+    OfferRecord, PriceOracle, RingParticipant, RingTrade, MAX_CONDITION_DATA, MAX_MULTISIG_SIGNERS,
+    MAX_RING_PARTICIPANTS,
+};
 use borsh::{BorshDeserialize, BorshSerialize};
+use solana_loader_v3_interface::{get_program_data_address, state::UpgradeableLoaderState};
 use solana_program::program::invoke_signed;
+use solana_program::program_pack::Pack;
+use solana_program::program_utils::limited_deserialize;
 use solana_program::{
-    account_info::AccountInfo, entrypoint::ProgramResult, msg, program::invoke,
-    program_error::ProgramError, pubkey::Pubkey,
+    account_info::AccountInfo,
+    clock::Clock,
+    compute_units::sol_remaining_compute_units,
+    entrypoint::ProgramResult,
+    instruction::{AccountMeta, Instruction},
+    msg,
+    program::invoke,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    rent::Rent,
+    sysvar::Sysvar,
 };
+use solana_sdk_ids::bpf_loader_upgradeable;
+use solana_system_interface::instruction as system_instruction;
+use spl_associated_token_account_client::address::get_associated_token_address_with_program_id;
+use spl_associated_token_account_client::instruction::create_associated_token_account_idempotent;
+use spl_token::state::Account as TokenAccount;
+
+//a settling Deposit takes its payout accounts right after the config account
+const DEPOSIT_PAYOUT_START: usize = 6;
+const DEPOSIT_PAYOUT_LEN: usize = 4;
+//ring instructions take their per leg account pairs after these
+const RING_LEGS_START: usize = 4;
+//escrow, vault_authority, vault_token_a, vault_token_b, user_a_token_b, user_b_token_a, market
+const BATCH_GROUP_LEN: usize = 7;
+//rough cost of one settlement, the batch stops before running out
+const BATCH_SETTLEMENT_RESERVE: u64 = 30_000;
 
 pub fn process_instruction(
     program_id: &Pubkey,
@@ -15,18 +49,47 @@ pub fn process_instruction(
 ) -> ProgramResult {
     let instruction = EscrowInstruction::try_from_slice(instruction_data)?;
 
+    if instruction.is_pausable() {
+        check_not_paused(program_id, accounts)?;
+    }
+
     match instruction {
         EscrowInstruction::InitEscrow {
             token_a_mint,
             token_b_mint,
             amount_a,
             amount_b,
+            hash_lock,
+            delegated_source,
+            maker_multisig,
+            permissionless_settlement,
+            keeper_tip,
+            auction,
+            oracle,
+            condition,
+            credential_issuer,
+            reference,
         } => {
-            let user_a_account = &accounts[0];
-            let escrow_account = &accounts[1];
-            let rent_account = &accounts[2];
+            let user_a_account = account_at(accounts, 0)?;
+            let escrow_account = account_at(accounts, 1)?;
+            let rent_account = account_at(accounts, 2)?;
+
+            let multisig = match maker_multisig {
+                Some(descriptor) => build_multisig(descriptor)?,
+                None => Multisig::default(),
+            };
+
+            //a multisig user_a is usually an spl multisig or a pda, one of its signers creates the escrow
+            let authorized = if multisig.is_enabled() {
+                accounts
+                    .iter()
+                    .any(|account| account.is_signer && multisig.position(account.key).is_some())
+                    && (user_a_account.is_signer || spl_multisig_lists(user_a_account, &multisig))
+            } else {
+                user_a_account.is_signer
+            };
 
-            if !user_a_account.is_signer {
+            if !authorized {
                 return Err(EscrowError::UnauthorizedCancel.into());
             }
 
@@ -36,38 +99,220 @@ pub fn process_instruction(
                 return Err(ProgramError::IncorrectProgramId);
             }
 
-            let (vault_pda, _vault_bump) =
+            //a live escrow keeps its terms, initializing it again would orphan the vault
+            if Escrow::load(&escrow_account.data.borrow())?.user_a != Pubkey::default() {
+                return Err(ProgramError::AccountAlreadyInitialized);
+            }
+
+            let (vault_pda, vault_bump) =
                 Pubkey::find_program_address(&[b"vault", escrow_account.key.as_ref()], program_id);
 
-            let escrow = Escrow {
+            let kind_count = [
+                hash_lock.is_some(),
+                delegated_source.is_some(),
+                auction.is_some(),
+                oracle.is_some(),
+                condition.is_some(),
+            ]
+            .iter()
+            .filter(|selected| **selected)
+            .count();
+            if kind_count > 1 {
+                return Err(EscrowError::InvalidEscrowKind.into());
+            }
+
+            let (user_b, kind, hash_lock) = match hash_lock {
+                Some(hash_lock) => {
+                    if hash_lock.timeout.get() <= Clock::get()?.unix_timestamp {
+                        return Err(EscrowError::HashLockExpired.into());
+                    }
+                    if hash_lock.recipient == Pubkey::default()
+                        || hash_lock.recipient == *user_a_account.key
+                    {
+                        return Err(EscrowError::InvalidRecipient.into());
+                    }
+                    (hash_lock.recipient, EscrowKind::HashLock, hash_lock)
+                }
+                None if delegated_source.is_some() => {
+                    (Pubkey::default(), EscrowKind::Approval, HashLock::default())
+                }
+                None => (Pubkey::default(), EscrowKind::Swap, HashLock::default()),
+            };
+
+            //auctions start without a buyer, amount_b is set by the bids or the dutch price
+            let (kind, amount_b, auction) = match auction {
+                Some(auction) => {
+                    if auction.start_time.get() >= auction.end_time.get()
+                        || auction.end_time.get() <= Clock::get()?.unix_timestamp
+                        || auction.start_price.get() == 0
+                    {
+                        return Err(EscrowError::InvalidAuction.into());
+                    }
+                    let kind = match auction.style {
+                        AuctionStyle::English => EscrowKind::EnglishAuction,
+                        AuctionStyle::Dutch => {
+                            if auction.end_price.get() > auction.start_price.get() {
+                                return Err(EscrowError::InvalidAuction.into());
+                            }
+                            EscrowKind::DutchAuction
+                        }
+                    };
+                    (kind, 0, auction)
+                }
+                None => (kind, amount_b, Auction::default()),
+            };
+
+            let (kind, amount_b, oracle) = match oracle {
+                Some(oracle) => {
+                    if oracle.feed == Pubkey::default()
+                        || oracle.max_staleness.get() <= 0
+                        || oracle.max_price.get() == 0
+                        || oracle.min_price.get() > oracle.max_price.get()
+                        || oracle.token_a_decimals > oracle::MAX_TOKEN_DECIMALS
+                        || oracle.token_b_decimals > oracle::MAX_TOKEN_DECIMALS
+                    {
+                        return Err(EscrowError::InvalidOracle.into());
+                    }
+                    (EscrowKind::OraclePriced, 0, *oracle)
+                }
+                None => (kind, amount_b, PriceOracle::default()),
+            };
+
+            let (kind, condition) = match condition {
+                Some(condition) => {
+                    if condition.program == Pubkey::default()
+                        || condition.program == *program_id
+                        || condition.data_len as usize > MAX_CONDITION_DATA
+                    {
+                        return Err(EscrowError::InvalidCondition.into());
+                    }
+                    (EscrowKind::Conditional, *condition)
+                }
+                None => (kind, Condition::default()),
+            };
+
+            //htlc escrows are settled by the preimage holder, not by CompleteSwap
+            if permissionless_settlement && kind == EscrowKind::HashLock {
+                return Err(EscrowError::InvalidEscrowKind.into());
+            }
+
+            //the htlc recipient is fixed up front and never deposits
+            if credential_issuer.is_some() && kind == EscrowKind::HashLock {
+                return Err(EscrowError::InvalidEscrowKind.into());
+            }
+
+            if keeper_tip > 0 {
+                if !permissionless_settlement {
+                    return Err(EscrowError::InvalidAmount.into());
+                }
+
+                //the tip comes out of user_a's wallet, a multisig member signature is not enough
+                if !user_a_account.is_signer {
+                    return Err(EscrowError::UnauthorizedCancel.into());
+                }
+
+                let system_program = accounts
+                    .iter()
+                    .find(|account| account.key == &solana_system_interface::program::ID)
+                    .ok_or(ProgramError::NotEnoughAccountKeys)?;
+
+                invoke(
+                    &system_instruction::transfer(
+                        user_a_account.key,
+                        escrow_account.key,
+                        keeper_tip,
+                    ),
+                    &[
+                        user_a_account.clone(),
+                        escrow_account.clone(),
+                        system_program.clone(),
+                    ],
+                )?;
+            }
+
+            let mut escrow = Escrow {
                 user_a: *user_a_account.key,
-                user_b: Pubkey::default(),
+                user_b,
                 token_a_mint,
                 token_b_mint,
-                amount_a,
-                amount_b,
+                amount_a: amount_a.into(),
+                amount_b: amount_b.into(),
                 token_a_deposited: false,
                 token_b_deposited: false,
                 vault_pda,
                 status: EscrowStatus::Active,
+                kind,
+                hash_lock,
+                delegated_source: delegated_source.unwrap_or_default(),
+                counter_proposer: Pubkey::default(),
+                counter_amount_a: 0.into(),
+                counter_amount_b: 0.into(),
+                multisig,
+                permissionless_settlement,
+                keeper_tip: keeper_tip.into(),
+                auction,
+                listed: false,
+                oracle,
+                condition,
+                credential_issuer: credential_issuer.unwrap_or_default(),
+                reference: reference.unwrap_or_default(),
+                vault_bump,
+                settlement_locked: false,
             };
 
-            escrow.serialize(&mut &mut escrow_account.data.borrow_mut()[..])?;
+            //passing the market account lists an approval escrow whose delegation is already in
+            //place, a swap is listed by user_a's deposit instead
+            if let Some(market_account) = find_market(program_id, &escrow, accounts) {
+                if escrow.kind != EscrowKind::Approval {
+                    return Err(EscrowError::InvalidEscrowKind.into());
+                }
+                let source_account = accounts
+                    .iter()
+                    .find(|account| account.key == &escrow.delegated_source)
+                    .ok_or(ProgramError::NotEnoughAccountKeys)?;
+                if !delegation_covers(&escrow, source_account)? {
+                    return Err(EscrowError::EscrowNotReady.into());
+                }
+                list_on_market(program_id, escrow_account.key, &mut escrow, market_account)?;
+            }
+
+            escrow.store(&mut escrow_account.data.borrow_mut())?;
+
+            msg!("Escrow initialized! {}", escrow_account.key);
+            log_reference(&escrow);
         }
 
-        EscrowInstruction::Deposit { amount } => {
-            let depositor_account = &accounts[0];
-            let escrow_account = &accounts[1];
-            let depositor_token_account = &accounts[2];
-            let vault_token_account = &accounts[3];
-            let token_program = &accounts[4];
+        EscrowInstruction::Deposit {
+            amount,
+            expected_amount_a_min,
+            expected_mint_a,
+            settle,
+        } => {
+            let depositor_account = account_at(accounts, 0)?;
+            let escrow_account = account_at(accounts, 1)?;
+            let depositor_token_account = account_at(accounts, 2)?;
+            let vault_token_account = account_at(accounts, 3)?;
+            let token_program = account_at(accounts, 4)?;
+
+            check_token_program(token_program)?;
 
-            let mut escrow = Escrow::try_from_slice(&escrow_account.data.borrow())?;
+            let mut escrow_data = escrow_account.data.borrow_mut();
+            let escrow = Escrow::load_mut(&mut escrow_data)?;
 
-            if !depositor_account.is_signer {
-                return Err(EscrowError::UnauthorizedCancel.into());
+            //a cancelled escrow has its flags cleared, tokens deposited now could never leave
+            if !matches!(&escrow.status, EscrowStatus::Active) {
+                return Err(EscrowError::EscrowNotReady.into());
+            }
+
+            //terms may have changed since the depositor looked at them
+            if escrow.token_a_mint != expected_mint_a
+                || escrow.amount_a.get() < expected_amount_a_min
+            {
+                return Err(EscrowError::SlippageExceeded.into());
             }
 
+            let spl_signers = spl_multisig_signers(accounts, 5);
+
             let is_user_a = depositor_account.key == &escrow.user_a;
             let is_user_b = if escrow.user_b == Pubkey::default() {
                 msg!("First user_b deposit - allowing non-user_a");
@@ -80,6 +325,75 @@ pub fn process_instruction(
                 return Err(EscrowError::UnauthorizedCancel.into());
             }
 
+            //htlc escrows are one sided, only user_a funds them
+            if escrow.kind == EscrowKind::HashLock && !is_user_a {
+                return Err(EscrowError::InvalidEscrowKind.into());
+            }
+
+            //approval escrows keep user_a's tokens in their own account until settlement
+            if escrow.kind == EscrowKind::Approval && is_user_a {
+                return Err(EscrowError::InvalidEscrowKind.into());
+            }
+
+            //english auction bids go through PlaceBid
+            if escrow.kind == EscrowKind::EnglishAuction && !is_user_a {
+                return Err(EscrowError::InvalidEscrowKind.into());
+            }
+
+            //a dutch buyer pays the current price, so the lot has to be in the vault already
+            if escrow.kind == EscrowKind::DutchAuction && !is_user_a {
+                if !escrow.token_a_deposited {
+                    return Err(EscrowError::EscrowNotReady.into());
+                }
+                let now = Clock::get()?.unix_timestamp;
+                if !escrow.auction.is_live(now) {
+                    return Err(EscrowError::AuctionNotLive.into());
+                }
+                let price = escrow.auction.dutch_price(now)?;
+                if amount < price {
+                    return Err(EscrowError::InvalidAmount.into());
+                }
+                escrow.amount_b = price.into();
+            }
+
+            //an oracle priced taker pays the feed price of the moment the swap settles
+            if escrow.kind == EscrowKind::OraclePriced && !is_user_a {
+                if !escrow.token_a_deposited {
+                    return Err(EscrowError::EscrowNotReady.into());
+                }
+                let feed_account = accounts
+                    .iter()
+                    .find(|account| account.key == &escrow.oracle.feed)
+                    .ok_or(ProgramError::NotEnoughAccountKeys)?;
+                let price = oracle::checked_price(
+                    &escrow.oracle,
+                    &feed_account.data.borrow(),
+                    Clock::get()?.unix_timestamp,
+                )?;
+                let taker_amount =
+                    oracle::taker_amount(&escrow.oracle, escrow.amount_a.get(), price)?;
+                if amount < taker_amount {
+                    return Err(EscrowError::InvalidAmount.into());
+                }
+                msg!("Oracle price: {}, taker pays {}", price, taker_amount);
+                escrow.amount_b = taker_amount.into();
+            }
+
+            //an spl multisig depositor is checked by the token program through its co-signers
+            if is_user_a {
+                authorize_maker(
+                    escrow,
+                    depositor_account,
+                    MakerAction::new(MakerActionKind::Deposit),
+                )?;
+            } else if !depositor_account.is_signer && spl_signers.is_empty() {
+                return Err(EscrowError::UnauthorizedCancel.into());
+            }
+
+            if is_user_b {
+                check_credential(program_id, escrow, depositor_account.key, accounts)?;
+            }
+
             //double deposit checking
             if is_user_a && escrow.token_a_deposited {
                 return Err(EscrowError::AlreadyDeposited.into());
@@ -95,36 +409,52 @@ pub fn process_instruction(
             }
 
             //token and the amount to transfer
-            let (_token_mint, expected_amount) = if is_user_a {
-                (escrow.token_a_mint, escrow.amount_a)
+            let (token_mint, expected_amount) = if is_user_a {
+                (escrow.token_a_mint, escrow.amount_a.get())
             } else {
-                (escrow.token_b_mint, escrow.amount_b)
+                (escrow.token_b_mint, escrow.amount_b.get())
             };
 
-            if amount != expected_amount {
-                return Err(EscrowError::InvalidAmount.into());
+            //a deposit only counts once it sits in this escrow's vault
+            let vault_token = TokenAccount::unpack(&vault_token_account.data.borrow())?;
+            if vault_token.owner != escrow.vault_pda || vault_token.mint != token_mint {
+                return Err(EscrowError::InvalidTokenAccount.into());
             }
 
+            let priced_on_deposit = matches!(
+                escrow.kind,
+                EscrowKind::DutchAuction | EscrowKind::OraclePriced
+            );
+            let amount = if priced_on_deposit && !is_user_a {
+                expected_amount
+            } else if amount != expected_amount {
+                return Err(EscrowError::InvalidAmount.into());
+            } else {
+                amount
+            };
+
             //token transfer instruction
+            let spl_signer_keys: Vec<&Pubkey> =
+                spl_signers.iter().map(|signer| signer.key).collect();
             let transfer_instruction = spl_token::instruction::transfer(
                 token_program.key,
                 depositor_token_account.key,
                 vault_token_account.key,
                 depositor_account.key,
-                &[],
+                &spl_signer_keys,
                 amount,
             )?;
 
             //cpi transfer
-            invoke(
-                &transfer_instruction,
-                &[
-                    depositor_account.clone(),
-                    vault_token_account.clone(),
-                    depositor_token_account.clone(),
-                    token_program.clone(),
-                ],
-            )?;
+            let mut transfer_accounts = vec![
+                depositor_account.clone(),
+                vault_token_account.clone(),
+                depositor_token_account.clone(),
+                token_program.clone(),
+            ];
+            transfer_accounts.extend(spl_signers.iter().cloned());
+
+            invoke(&transfer_instruction, &transfer_accounts)?;
 
             //updating the escrow state
             if is_user_a {
@@ -133,106 +463,196 @@ pub fn process_instruction(
                 escrow.token_b_deposited = true;
             }
 
-            //saving the escrow state
-            escrow.serialize(&mut &mut escrow_account.data.borrow_mut()[..])?;
+            //an open swap goes on the book once its token a is in the vault
+            if is_user_a && escrow.kind == EscrowKind::Swap && escrow.user_b == Pubkey::default() {
+                if let Some(market_account) = find_market(program_id, escrow, accounts) {
+                    list_on_market(program_id, escrow_account.key, escrow, market_account)?;
+                }
+            }
 
+            //saving the escrow state
             msg!("Deposit successful! Amount: {}", amount);
-        }
+            log_reference(escrow);
 
-        EscrowInstruction::CompleteSwap { .. } => {
-            let caller = &accounts[0];
-            let escrow_account = &accounts[1];
-            let vault_authority = &accounts[2];
-            let vault_token_a = &accounts[3];
-            let vault_token_b = &accounts[4];
-            let user_a_token_b_account = &accounts[5];
-            let user_b_token_a_account = &accounts[6];
-            let token_program = &accounts[7];
+            //the oracle price is only good for this instruction, so the taker has to settle
+            if escrow.kind == EscrowKind::OraclePriced && !is_user_a && !settle {
+                return Err(EscrowError::EscrowNotReady.into());
+            }
 
-            if !caller.is_signer {
-                return Err(EscrowError::UnauthorizedCancel.into());
+            if !settle {
+                return Ok(());
             }
 
-            let mut escrow = Escrow::try_from_slice(&escrow_account.data.borrow())?;
+            let payout_accounts = accounts
+                .get(DEPOSIT_PAYOUT_START..DEPOSIT_PAYOUT_START + DEPOSIT_PAYOUT_LEN)
+                .ok_or(ProgramError::NotEnoughAccountKeys)?;
 
-            if caller.key != &escrow.user_a && caller.key != &escrow.user_b {
-                return Err(EscrowError::UnauthorizedCancel.into());
-            }
+            let completing = match escrow.kind {
+                EscrowKind::Swap
+                | EscrowKind::EnglishAuction
+                | EscrowKind::DutchAuction
+                | EscrowKind::OraclePriced
+                | EscrowKind::Conditional => escrow.token_a_deposited && escrow.token_b_deposited,
+                EscrowKind::Approval => escrow.token_b_deposited,
+                EscrowKind::HashLock => return Err(EscrowError::InvalidEscrowKind.into()),
+            };
 
-            if !escrow.token_a_deposited || !escrow.token_b_deposited {
-                return Err(EscrowError::EscrowNotReady.into());
+            if !completing {
+                msg!("Waiting for the other deposit, payout accounts ignored");
+                return Ok(());
             }
 
-            let (vault_pda, vault_bump) =
-                Pubkey::find_program_address(&[b"vault", escrow_account.key.as_ref()], program_id);
+            let (vault_token_a, vault_token_b) = if is_user_a {
+                (vault_token_account, &payout_accounts[1])
+            } else {
+                (&payout_accounts[1], vault_token_account)
+            };
 
-            let transfer_a_ix = spl_token::instruction::transfer(
-                token_program.key,
-                vault_token_a.key,
-                user_b_token_a_account.key,
-                &vault_pda,
-                &[],
-                escrow.amount_a,
-            )?;
+            let settlement = SettlementAccounts {
+                escrow_account,
+                vault_authority: &payout_accounts[0],
+                vault_token_a,
+                vault_token_b,
+                user_a_token_b_account: &payout_accounts[2],
+                user_b_token_a_account: &payout_accounts[3],
+                token_program,
+                market_search: accounts,
+                creation_search: accounts,
+            };
 
-            let transfer_b_ix = spl_token::instruction::transfer(
-                token_program.key,
-                vault_token_b.key,
-                user_a_token_b_account.key,
-                &vault_pda,
-                &[],
-                escrow.amount_b,
-            )?;
+            check_settlement_ready(program_id, escrow, depositor_account, &settlement)?;
+            drop(escrow_data);
 
-            let seeds: &[&[u8]] = &[b"vault", escrow_account.key.as_ref(), &[vault_bump]];
-            let signer_seeds = &[seeds];
+            settle_escrow(program_id, depositor_account, &settlement)?;
 
-            invoke_signed(
-                &transfer_a_ix,
-                &[
-                    vault_token_a.clone(),
-                    user_b_token_a_account.clone(),
-                    vault_authority.clone(),
-                    token_program.clone(),
-                ],
-                signer_seeds,
-            )?;
+            msg!("Tokens have been swapped successfully!");
+        }
 
-            invoke_signed(
-                &transfer_b_ix,
-                &[
-                    vault_token_b.clone(),
-                    user_a_token_b_account.clone(),
-                    vault_authority.clone(),
-                    token_program.clone(),
-                ],
-                signer_seeds,
-            )?;
+        EscrowInstruction::CompleteSwap => {
+            let caller = account_at(accounts, 0)?;
+            let settlement = SettlementAccounts {
+                escrow_account: account_at(accounts, 1)?,
+                vault_authority: account_at(accounts, 2)?,
+                vault_token_a: account_at(accounts, 3)?,
+                vault_token_b: account_at(accounts, 4)?,
+                user_a_token_b_account: account_at(accounts, 5)?,
+                user_b_token_a_account: account_at(accounts, 6)?,
+                token_program: account_at(accounts, 7)?,
+                market_search: accounts,
+                creation_search: accounts,
+            };
 
-            escrow.status = EscrowStatus::Completed;
+            check_token_program(settlement.token_program)?;
+
+            if !caller.is_signer {
+                return Err(EscrowError::UnauthorizedCancel.into());
+            }
+
+            check_settlement_ready(
+                program_id,
+                Escrow::load(&settlement.escrow_account.data.borrow())?,
+                caller,
+                &settlement,
+            )?;
 
-            escrow.serialize(&mut &mut escrow_account.data.borrow_mut()[..])?;
+            settle_escrow(program_id, caller, &settlement)?;
 
             msg!("Tokens have been swapped successfully!");
         }
 
-        EscrowInstruction::Cancel { .. } => {
-            let caller = &accounts[0];
-            let escrow_account = &accounts[1];
-            let vault_authority = &accounts[2];
-            let vault_token_a = &accounts[3];
-            let vault_token_b = &accounts[4];
-            let user_a_token_account = &accounts[5];
-            let user_b_token_account = &accounts[6];
-            let token_program = &accounts[7];
+        EscrowInstruction::CompleteSwapBatch => {
+            let caller = account_at(accounts, 0)?;
+            let token_program = account_at(accounts, 1)?;
+            let escrow_groups = &accounts[2..];
+
+            check_token_program(token_program)?;
 
             if !caller.is_signer {
                 return Err(EscrowError::UnauthorizedCancel.into());
             }
 
-            let mut escrow = Escrow::try_from_slice(&escrow_account.data.borrow())?;
+            if escrow_groups.is_empty() || !escrow_groups.len().is_multiple_of(BATCH_GROUP_LEN) {
+                return Err(ProgramError::NotEnoughAccountKeys);
+            }
+
+            let mut settled = 0;
+            let mut skipped = 0;
 
-            if caller.key != &escrow.user_a && caller.key != &escrow.user_b {
+            for (index, group) in escrow_groups.chunks(BATCH_GROUP_LEN).enumerate() {
+                //off-chain stubs report 0, only stop on a real reading
+                let remaining = sol_remaining_compute_units();
+                if remaining != 0 && remaining < BATCH_SETTLEMENT_RESERVE {
+                    msg!(
+                        "Batch stopped before escrow #{}: {} compute units left",
+                        index,
+                        remaining
+                    );
+                    break;
+                }
+
+                let settlement = SettlementAccounts {
+                    escrow_account: &group[0],
+                    vault_authority: &group[1],
+                    vault_token_a: &group[2],
+                    vault_token_b: &group[3],
+                    user_a_token_b_account: &group[4],
+                    user_b_token_a_account: &group[5],
+                    token_program,
+                    market_search: group,
+                    creation_search: &[],
+                };
+
+                //everything that could make a transfer fail is checked first, so an
+                //unready escrow is skipped instead of failing the whole batch
+                let ready =
+                    Escrow::load(&settlement.escrow_account.data.borrow()).and_then(|escrow| {
+                        check_settlement_ready(program_id, escrow, caller, &settlement)
+                    });
+
+                match ready {
+                    Ok(()) => {
+                        settle_escrow(program_id, caller, &settlement)?;
+                        settled += 1;
+                        msg!(
+                            "Escrow #{} {}: settled, {} compute units left",
+                            index,
+                            settlement.escrow_account.key,
+                            sol_remaining_compute_units()
+                        );
+                    }
+                    Err(err) => {
+                        skipped += 1;
+                        msg!(
+                            "Escrow #{} {}: skipped, {}",
+                            index,
+                            settlement.escrow_account.key,
+                            err
+                        );
+                    }
+                }
+            }
+
+            msg!("Batch complete! settled: {}, skipped: {}", settled, skipped);
+        }
+
+        EscrowInstruction::Cancel => {
+            let caller = account_at(accounts, 0)?;
+            let escrow_account = account_at(accounts, 1)?;
+            let vault_authority = account_at(accounts, 2)?;
+            let vault_token_a = account_at(accounts, 3)?;
+            let vault_token_b = account_at(accounts, 4)?;
+            let user_a_token_account = account_at(accounts, 5)?;
+            let user_b_token_account = account_at(accounts, 6)?;
+            let token_program = account_at(accounts, 7)?;
+
+            check_token_program(token_program)?;
+
+            let mut escrow_data = escrow_account.data.borrow_mut();
+            let escrow = Escrow::load_mut(&mut escrow_data)?;
+
+            if caller.key == &escrow.user_a {
+                authorize_maker(escrow, caller, MakerAction::new(MakerActionKind::Cancel))?;
+            } else if caller.key != &escrow.user_b || !caller.is_signer {
                 return Err(EscrowError::UnauthorizedCancel.into());
             }
 
@@ -244,8 +664,34 @@ pub fn process_instruction(
                 return Err(EscrowError::UnauthorizedCancel.into());
             }
 
-            let (vault_pda, vault_bump) =
-                Pubkey::find_program_address(&[b"vault", escrow_account.key.as_ref()], program_id);
+            //htlc refunds go back to user_a only once the timeout has passed
+            if escrow.kind == EscrowKind::HashLock {
+                if caller.key != &escrow.user_a {
+                    return Err(EscrowError::UnauthorizedCancel.into());
+                }
+                if Clock::get()?.unix_timestamp < escrow.hash_lock.timeout.get() {
+                    return Err(EscrowError::HashLockNotExpired.into());
+                }
+            }
+
+            check_vault_authority(
+                program_id,
+                escrow_account.key,
+                &escrow.vault_pda,
+                escrow.vault_bump,
+                vault_authority,
+            )?;
+            let (vault_pda, vault_bump) = (escrow.vault_pda, escrow.vault_bump);
+
+            if escrow.token_a_deposited {
+                check_recipient(user_a_token_account, &escrow.user_a, &escrow.token_a_mint)?;
+            }
+            if escrow.token_b_deposited {
+                check_recipient(user_b_token_account, &escrow.user_b, &escrow.token_b_mint)?;
+            }
+
+            //only a refund that passed every check takes the lock
+            lock_settlement(escrow)?;
 
             let seeds: &[&[u8]] = &[b"vault", escrow_account.key.as_ref(), &[vault_bump]];
             let signer_seeds = &[seeds];
@@ -258,7 +704,7 @@ pub fn process_instruction(
                     user_a_token_account.key,
                     &vault_pda,
                     &[],
-                    escrow.amount_a,
+                    escrow.amount_a.get(),
                 )?;
 
                 invoke_signed(
@@ -282,7 +728,7 @@ pub fn process_instruction(
                     user_b_token_account.key,
                     &vault_pda,
                     &[],
-                    escrow.amount_b,
+                    escrow.amount_b.get(),
                 )?;
 
                 invoke_signed(
@@ -299,12 +745,1991 @@ pub fn process_instruction(
                 escrow.token_b_deposited = false;
             }
 
-            escrow.status = EscrowStatus::Cancelled;
+            //an unclaimed keeper tip goes back to user_a, passed after the token program
+            //when user_b cancels
+            if escrow.keeper_tip.get() > 0 {
+                let user_a_account = if caller.key == &escrow.user_a {
+                    caller
+                } else {
+                    accounts.get(8).ok_or(ProgramError::NotEnoughAccountKeys)?
+                };
+                if user_a_account.key != &escrow.user_a {
+                    return Err(EscrowError::InvalidRecipient.into());
+                }
+                release_keeper_tip(escrow, escrow_account, user_a_account)?;
+            }
+
+            delist_from_market(program_id, escrow_account.key, escrow, accounts)?;
 
-            escrow.serialize(&mut &mut escrow_account.data.borrow_mut()[..])?;
+            escrow.status = EscrowStatus::Cancelled;
 
             msg!("Escrow cancelled! Refund has been initiated!");
+            log_reference(escrow);
+        }
+
+        EscrowInstruction::ClaimHashLock { preimage } => {
+            let caller = account_at(accounts, 0)?;
+            let escrow_account = account_at(accounts, 1)?;
+            let vault_authority = account_at(accounts, 2)?;
+            let vault_token_a = account_at(accounts, 3)?;
+            let user_b_token_a_account = account_at(accounts, 4)?;
+            let token_program = account_at(accounts, 5)?;
+
+            //anyone holding the preimage may claim, the payout always goes to user_b
+            if !caller.is_signer {
+                return Err(EscrowError::UnauthorizedCancel.into());
+            }
+
+            check_token_program(token_program)?;
+
+            let mut escrow_data = escrow_account.data.borrow_mut();
+            let escrow = Escrow::load_mut(&mut escrow_data)?;
+
+            if escrow.kind != EscrowKind::HashLock {
+                return Err(EscrowError::InvalidEscrowKind.into());
+            }
+
+            if !matches!(&escrow.status, EscrowStatus::Active) || !escrow.token_a_deposited {
+                return Err(EscrowError::EscrowNotReady.into());
+            }
+
+            if Clock::get()?.unix_timestamp >= escrow.hash_lock.timeout.get() {
+                return Err(EscrowError::HashLockExpired.into());
+            }
+
+            if !escrow.hash_lock.matches(&preimage) {
+                return Err(EscrowError::InvalidPreimage.into());
+            }
+
+            check_recipient(user_b_token_a_account, &escrow.user_b, &escrow.token_a_mint)?;
+
+            check_vault_authority(
+                program_id,
+                escrow_account.key,
+                &escrow.vault_pda,
+                escrow.vault_bump,
+                vault_authority,
+            )?;
+            let (vault_pda, vault_bump) = (escrow.vault_pda, escrow.vault_bump);
+
+            lock_settlement(escrow)?;
+
+            let transfer_a_ix = spl_token::instruction::transfer(
+                token_program.key,
+                vault_token_a.key,
+                user_b_token_a_account.key,
+                &vault_pda,
+                &[],
+                escrow.amount_a.get(),
+            )?;
+
+            let seeds: &[&[u8]] = &[b"vault", escrow_account.key.as_ref(), &[vault_bump]];
+            let signer_seeds = &[seeds];
+
+            invoke_signed(
+                &transfer_a_ix,
+                &[
+                    vault_token_a.clone(),
+                    user_b_token_a_account.clone(),
+                    vault_authority.clone(),
+                    token_program.clone(),
+                ],
+                signer_seeds,
+            )?;
+
+            escrow.token_a_deposited = false;
+            escrow.status = EscrowStatus::Completed;
+
+            let preimage_hex: String = preimage.iter().map(|b| format!("{:02x}", b)).collect();
+            msg!("HTLC claimed! Preimage: {}", preimage_hex);
+            log_reference(escrow);
         }
+
+        EscrowInstruction::FillSignedOffer { offer } => {
+            let taker = account_at(accounts, 0)?;
+            let maker_token_a_account = account_at(accounts, 1)?;
+            let maker_token_b_account = account_at(accounts, 2)?;
+            let taker_token_a_account = account_at(accounts, 3)?;
+            let taker_token_b_account = account_at(accounts, 4)?;
+            let delegate_authority = account_at(accounts, 5)?;
+            let offer_record_account = account_at(accounts, 6)?;
+            let instructions_sysvar = account_at(accounts, 7)?;
+            let token_program = account_at(accounts, 8)?;
+            let system_program = account_at(accounts, 9)?;
+
+            check_token_program(token_program)?;
+
+            if !taker.is_signer {
+                return Err(EscrowError::UnauthorizedCancel.into());
+            }
+
+            if offer.program_id != *program_id {
+                return Err(EscrowError::InvalidSignature.into());
+            }
+
+            check_ed25519_signature(instructions_sysvar, &offer.maker, &borsh::to_vec(&offer)?)?;
+
+            if offer.taker != Pubkey::default() && offer.taker != *taker.key {
+                return Err(EscrowError::UnauthorizedCancel.into());
+            }
+
+            if Clock::get()?.unix_timestamp >= offer.expiry {
+                return Err(EscrowError::OfferExpired.into());
+            }
+
+            //the maker's side of the trade must come from and go to accounts they own
+            let maker_token_a = TokenAccount::unpack(&maker_token_a_account.data.borrow())?;
+            let maker_token_b = TokenAccount::unpack(&maker_token_b_account.data.borrow())?;
+            if maker_token_a.owner != offer.maker
+                || maker_token_a.mint != offer.token_a_mint
+                || maker_token_b.owner != offer.maker
+                || maker_token_b.mint != offer.token_b_mint
+            {
+                return Err(EscrowError::InvalidTokenAccount.into());
+            }
+
+            //one record per (maker, nonce), so an offer can only be filled once
+            let nonce_bytes = offer.nonce.to_le_bytes();
+            let (offer_record_pda, offer_record_bump) = Pubkey::find_program_address(
+                &[b"offer", offer.maker.as_ref(), &nonce_bytes],
+                program_id,
+            );
+
+            if offer_record_account.key != &offer_record_pda {
+                return Err(ProgramError::InvalidSeeds);
+            }
+
+            if offer_record_account.owner == program_id {
+                return Err(EscrowError::OfferAlreadyFilled.into());
+            }
+
+            let offer_record = OfferRecord {
+                maker: offer.maker,
+                taker: *taker.key,
+                nonce: offer.nonce,
+            };
+            let record_size = borsh::to_vec(&offer_record)?.len();
+
+            create_pda(
+                program_id,
+                taker,
+                offer_record_account,
+                system_program,
+                record_size,
+                &[
+                    b"offer",
+                    offer.maker.as_ref(),
+                    &nonce_bytes,
+                    &[offer_record_bump],
+                ],
+            )?;
+
+            offer_record.serialize(&mut &mut offer_record_account.data.borrow_mut()[..])?;
+
+            let (delegate_pda, delegate_bump) =
+                Pubkey::find_program_address(&[b"delegate"], program_id);
+
+            //maker -> taker through the delegate approval
+            let transfer_a_ix = spl_token::instruction::transfer(
+                token_program.key,
+                maker_token_a_account.key,
+                taker_token_a_account.key,
+                &delegate_pda,
+                &[],
+                offer.amount_a,
+            )?;
+
+            invoke_signed(
+                &transfer_a_ix,
+                &[
+                    maker_token_a_account.clone(),
+                    taker_token_a_account.clone(),
+                    delegate_authority.clone(),
+                    token_program.clone(),
+                ],
+                &[&[b"delegate", &[delegate_bump]]],
+            )?;
+
+            //taker -> maker
+            let transfer_b_ix = spl_token::instruction::transfer(
+                token_program.key,
+                taker_token_b_account.key,
+                maker_token_b_account.key,
+                taker.key,
+                &[],
+                offer.amount_b,
+            )?;
+
+            invoke(
+                &transfer_b_ix,
+                &[
+                    taker_token_b_account.clone(),
+                    maker_token_b_account.clone(),
+                    taker.clone(),
+                    token_program.clone(),
+                ],
+            )?;
+
+            msg!(
+                "Signed offer {} from {} filled by {}",
+                offer.nonce,
+                offer.maker,
+                taker.key
+            );
+        }
+
+        EscrowInstruction::UpdateTerms { amount_a, amount_b } => {
+            let user_a_account = account_at(accounts, 0)?;
+            let escrow_account = account_at(accounts, 1)?;
+
+            let mut escrow_data = escrow_account.data.borrow_mut();
+            let escrow = Escrow::load_mut(&mut escrow_data)?;
+
+            authorize_maker(
+                escrow,
+                user_a_account,
+                MakerAction::with_amounts(MakerActionKind::UpdateTerms, amount_a, amount_b),
+            )?;
+
+            check_terms_open(escrow)?;
+
+            rebalance_token_a_deposit(program_id, accounts, escrow, amount_a)?;
+
+            escrow.amount_a = amount_a.into();
+            escrow.amount_b = amount_b.into();
+
+            //the offer moves to its new place in the book
+            if escrow.listed {
+                delist_from_market(program_id, escrow_account.key, escrow, accounts)?;
+                let market_account = find_market(program_id, escrow, accounts)
+                    .ok_or(ProgramError::NotEnoughAccountKeys)?;
+                list_on_market(program_id, escrow_account.key, escrow, market_account)?;
+            }
+
+            //a pending counter offer was made against the old terms
+            escrow.counter_proposer = Pubkey::default();
+            escrow.counter_amount_a = 0.into();
+            escrow.counter_amount_b = 0.into();
+
+            msg!(
+                "Terms updated! amount_a: {}, amount_b: {}",
+                amount_a,
+                amount_b
+            );
+            log_reference(escrow);
+        }
+
+        EscrowInstruction::ProposeCounter { amount_a, amount_b } => {
+            let proposer_account = account_at(accounts, 0)?;
+            let escrow_account = account_at(accounts, 1)?;
+
+            if !proposer_account.is_signer {
+                return Err(EscrowError::UnauthorizedCancel.into());
+            }
+
+            let mut escrow_data = escrow_account.data.borrow_mut();
+            let escrow = Escrow::load_mut(&mut escrow_data)?;
+
+            check_terms_open(escrow)?;
+
+            if proposer_account.key == &escrow.user_a {
+                return Err(EscrowError::UnauthorizedCancel.into());
+            }
+
+            //an escrow already reserved for a taker only negotiates with that taker
+            if escrow.user_b != Pubkey::default() && proposer_account.key != &escrow.user_b {
+                return Err(EscrowError::UnauthorizedCancel.into());
+            }
+
+            escrow.counter_proposer = *proposer_account.key;
+            escrow.counter_amount_a = amount_a.into();
+            escrow.counter_amount_b = amount_b.into();
+
+            msg!(
+                "Counter offer from {}! amount_a: {}, amount_b: {}",
+                proposer_account.key,
+                amount_a,
+                amount_b
+            );
+            log_reference(escrow);
+        }
+
+        EscrowInstruction::AcceptCounter {
+            proposer,
+            amount_a,
+            amount_b,
+        } => {
+            let user_a_account = account_at(accounts, 0)?;
+            let escrow_account = account_at(accounts, 1)?;
+
+            let mut escrow_data = escrow_account.data.borrow_mut();
+            let escrow = Escrow::load_mut(&mut escrow_data)?;
+
+            check_terms_open(escrow)?;
+
+            if escrow.counter_proposer == Pubkey::default() {
+                return Err(EscrowError::NoCounterOffer.into());
+            }
+
+            //anyone can replace the counter while the accept is in flight
+            if escrow.counter_proposer != proposer
+                || escrow.counter_amount_a.get() != amount_a
+                || escrow.counter_amount_b.get() != amount_b
+            {
+                return Err(EscrowError::SlippageExceeded.into());
+            }
+
+            //binding the amounts stops the proposer from swapping the counter under the approvals
+            let accept_action =
+                MakerAction::with_amounts(MakerActionKind::AcceptCounter, amount_a, amount_b);
+            authorize_maker(escrow, user_a_account, accept_action)?;
+
+            rebalance_token_a_deposit(program_id, accounts, escrow, escrow.counter_amount_a.get())?;
+
+            escrow.amount_a = escrow.counter_amount_a;
+            escrow.amount_b = escrow.counter_amount_b;
+            escrow.user_b = escrow.counter_proposer;
+
+            //reserved for the proposer, so no longer open to other takers
+            delist_from_market(program_id, escrow_account.key, escrow, accounts)?;
+
+            escrow.counter_proposer = Pubkey::default();
+            escrow.counter_amount_a = 0.into();
+            escrow.counter_amount_b = 0.into();
+
+            msg!(
+                "Counter offer accepted! Escrow reserved for {}",
+                escrow.user_b
+            );
+            log_reference(escrow);
+        }
+
+        EscrowInstruction::InitConfig => {
+            let admin_account = account_at(accounts, 0)?;
+            let config_account = account_at(accounts, 1)?;
+            let system_program = account_at(accounts, 2)?;
+            let program_data_account = account_at(accounts, 3)?;
+
+            if !admin_account.is_signer {
+                return Err(EscrowError::UnauthorizedCancel.into());
+            }
+
+            //whoever sends this first would own the kill switch, so only the deployer may
+            check_upgrade_authority(program_id, program_data_account, admin_account.key)?;
+
+            let (config_pda, config_bump) = Pubkey::find_program_address(&[b"config"], program_id);
+
+            if config_account.key != &config_pda {
+                return Err(ProgramError::InvalidSeeds);
+            }
+
+            if config_account.owner == program_id {
+                return Err(ProgramError::AccountAlreadyInitialized);
+            }
+
+            let config = Config {
+                admin: *admin_account.key,
+                paused: false,
+            };
+            let config_size = borsh::to_vec(&config)?.len();
+
+            create_pda(
+                program_id,
+                admin_account,
+                config_account,
+                system_program,
+                config_size,
+                &[b"config", &[config_bump]],
+            )?;
+
+            config.serialize(&mut &mut config_account.data.borrow_mut()[..])?;
+
+            msg!("Config initialized! Admin: {}", admin_account.key);
+        }
+
+        EscrowInstruction::SetPaused { paused } => {
+            let admin_account = account_at(accounts, 0)?;
+            let config_account = account_at(accounts, 1)?;
+
+            if !admin_account.is_signer {
+                return Err(EscrowError::UnauthorizedCancel.into());
+            }
+
+            let (config_pda, _) = Pubkey::find_program_address(&[b"config"], program_id);
+
+            if config_account.key != &config_pda {
+                return Err(ProgramError::InvalidSeeds);
+            }
+
+            if config_account.owner != program_id {
+                return Err(ProgramError::IncorrectProgramId);
+            }
+
+            let mut config = Config::try_from_slice(&config_account.data.borrow())?;
+
+            if admin_account.key != &config.admin {
+                return Err(EscrowError::UnauthorizedCancel.into());
+            }
+
+            config.paused = paused;
+
+            config.serialize(&mut &mut config_account.data.borrow_mut()[..])?;
+
+            msg!("Program paused: {}", paused);
+        }
+
+        EscrowInstruction::Approve { action } => {
+            let signer_account = account_at(accounts, 0)?;
+            let escrow_account = account_at(accounts, 1)?;
+
+            if !signer_account.is_signer {
+                return Err(EscrowError::UnauthorizedCancel.into());
+            }
+
+            let mut escrow_data = escrow_account.data.borrow_mut();
+            let escrow = Escrow::load_mut(&mut escrow_data)?;
+
+            if !escrow.multisig.is_enabled() {
+                return Err(EscrowError::InvalidMultisig.into());
+            }
+
+            if !matches!(&escrow.status, EscrowStatus::Active) {
+                return Err(EscrowError::EscrowNotReady.into());
+            }
+
+            let index = escrow
+                .multisig
+                .position(signer_account.key)
+                .ok_or(EscrowError::UnauthorizedCancel)?;
+
+            escrow.multisig.approve(index, action);
+
+            msg!(
+                "{} approved {:?}! {} of {} approvals",
+                signer_account.key,
+                action.kind,
+                escrow.multisig.approval_count(&action),
+                escrow.multisig.threshold
+            );
+            log_reference(escrow);
+        }
+
+        EscrowInstruction::InitRing { participants } => {
+            let creator_account = account_at(accounts, 0)?;
+            let ring_account = account_at(accounts, 1)?;
+            let rent_account = account_at(accounts, 2)?;
+
+            if participants.len() < 3 || participants.len() > MAX_RING_PARTICIPANTS {
+                return Err(EscrowError::InvalidParticipants.into());
+            }
+
+            for (index, leg) in participants.iter().enumerate() {
+                if leg.owner == Pubkey::default()
+                    || participants[..index]
+                        .iter()
+                        .any(|other| other.owner == leg.owner)
+                {
+                    return Err(EscrowError::InvalidParticipants.into());
+                }
+                if leg.amount == 0 {
+                    return Err(EscrowError::InvalidAmount.into());
+                }
+            }
+
+            //only a party to the trade can open it
+            if !creator_account.is_signer
+                || !participants
+                    .iter()
+                    .any(|leg| &leg.owner == creator_account.key)
+            {
+                return Err(EscrowError::UnauthorizedCancel.into());
+            }
+
+            check_rent_exempt(ring_account, rent_account)?;
+
+            if ring_account.owner != program_id {
+                return Err(ProgramError::IncorrectProgramId);
+            }
+
+            let mut ring = RingTrade::try_from_slice(&ring_account.data.borrow())?;
+            if ring.participant_count != 0 {
+                return Err(ProgramError::AccountAlreadyInitialized);
+            }
+
+            for (slot, leg) in ring.participants.iter_mut().zip(&participants) {
+                *slot = RingParticipant {
+                    owner: leg.owner,
+                    mint: leg.mint,
+                    amount: leg.amount,
+                    deposited: false,
+                };
+            }
+            ring.participant_count = participants.len() as u8;
+            (ring.vault_pda, ring.vault_bump) =
+                Pubkey::find_program_address(&[b"vault", ring_account.key.as_ref()], program_id);
+            ring.status = EscrowStatus::Active;
+
+            ring.serialize(&mut &mut ring_account.data.borrow_mut()[..])?;
+
+            msg!(
+                "Ring trade created with {} participants",
+                participants.len()
+            );
+        }
+
+        EscrowInstruction::DepositRing { amount } => {
+            let depositor_account = account_at(accounts, 0)?;
+            let ring_account = account_at(accounts, 1)?;
+            let depositor_token_account = account_at(accounts, 2)?;
+            let vault_token_account = account_at(accounts, 3)?;
+            let token_program = account_at(accounts, 4)?;
+
+            check_token_program(token_program)?;
+
+            if ring_account.owner != program_id {
+                return Err(ProgramError::IncorrectProgramId);
+            }
+
+            let mut ring = RingTrade::try_from_slice(&ring_account.data.borrow())?;
+
+            if !matches!(&ring.status, EscrowStatus::Active) {
+                return Err(EscrowError::EscrowNotReady.into());
+            }
+
+            let index = ring
+                .position(depositor_account.key)
+                .ok_or(EscrowError::UnauthorizedCancel)?;
+            let leg = ring.participants[index];
+
+            if !depositor_account.is_signer {
+                return Err(EscrowError::UnauthorizedCancel.into());
+            }
+
+            if leg.deposited {
+                return Err(EscrowError::AlreadyDeposited.into());
+            }
+
+            if amount != leg.amount {
+                return Err(EscrowError::InvalidAmount.into());
+            }
+
+            let vault_token = TokenAccount::unpack(&vault_token_account.data.borrow())?;
+            if vault_token.owner != ring.vault_pda || vault_token.mint != leg.mint {
+                return Err(EscrowError::InvalidTokenAccount.into());
+            }
+
+            let transfer_instruction = spl_token::instruction::transfer(
+                token_program.key,
+                depositor_token_account.key,
+                vault_token_account.key,
+                depositor_account.key,
+                &[],
+                amount,
+            )?;
+
+            invoke(
+                &transfer_instruction,
+                &[
+                    depositor_account.clone(),
+                    vault_token_account.clone(),
+                    depositor_token_account.clone(),
+                    token_program.clone(),
+                ],
+            )?;
+
+            ring.participants[index].deposited = true;
+
+            ring.serialize(&mut &mut ring_account.data.borrow_mut()[..])?;
+
+            msg!(
+                "Ring leg {} funded! {} of {} legs",
+                index,
+                ring.legs().iter().filter(|leg| leg.deposited).count(),
+                ring.participant_count
+            );
+        }
+
+        EscrowInstruction::CompleteRing => {
+            let caller = account_at(accounts, 0)?;
+            let ring_account = account_at(accounts, 1)?;
+            let vault_authority = account_at(accounts, 2)?;
+            let token_program = account_at(accounts, 3)?;
+
+            check_token_program(token_program)?;
+
+            let mut ring = load_ring_for_participant(program_id, ring_account, caller)?;
+
+            if !ring.is_funded() {
+                return Err(EscrowError::EscrowNotReady.into());
+            }
+
+            let leg_accounts = ring_leg_accounts(&ring, accounts)?;
+
+            check_vault_authority(
+                program_id,
+                ring_account.key,
+                &ring.vault_pda,
+                ring.vault_bump,
+                vault_authority,
+            )?;
+
+            //every recipient account is checked before the first transfer
+            for (index, (_, recipient_token_account)) in leg_accounts.iter().enumerate() {
+                let recipient = ring.recipient_of(index);
+                check_recipient(
+                    recipient_token_account,
+                    &recipient.owner,
+                    &ring.participants[index].mint,
+                )?;
+            }
+
+            for (index, (vault_token_account, recipient_token_account)) in
+                leg_accounts.iter().enumerate()
+            {
+                transfer_from_vault(
+                    ring_account,
+                    ring.vault_bump,
+                    vault_authority,
+                    token_program,
+                    vault_token_account,
+                    recipient_token_account,
+                    ring.participants[index].amount,
+                )?;
+            }
+
+            ring.status = EscrowStatus::Completed;
+
+            ring.serialize(&mut &mut ring_account.data.borrow_mut()[..])?;
+
+            msg!("Ring trade settled! {} legs paid", ring.participant_count);
+        }
+
+        EscrowInstruction::CancelRing => {
+            let caller = account_at(accounts, 0)?;
+            let ring_account = account_at(accounts, 1)?;
+            let vault_authority = account_at(accounts, 2)?;
+            let token_program = account_at(accounts, 3)?;
+
+            check_token_program(token_program)?;
+
+            let mut ring = load_ring_for_participant(program_id, ring_account, caller)?;
+
+            //a fully funded ring can only settle, same as a swap with both deposits
+            if ring.is_funded() {
+                return Err(EscrowError::UnauthorizedCancel.into());
+            }
+
+            let leg_accounts = ring_leg_accounts(&ring, accounts)?;
+
+            check_vault_authority(
+                program_id,
+                ring_account.key,
+                &ring.vault_pda,
+                ring.vault_bump,
+                vault_authority,
+            )?;
+
+            for (index, (vault_token_account, refund_token_account)) in
+                leg_accounts.iter().enumerate()
+            {
+                let leg = ring.participants[index];
+                if !leg.deposited {
+                    continue;
+                }
+
+                check_recipient(refund_token_account, &leg.owner, &leg.mint)?;
+
+                transfer_from_vault(
+                    ring_account,
+                    ring.vault_bump,
+                    vault_authority,
+                    token_program,
+                    vault_token_account,
+                    refund_token_account,
+                    leg.amount,
+                )?;
+
+                ring.participants[index].deposited = false;
+            }
+
+            ring.status = EscrowStatus::Cancelled;
+
+            ring.serialize(&mut &mut ring_account.data.borrow_mut()[..])?;
+
+            msg!("Ring trade cancelled! Funded legs refunded");
+        }
+
+        EscrowInstruction::PlaceBid { amount } => {
+            let bidder_account = account_at(accounts, 0)?;
+            let escrow_account = account_at(accounts, 1)?;
+            let bid_account = account_at(accounts, 2)?;
+            let bidder_token_account = account_at(accounts, 3)?;
+            let vault_token_account = account_at(accounts, 4)?;
+            let token_program = account_at(accounts, 5)?;
+            let system_program = account_at(accounts, 6)?;
+
+            check_token_program(token_program)?;
+
+            if escrow_account.owner != program_id {
+                return Err(ProgramError::IncorrectProgramId);
+            }
+
+            let mut escrow_data = escrow_account.data.borrow_mut();
+            let escrow = Escrow::load_mut(&mut escrow_data)?;
+
+            if escrow.kind != EscrowKind::EnglishAuction {
+                return Err(EscrowError::InvalidEscrowKind.into());
+            }
+
+            if !bidder_account.is_signer || bidder_account.key == &escrow.user_a {
+                return Err(EscrowError::UnauthorizedCancel.into());
+            }
+
+            if !matches!(&escrow.status, EscrowStatus::Active) || !escrow.token_a_deposited {
+                return Err(EscrowError::EscrowNotReady.into());
+            }
+
+            if !escrow.auction.is_live(Clock::get()?.unix_timestamp) {
+                return Err(EscrowError::AuctionNotLive.into());
+            }
+
+            check_credential(program_id, escrow, bidder_account.key, accounts)?;
+
+            let minimum_bid = if escrow.token_b_deposited {
+                math::add(
+                    escrow.amount_b.get(),
+                    escrow.auction.min_increment.get().max(1),
+                )?
+            } else {
+                escrow.auction.start_price.get()
+            };
+            if amount < minimum_bid {
+                return Err(EscrowError::InvalidAmount.into());
+            }
+
+            let vault_token = TokenAccount::unpack(&vault_token_account.data.borrow())?;
+            if vault_token.owner != escrow.vault_pda || vault_token.mint != escrow.token_b_mint {
+                return Err(EscrowError::InvalidTokenAccount.into());
+            }
+
+            let (mut bid, bid_bump) = load_bid(
+                program_id,
+                bid_account,
+                escrow_account.key,
+                bidder_account.key,
+            )?;
+
+            if bid_account.owner != program_id {
+                bid = Bid {
+                    escrow: *escrow_account.key,
+                    bidder: *bidder_account.key,
+                    amount: 0,
+                };
+                let bid_size = borsh::to_vec(&bid)?.len();
+
+                create_pda(
+                    program_id,
+                    bidder_account,
+                    bid_account,
+                    system_program,
+                    bid_size,
+                    &[
+                        b"bid",
+                        escrow_account.key.as_ref(),
+                        bidder_account.key.as_ref(),
+                        &[bid_bump],
+                    ],
+                )?;
+            }
+
+            //an earlier bid that was not refunded yet counts towards the new one
+            let top_up = math::sub(amount, bid.amount)?;
+
+            let transfer_instruction = spl_token::instruction::transfer(
+                token_program.key,
+                bidder_token_account.key,
+                vault_token_account.key,
+                bidder_account.key,
+                &[],
+                top_up,
+            )?;
+
+            invoke(
+                &transfer_instruction,
+                &[
+                    bidder_account.clone(),
+                    vault_token_account.clone(),
+                    bidder_token_account.clone(),
+                    token_program.clone(),
+                ],
+            )?;
+
+            bid.amount = amount;
+            bid.serialize(&mut &mut bid_account.data.borrow_mut()[..])?;
+
+            escrow.user_b = *bidder_account.key;
+            escrow.amount_b = amount.into();
+            escrow.token_b_deposited = true;
+
+            msg!("New highest bid! {} bid {}", bidder_account.key, amount);
+            log_reference(escrow);
+        }
+
+        EscrowInstruction::RefundBid => {
+            let bidder_account = account_at(accounts, 0)?;
+            let escrow_account = account_at(accounts, 1)?;
+            let bid_account = account_at(accounts, 2)?;
+            let vault_authority = account_at(accounts, 3)?;
+            let vault_token_account = account_at(accounts, 4)?;
+            let bidder_token_account = account_at(accounts, 5)?;
+            let token_program = account_at(accounts, 6)?;
+
+            check_token_program(token_program)?;
+
+            if !bidder_account.is_signer {
+                return Err(EscrowError::UnauthorizedCancel.into());
+            }
+
+            if escrow_account.owner != program_id {
+                return Err(ProgramError::IncorrectProgramId);
+            }
+
+            let escrow_data = escrow_account.data.borrow();
+            let escrow = Escrow::load(&escrow_data)?;
+
+            if escrow.kind != EscrowKind::EnglishAuction {
+                return Err(EscrowError::InvalidEscrowKind.into());
+            }
+
+            let (mut bid, _) = load_bid(
+                program_id,
+                bid_account,
+                escrow_account.key,
+                bidder_account.key,
+            )?;
+
+            if bid.amount == 0 {
+                return Err(EscrowError::InvalidAmount.into());
+            }
+
+            //the highest bid stays in the vault, it is paid to user_a at settlement
+            if escrow.user_b == *bidder_account.key {
+                return Err(EscrowError::UnauthorizedCancel.into());
+            }
+
+            check_recipient(bidder_token_account, &bid.bidder, &escrow.token_b_mint)?;
+
+            check_vault_authority(
+                program_id,
+                escrow_account.key,
+                &escrow.vault_pda,
+                escrow.vault_bump,
+                vault_authority,
+            )?;
+
+            transfer_from_vault(
+                escrow_account,
+                escrow.vault_bump,
+                vault_authority,
+                token_program,
+                vault_token_account,
+                bidder_token_account,
+                bid.amount,
+            )?;
+
+            msg!("Outbid refund! {} got back {}", bid.bidder, bid.amount);
+            log_reference(escrow);
+
+            bid.amount = 0;
+            bid.serialize(&mut &mut bid_account.data.borrow_mut()[..])?;
+        }
+
+        EscrowInstruction::InitMarket {
+            token_a_mint,
+            token_b_mint,
+        } => {
+            let payer_account = account_at(accounts, 0)?;
+            let market_account = account_at(accounts, 1)?;
+            let system_program = account_at(accounts, 2)?;
+
+            if !payer_account.is_signer {
+                return Err(EscrowError::UnauthorizedCancel.into());
+            }
+
+            let (market_pda, market_bump) = Pubkey::find_program_address(
+                &[b"market", token_a_mint.as_ref(), token_b_mint.as_ref()],
+                program_id,
+            );
+
+            if market_account.key != &market_pda {
+                return Err(ProgramError::InvalidSeeds);
+            }
+
+            if market_account.owner == program_id {
+                return Err(ProgramError::AccountAlreadyInitialized);
+            }
+
+            let market = Market {
+                token_a_mint,
+                token_b_mint,
+                ..Market::default()
+            };
+            let market_size = borsh::to_vec(&market)?.len();
+
+            create_pda(
+                program_id,
+                payer_account,
+                market_account,
+                system_program,
+                market_size,
+                &[
+                    b"market",
+                    token_a_mint.as_ref(),
+                    token_b_mint.as_ref(),
+                    &[market_bump],
+                ],
+            )?;
+
+            market.serialize(&mut &mut market_account.data.borrow_mut()[..])?;
+
+            msg!("Market opened for {} / {}", token_a_mint, token_b_mint);
+        }
+
+        EscrowInstruction::IssueCredential {
+            subject,
+            expires_at,
+        } => {
+            let issuer_account = account_at(accounts, 0)?;
+            let credential_account = account_at(accounts, 1)?;
+            let system_program = account_at(accounts, 2)?;
+
+            if !issuer_account.is_signer {
+                return Err(EscrowError::UnauthorizedCancel.into());
+            }
+
+            let (credential_pda, credential_bump) = Pubkey::find_program_address(
+                &[b"credential", issuer_account.key.as_ref(), subject.as_ref()],
+                program_id,
+            );
+
+            if credential_account.key != &credential_pda {
+                return Err(ProgramError::InvalidSeeds);
+            }
+
+            let credential = Credential {
+                issuer: *issuer_account.key,
+                subject,
+                expires_at,
+            };
+
+            //renewals and revocations rewrite the existing account
+            if credential_account.owner != program_id {
+                let credential_size = borsh::to_vec(&credential)?.len();
+
+                create_pda(
+                    program_id,
+                    issuer_account,
+                    credential_account,
+                    system_program,
+                    credential_size,
+                    &[
+                        b"credential",
+                        issuer_account.key.as_ref(),
+                        subject.as_ref(),
+                        &[credential_bump],
+                    ],
+                )?;
+            }
+
+            credential.serialize(&mut &mut credential_account.data.borrow_mut()[..])?;
+
+            msg!("Credential for {} valid until {}", subject, expires_at);
+        }
+    }
+
+    Ok(())
+}
+
+//the escrow's [b"market", token_a_mint, token_b_mint] pda when it is among `accounts`
+fn find_market<'a, 'b>(
+    program_id: &Pubkey,
+    escrow: &Escrow,
+    accounts: &'a [AccountInfo<'b>],
+) -> Option<&'a AccountInfo<'b>> {
+    let (market_pda, _) = Pubkey::find_program_address(
+        &[
+            b"market",
+            escrow.token_a_mint.as_ref(),
+            escrow.token_b_mint.as_ref(),
+        ],
+        program_id,
+    );
+
+    accounts.iter().find(|account| account.key == &market_pda)
+}
+
+fn list_on_market(
+    program_id: &Pubkey,
+    escrow_key: &Pubkey,
+    escrow: &mut Escrow,
+    market_account: &AccountInfo,
+) -> ProgramResult {
+    if market_account.owner != program_id {
+        return Err(ProgramError::UninitializedAccount);
+    }
+
+    if escrow.amount_a.get() == 0 {
+        return Err(EscrowError::InvalidAmount.into());
+    }
+
+    let mut market = Market::try_from_slice(&market_account.data.borrow())?;
+
+    let offer = MarketOffer {
+        escrow: *escrow_key,
+        amount_a: escrow.amount_a.get(),
+        amount_b: escrow.amount_b.get(),
+    };
+    if !market.insert(offer) {
+        return Err(EscrowError::MarketFull.into());
+    }
+
+    market.serialize(&mut &mut market_account.data.borrow_mut()[..])?;
+
+    escrow.listed = true;
+
+    Ok(())
+}
+
+//a listed escrow needs its market among `accounts` to leave the book, one that a better
+//offer pushed off a full book is already gone from it
+fn delist_from_market(
+    program_id: &Pubkey,
+    escrow_key: &Pubkey,
+    escrow: &mut Escrow,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    if !escrow.listed {
+        return Ok(());
+    }
+
+    let market_account =
+        find_market(program_id, escrow, accounts).ok_or(ProgramError::NotEnoughAccountKeys)?;
+
+    let mut market = Market::try_from_slice(&market_account.data.borrow())?;
+    market.remove(escrow_key);
+    market.serialize(&mut &mut market_account.data.borrow_mut()[..])?;
+
+    escrow.listed = false;
+
+    Ok(())
+}
+
+//a taker of a credential gated escrow needs a live credential from its issuer, the pda is
+//looked up among the accounts
+fn check_credential(
+    program_id: &Pubkey,
+    escrow: &Escrow,
+    taker: &Pubkey,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    if escrow.credential_issuer == Pubkey::default() {
+        return Ok(());
+    }
+
+    let (credential_pda, _) = Pubkey::find_program_address(
+        &[
+            b"credential",
+            escrow.credential_issuer.as_ref(),
+            taker.as_ref(),
+        ],
+        program_id,
+    );
+
+    let credential_account = accounts
+        .iter()
+        .find(|account| account.key == &credential_pda)
+        .ok_or(EscrowError::InvalidCredential)?;
+
+    if credential_account.owner != program_id {
+        return Err(EscrowError::InvalidCredential.into());
+    }
+
+    let credential = Credential::try_from_slice(&credential_account.data.borrow())?;
+    if credential.issuer != escrow.credential_issuer || &credential.subject != taker {
+        return Err(EscrowError::InvalidCredential.into());
+    }
+
+    if credential.expires_at <= Clock::get()?.unix_timestamp {
+        return Err(EscrowError::CredentialExpired.into());
+    }
+
+    Ok(())
+}
+
+//the [b"bid", escrow, bidder] record, Bid::default() when it was never created
+fn load_bid(
+    program_id: &Pubkey,
+    bid_account: &AccountInfo,
+    escrow_key: &Pubkey,
+    bidder: &Pubkey,
+) -> Result<(Bid, u8), ProgramError> {
+    let (bid_pda, bid_bump) =
+        Pubkey::find_program_address(&[b"bid", escrow_key.as_ref(), bidder.as_ref()], program_id);
+
+    if bid_account.key != &bid_pda {
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    if bid_account.owner != program_id {
+        return Ok((Bid::default(), bid_bump));
+    }
+
+    Ok((Bid::try_from_slice(&bid_account.data.borrow())?, bid_bump))
+}
+
+//an active ring and a signing caller who is one of its participants
+fn load_ring_for_participant(
+    program_id: &Pubkey,
+    ring_account: &AccountInfo,
+    caller: &AccountInfo,
+) -> Result<RingTrade, ProgramError> {
+    if ring_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let ring = RingTrade::try_from_slice(&ring_account.data.borrow())?;
+
+    if !caller.is_signer || ring.position(caller.key).is_none() {
+        return Err(EscrowError::UnauthorizedCancel.into());
+    }
+
+    if !matches!(&ring.status, EscrowStatus::Active) {
+        return Err(EscrowError::EscrowNotReady.into());
+    }
+
+    Ok(ring)
+}
+
+//the (vault token account, counterpart token account) pair passed for every leg
+fn ring_leg_accounts<'a, 'b>(
+    ring: &RingTrade,
+    accounts: &'a [AccountInfo<'b>],
+) -> Result<Vec<(&'a AccountInfo<'b>, &'a AccountInfo<'b>)>, ProgramError> {
+    let leg_accounts = accounts.get(RING_LEGS_START..).unwrap_or_default();
+    if leg_accounts.len() != ring.participant_count as usize * 2 {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    }
+
+    Ok(leg_accounts
+        .chunks(2)
+        .map(|pair| (&pair[0], &pair[1]))
+        .collect())
+}
+
+//the account at `index`, a short account list fails the instruction instead of panicking
+fn account_at<'a, 'b>(
+    accounts: &'a [AccountInfo<'b>],
+    index: usize,
+) -> Result<&'a AccountInfo<'b>, ProgramError> {
+    accounts
+        .get(index)
+        .ok_or(ProgramError::NotEnoughAccountKeys)
+}
+
+//`authority` must be the upgrade authority recorded in the program's programdata account
+fn check_upgrade_authority(
+    program_id: &Pubkey,
+    program_data_account: &AccountInfo,
+    authority: &Pubkey,
+) -> ProgramResult {
+    if program_data_account.key != &get_program_data_address(program_id)
+        || program_data_account.owner != &bpf_loader_upgradeable::id()
+    {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let state: UpgradeableLoaderState = limited_deserialize(
+        &program_data_account.data.borrow(),
+        UpgradeableLoaderState::size_of_programdata_metadata() as u64,
+    )
+    .map_err(|_| ProgramError::InvalidAccountData)?;
+
+    match state {
+        UpgradeableLoaderState::ProgramData {
+            upgrade_authority_address: Some(upgrade_authority),
+            ..
+        } if &upgrade_authority == authority => Ok(()),
+        _ => Err(EscrowError::UnauthorizedCancel.into()),
+    }
+}
+
+//token cpis only go to spl token, any other program could report a transfer it never made
+fn check_token_program(token_program: &AccountInfo) -> ProgramResult {
+    if token_program.key != &spl_token::id() {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    Ok(())
+}
+
+//rebuilds the [b"vault", owner] pda from its stored bump, it has to be the vault recorded at
+//init and the vault_authority account that was passed in
+fn check_vault_authority(
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    vault_pda: &Pubkey,
+    vault_bump: u8,
+    vault_authority: &AccountInfo,
+) -> ProgramResult {
+    let derived =
+        Pubkey::create_program_address(&[b"vault", owner.as_ref(), &[vault_bump]], program_id)
+            .map_err(|_| ProgramError::InvalidSeeds)?;
+
+    if &derived != vault_pda || vault_authority.key != vault_pda {
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    Ok(())
+}
+
+//moves `amount` out of a vault token account owned by the [b"vault", owner_account] pda,
+//vault_authority has to be checked against the stored bump first
+fn transfer_from_vault<'a>(
+    owner_account: &AccountInfo<'a>,
+    vault_bump: u8,
+    vault_authority: &AccountInfo<'a>,
+    token_program: &AccountInfo<'a>,
+    vault_token_account: &AccountInfo<'a>,
+    destination_account: &AccountInfo<'a>,
+    amount: u64,
+) -> ProgramResult {
+    let transfer_ix = spl_token::instruction::transfer(
+        token_program.key,
+        vault_token_account.key,
+        destination_account.key,
+        vault_authority.key,
+        &[],
+        amount,
+    )?;
+
+    invoke_signed(
+        &transfer_ix,
+        &[
+            vault_token_account.clone(),
+            destination_account.clone(),
+            vault_authority.clone(),
+            token_program.clone(),
+        ],
+        &[&[b"vault", owner_account.key.as_ref(), &[vault_bump]]],
+    )
+}
+
+//creates one of our pdas at the payer's expense. anyone can send lamports to a pda address
+//beforehand and create_account refuses an account with lamports, so such a pda is topped up
+//to rent exemption and then allocated and assigned instead
+fn create_pda<'a>(
+    program_id: &Pubkey,
+    payer: &AccountInfo<'a>,
+    pda_account: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    space: usize,
+    seeds: &[&[u8]],
+) -> ProgramResult {
+    let rent = Rent::get()?.minimum_balance(space);
+
+    if pda_account.lamports() == 0 {
+        return invoke_signed(
+            &system_instruction::create_account(
+                payer.key,
+                pda_account.key,
+                rent,
+                space as u64,
+                program_id,
+            ),
+            &[payer.clone(), pda_account.clone(), system_program.clone()],
+            &[seeds],
+        );
+    }
+
+    let top_up = rent.saturating_sub(pda_account.lamports());
+    if top_up > 0 {
+        invoke(
+            &system_instruction::transfer(payer.key, pda_account.key, top_up),
+            &[payer.clone(), pda_account.clone(), system_program.clone()],
+        )?;
+    }
+
+    invoke_signed(
+        &system_instruction::allocate(pda_account.key, space as u64),
+        &[pda_account.clone(), system_program.clone()],
+        &[seeds],
+    )?;
+    invoke_signed(
+        &system_instruction::assign(pda_account.key, program_id),
+        &[pda_account.clone(), system_program.clone()],
+        &[seeds],
+    )
+}
+
+//the [b"config"] pda must be among the accounts, an uninitialized config is not paused
+fn check_not_paused(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let (config_pda, _) = Pubkey::find_program_address(&[b"config"], program_id);
+
+    let config_account = accounts
+        .iter()
+        .find(|account| account.key == &config_pda)
+        .ok_or(ProgramError::NotEnoughAccountKeys)?;
+
+    if config_account.owner != program_id {
+        return Ok(());
+    }
+
+    if Config::try_from_slice(&config_account.data.borrow())?.paused {
+        return Err(EscrowError::ProgramPaused.into());
+    }
+
+    Ok(())
+}
+
+fn build_multisig(descriptor: MultisigDescriptor) -> Result<Multisig, ProgramError> {
+    let signer_count = descriptor.signers.len();
+
+    if signer_count == 0
+        || signer_count > MAX_MULTISIG_SIGNERS
+        || descriptor.threshold == 0
+        || descriptor.threshold as usize > signer_count
+    {
+        return Err(EscrowError::InvalidMultisig.into());
+    }
+
+    let mut multisig = Multisig {
+        threshold: descriptor.threshold,
+        ..Multisig::default()
+    };
+
+    for (index, signer) in descriptor.signers.iter().enumerate() {
+        if multisig.position(signer).is_some() {
+            return Err(EscrowError::InvalidMultisig.into());
+        }
+        multisig.signers[index] = *signer;
+        multisig.signer_count = index as u8 + 1;
+    }
+
+    Ok(multisig)
+}
+
+//true when `account` is an spl multisig whose signers include every escrow multisig signer,
+//so the escrow's signers cannot name someone else's account as user_a
+fn spl_multisig_lists(account: &AccountInfo, multisig: &Multisig) -> bool {
+    if account.owner != &spl_token::id() {
+        return false;
+    }
+    let Ok(spl_multisig) = spl_token::state::Multisig::unpack(&account.data.borrow()) else {
+        return false;
+    };
+    let spl_signers =
+        &spl_multisig.signers[..(spl_multisig.n as usize).min(spl_token::instruction::MAX_SIGNERS)];
+
+    multisig.signers[..multisig.signer_count as usize]
+        .iter()
+        .all(|signer| spl_signers.contains(signer))
+}
+
+//user_a signs directly, or the escrow's multisig approved exactly this action
+fn authorize_maker(
+    escrow: &mut Escrow,
+    user_a_account: &AccountInfo,
+    action: MakerAction,
+) -> ProgramResult {
+    if user_a_account.key != &escrow.user_a {
+        return Err(EscrowError::UnauthorizedCancel.into());
+    }
+
+    if !escrow.multisig.is_enabled() {
+        if !user_a_account.is_signer {
+            return Err(EscrowError::UnauthorizedCancel.into());
+        }
+        return Ok(());
+    }
+
+    if !escrow.multisig.consume(&action) {
+        return Err(EscrowError::MultisigThresholdNotMet.into());
+    }
+
+    Ok(())
+}
+
+//signer accounts from `start` on, they co-sign for a token account owned by an spl multisig
+fn spl_multisig_signers<'a>(accounts: &[AccountInfo<'a>], start: usize) -> Vec<AccountInfo<'a>> {
+    accounts
+        .iter()
+        .skip(start)
+        .filter(|account| account.is_signer)
+        .cloned()
+        .collect()
+}
+
+//accounts CompleteSwap pays out of, CompleteSwapBatch repeats them per escrow
+struct SettlementAccounts<'a, 'b> {
+    escrow_account: &'a AccountInfo<'b>,
+    vault_authority: &'a AccountInfo<'b>,
+    vault_token_a: &'a AccountInfo<'b>,
+    vault_token_b: &'a AccountInfo<'b>,
+    user_a_token_b_account: &'a AccountInfo<'b>,
+    user_b_token_a_account: &'a AccountInfo<'b>,
+    token_program: &'a AccountInfo<'b>,
+    //where the market account of a listed escrow is looked up
+    market_search: &'a [AccountInfo<'b>],
+    //where the owner, mint and programs needed to create a missing payout account are looked up
+    creation_search: &'a [AccountInfo<'b>],
+}
+
+fn check_settlement_ready(
+    program_id: &Pubkey,
+    escrow: &Escrow,
+    caller: &AccountInfo,
+    accounts: &SettlementAccounts,
+) -> ProgramResult {
+    if accounts.escrow_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    if escrow.settlement_locked {
+        return Err(EscrowError::SettlementLocked.into());
+    }
+
+    let is_multisig_signer = escrow.multisig.position(caller.key).is_some();
+    if caller.key != &escrow.user_a
+        && caller.key != &escrow.user_b
+        && !is_multisig_signer
+        && !escrow.permissionless_settlement
+    {
+        return Err(EscrowError::UnauthorizedCancel.into());
+    }
+
+    if !matches!(&escrow.status, EscrowStatus::Active) {
+        return Err(EscrowError::EscrowNotReady.into());
+    }
+
+    if accounts.vault_authority.key != &escrow.vault_pda {
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    //for approval escrows vault_token_a is user_a's delegated source account
+    let token_a_ready = match escrow.kind {
+        EscrowKind::Swap
+        | EscrowKind::EnglishAuction
+        | EscrowKind::DutchAuction
+        | EscrowKind::OraclePriced
+        | EscrowKind::Conditional => {
+            //the highest english bid only wins once bidding has closed
+            if escrow.kind == EscrowKind::EnglishAuction
+                && Clock::get()?.unix_timestamp < escrow.auction.end_time.get()
+            {
+                return Err(EscrowError::EscrowNotReady.into());
+            }
+            escrow.token_a_deposited
+                && holds_at_least(
+                    accounts.vault_token_a,
+                    &escrow.vault_pda,
+                    &escrow.token_a_mint,
+                    escrow.amount_a.get(),
+                )?
+        }
+        EscrowKind::Approval => delegation_covers(escrow, accounts.vault_token_a)?,
+        EscrowKind::HashLock => return Err(EscrowError::InvalidEscrowKind.into()),
+    };
+
+    let token_b_ready = escrow.token_b_deposited
+        && holds_at_least(
+            accounts.vault_token_b,
+            &escrow.vault_pda,
+            &escrow.token_b_mint,
+            escrow.amount_b.get(),
+        )?;
+
+    if !token_a_ready || !token_b_ready {
+        return Err(EscrowError::EscrowNotReady.into());
+    }
+
+    if escrow.listed && find_market(program_id, escrow, accounts.market_search).is_none() {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    }
+
+    if escrow.kind == EscrowKind::Conditional {
+        let condition_program = accounts
+            .market_search
+            .iter()
+            .find(|account| account.key == &escrow.condition.program)
+            .ok_or(ProgramError::NotEnoughAccountKeys)?;
+        if !condition_program.executable {
+            return Err(EscrowError::InvalidCondition.into());
+        }
+    }
+
+    check_payout_account(
+        accounts.user_a_token_b_account,
+        &escrow.user_a,
+        &escrow.token_b_mint,
+        accounts,
+    )?;
+    check_payout_account(
+        accounts.user_b_token_a_account,
+        &escrow.user_b,
+        &escrow.token_a_mint,
+        accounts,
+    )
+}
+
+//settlement only pays into the canonical associated token account of `owner` for `mint`.
+//one that does not exist yet is created by settle_escrow, so everything it takes must be there
+fn check_payout_account(
+    payout_account: &AccountInfo,
+    owner: &Pubkey,
+    mint: &Pubkey,
+    accounts: &SettlementAccounts,
+) -> ProgramResult {
+    let expected =
+        get_associated_token_address_with_program_id(owner, mint, accounts.token_program.key);
+    if payout_account.key != &expected {
+        return Err(EscrowError::InvalidRecipient.into());
+    }
+
+    if payout_account.data_is_empty() {
+        return payout_creation_accounts(owner, mint, accounts).map(|_| ());
+    }
+
+    check_recipient(payout_account, owner, mint)
+}
+
+//owner, mint, system program and associated token program, in that order
+fn payout_creation_accounts<'a, 'b>(
+    owner: &Pubkey,
+    mint: &Pubkey,
+    accounts: &SettlementAccounts<'a, 'b>,
+) -> Result<[&'a AccountInfo<'b>; 4], ProgramError> {
+    let find = |key: &Pubkey| {
+        accounts
+            .creation_search
+            .iter()
+            .find(|account| account.key == key)
+            .ok_or(ProgramError::NotEnoughAccountKeys)
+    };
+
+    Ok([
+        find(owner)?,
+        find(mint)?,
+        find(&solana_system_interface::program::ID)?,
+        find(&spl_associated_token_account_client::program::ID)?,
+    ])
+}
+
+//the caller pays the rent of a payout account its recipient never opened
+fn create_payout_account<'b>(
+    caller: &AccountInfo<'b>,
+    payout_account: &AccountInfo<'b>,
+    owner: &Pubkey,
+    mint: &Pubkey,
+    accounts: &SettlementAccounts<'_, 'b>,
+) -> ProgramResult {
+    if !payout_account.data_is_empty() {
+        return Ok(());
+    }
+
+    let [owner_account, mint_account, system_program, ata_program] =
+        payout_creation_accounts(owner, mint, accounts)?;
+
+    invoke(
+        &create_associated_token_account_idempotent(
+            caller.key,
+            owner,
+            mint,
+            accounts.token_program.key,
+        ),
+        &[
+            caller.clone(),
+            payout_account.clone(),
+            owner_account.clone(),
+            mint_account.clone(),
+            system_program.clone(),
+            accounts.token_program.clone(),
+            ata_program.clone(),
+        ],
+    )?;
+
+    msg!("Created payout account {}", payout_account.key);
+
+    Ok(())
+}
+
+//payouts and refunds only go to a spl token account of `mint` owned by `owner`
+fn check_recipient(token_account: &AccountInfo, owner: &Pubkey, mint: &Pubkey) -> ProgramResult {
+    if token_account.owner != &spl_token::id() {
+        return Err(EscrowError::InvalidRecipient.into());
+    }
+    let account = TokenAccount::unpack(&token_account.data.borrow())?;
+    if &account.owner != owner || &account.mint != mint {
+        return Err(EscrowError::InvalidRecipient.into());
+    }
+    Ok(())
+}
+
+//user_a's delegated source lets the vault authority take amount_a
+fn delegation_covers(escrow: &Escrow, source_account: &AccountInfo) -> Result<bool, ProgramError> {
+    if source_account.key != &escrow.delegated_source || source_account.owner != &spl_token::id() {
+        return Err(EscrowError::InvalidTokenAccount.into());
+    }
+    let source = TokenAccount::unpack(&source_account.data.borrow())?;
+    Ok(source.owner == escrow.user_a
+        && source.mint == escrow.token_a_mint
+        && source.delegate.contains(&escrow.vault_pda)
+        && source.delegated_amount >= escrow.amount_a.get()
+        && source.amount >= escrow.amount_a.get())
+}
+
+//spl token account of `mint` owned by `owner` holding at least `amount`
+fn holds_at_least(
+    token_account: &AccountInfo,
+    owner: &Pubkey,
+    mint: &Pubkey,
+    amount: u64,
+) -> Result<bool, ProgramError> {
+    if token_account.owner != &spl_token::id() {
+        return Err(EscrowError::InvalidTokenAccount.into());
+    }
+    let account = TokenAccount::unpack(&token_account.data.borrow())?;
+    if &account.owner != owner || &account.mint != mint {
+        return Err(EscrowError::InvalidTokenAccount.into());
+    }
+    Ok(account.amount >= amount)
+}
+
+//asks the condition program whether the escrow may settle, it gets the escrow and the caller
+//followed by every account passed after it. signatures are never forwarded and a failing
+//condition fails the whole transaction, a batch included
+fn invoke_condition<'b>(
+    condition: &Condition,
+    caller: &AccountInfo<'b>,
+    accounts: &SettlementAccounts<'_, 'b>,
+) -> ProgramResult {
+    let position = accounts
+        .market_search
+        .iter()
+        .position(|account| account.key == &condition.program)
+        .ok_or(ProgramError::NotEnoughAccountKeys)?;
+    let condition_accounts = &accounts.market_search[position + 1..];
+
+    let mut metas = vec![
+        AccountMeta::new_readonly(*accounts.escrow_account.key, false),
+        AccountMeta::new_readonly(*caller.key, false),
+    ];
+    metas.extend(condition_accounts.iter().map(|account| {
+        if account.is_writable {
+            AccountMeta::new(*account.key, false)
+        } else {
+            AccountMeta::new_readonly(*account.key, false)
+        }
+    }));
+
+    let condition_ix = Instruction {
+        program_id: condition.program,
+        accounts: metas,
+        data: condition.data().to_vec(),
+    };
+
+    let mut infos = vec![
+        accounts.escrow_account.clone(),
+        caller.clone(),
+        accounts.market_search[position].clone(),
+    ];
+    infos.extend(condition_accounts.iter().cloned());
+
+    invoke(&condition_ix, &infos)?;
+
+    msg!("Condition {} accepted the settlement", condition.program);
+
+    Ok(())
+}
+
+fn reference_hex(reference: &[u8; 32]) -> String {
+    reference.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+//every escrow event is followed by the escrow's reference so logs can be matched to trades
+fn log_reference(escrow: &Escrow) {
+    if escrow.reference != [0; 32] {
+        msg!("Reference: {}", reference_hex(&escrow.reference));
+    }
+}
+
+//puts the reference next to the settlement transfers for explorers and indexers
+fn write_settlement_memo<'b>(
+    escrow: &Escrow,
+    escrow_account: &AccountInfo<'b>,
+    memo_program: &AccountInfo<'b>,
+) -> ProgramResult {
+    if escrow.reference == [0; 32] {
+        return Ok(());
+    }
+
+    let memo = format!(
+        "escrow {} ref {}",
+        escrow_account.key,
+        reference_hex(&escrow.reference)
+    );
+
+    invoke(
+        &spl_memo::build_memo(memo.as_bytes(), &[]),
+        std::slice::from_ref(memo_program),
+    )
+}
+
+//pays both sides out of the vault, tips the caller and marks the escrow completed. the
+//caller must not hold a borrow of the escrow data, the condition program reads it
+fn settle_escrow<'b>(
+    program_id: &Pubkey,
+    caller: &AccountInfo<'b>,
+    accounts: &SettlementAccounts<'_, 'b>,
+) -> ProgramResult {
+    let escrow_account = accounts.escrow_account;
+
+    check_token_program(accounts.token_program)?;
+
+    //the condition program is the first cpi, it already runs under the lock
+    let condition = {
+        let mut escrow_data = escrow_account.data.borrow_mut();
+        let escrow = Escrow::load_mut(&mut escrow_data)?;
+        check_vault_authority(
+            program_id,
+            escrow_account.key,
+            &escrow.vault_pda,
+            escrow.vault_bump,
+            accounts.vault_authority,
+        )?;
+        lock_settlement(escrow)?;
+        (escrow.kind == EscrowKind::Conditional).then_some(escrow.condition)
+    };
+
+    if let Some(condition) = condition {
+        invoke_condition(&condition, caller, accounts)?;
+    }
+
+    let mut escrow_data = escrow_account.data.borrow_mut();
+    let escrow = Escrow::load_mut(&mut escrow_data)?;
+    let (vault_pda, vault_bump) = (escrow.vault_pda, escrow.vault_bump);
+
+    create_payout_account(
+        caller,
+        accounts.user_b_token_a_account,
+        &escrow.user_b,
+        &escrow.token_a_mint,
+        accounts,
+    )?;
+    create_payout_account(
+        caller,
+        accounts.user_a_token_b_account,
+        &escrow.user_a,
+        &escrow.token_b_mint,
+        accounts,
+    )?;
+
+    let transfer_a_ix = spl_token::instruction::transfer(
+        accounts.token_program.key,
+        accounts.vault_token_a.key,
+        accounts.user_b_token_a_account.key,
+        &vault_pda,
+        &[],
+        escrow.amount_a.get(),
+    )?;
+
+    let transfer_b_ix = spl_token::instruction::transfer(
+        accounts.token_program.key,
+        accounts.vault_token_b.key,
+        accounts.user_a_token_b_account.key,
+        &vault_pda,
+        &[],
+        escrow.amount_b.get(),
+    )?;
+
+    let seeds: &[&[u8]] = &[b"vault", escrow_account.key.as_ref(), &[vault_bump]];
+    let signer_seeds = &[seeds];
+
+    invoke_signed(
+        &transfer_a_ix,
+        &[
+            accounts.vault_token_a.clone(),
+            accounts.user_b_token_a_account.clone(),
+            accounts.vault_authority.clone(),
+            accounts.token_program.clone(),
+        ],
+        signer_seeds,
+    )?;
+
+    invoke_signed(
+        &transfer_b_ix,
+        &[
+            accounts.vault_token_b.clone(),
+            accounts.user_a_token_b_account.clone(),
+            accounts.vault_authority.clone(),
+            accounts.token_program.clone(),
+        ],
+        signer_seeds,
+    )?;
+
+    release_keeper_tip(escrow, escrow_account, caller)?;
+
+    delist_from_market(
+        program_id,
+        escrow_account.key,
+        escrow,
+        accounts.market_search,
+    )?;
+
+    if let Some(memo_program) = accounts
+        .market_search
+        .iter()
+        .find(|account| account.key == &spl_memo::id())
+    {
+        write_settlement_memo(escrow, escrow_account, memo_program)?;
+    }
+
+    //the vault is empty again, nothing may be settled or refunded from it
+    escrow.token_a_deposited = false;
+    escrow.token_b_deposited = false;
+    escrow.status = EscrowStatus::Completed;
+
+    msg!("Escrow settled! {}", escrow_account.key);
+    log_reference(escrow);
+
+    Ok(())
+}
+
+//takes the escrow's one-shot payout lock once every check has passed, `escrow` is the
+//account data itself so the lock is in place before any transfer and the instruction cannot
+//run again for this escrow, nor be reentered through a cpi
+fn lock_settlement(escrow: &mut Escrow) -> ProgramResult {
+    if escrow.settlement_locked {
+        return Err(EscrowError::SettlementLocked.into());
+    }
+
+    escrow.settlement_locked = true;
+    Ok(())
+}
+
+//moves the tip lamports off the escrow account, it is program owned so no cpi is needed
+fn release_keeper_tip(
+    escrow: &mut Escrow,
+    escrow_account: &AccountInfo,
+    recipient: &AccountInfo,
+) -> ProgramResult {
+    if escrow.keeper_tip.get() == 0 {
+        return Ok(());
+    }
+
+    **escrow_account.try_borrow_mut_lamports()? =
+        math::sub(escrow_account.lamports(), escrow.keeper_tip.get())?;
+    **recipient.try_borrow_mut_lamports()? =
+        math::add(recipient.lamports(), escrow.keeper_tip.get())?;
+
+    escrow.keeper_tip = 0.into();
+
+    Ok(())
+}
+
+//terms can only change on an active swap before the taker has deposited
+fn check_terms_open(escrow: &Escrow) -> ProgramResult {
+    if !matches!(&escrow.status, EscrowStatus::Active) {
+        return Err(EscrowError::EscrowNotReady.into());
+    }
+
+    let negotiable = matches!(escrow.kind, EscrowKind::Swap | EscrowKind::Approval);
+    if !negotiable || escrow.token_b_deposited {
+        return Err(EscrowError::TermsLocked.into());
+    }
+
+    Ok(())
+}
+
+//moves the amount_a difference between user_a and the vault when user_a already deposited
+//accounts: user_a, escrow, vault_authority, vault_token_a, user_a_token_a, token_program
+fn rebalance_token_a_deposit(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    escrow: &Escrow,
+    new_amount_a: u64,
+) -> ProgramResult {
+    if !escrow.token_a_deposited || new_amount_a == escrow.amount_a.get() {
+        return Ok(());
+    }
+
+    let user_a_account = account_at(accounts, 0)?;
+    let escrow_account = account_at(accounts, 1)?;
+    let vault_authority = account_at(accounts, 2)?;
+    let vault_token_a = account_at(accounts, 3)?;
+    let user_a_token_a_account = account_at(accounts, 4)?;
+    let token_program = account_at(accounts, 5)?;
+
+    check_token_program(token_program)?;
+
+    //a top up has to land in the vault, and a refund can only go back to user_a
+    let vault_token = TokenAccount::unpack(&vault_token_a.data.borrow())?;
+    if vault_token.owner != escrow.vault_pda || vault_token.mint != escrow.token_a_mint {
+        return Err(EscrowError::InvalidTokenAccount.into());
+    }
+    check_recipient(user_a_token_a_account, &escrow.user_a, &escrow.token_a_mint)?;
+
+    if new_amount_a > escrow.amount_a.get() {
+        let spl_signers = spl_multisig_signers(accounts, 6);
+        let spl_signer_keys: Vec<&Pubkey> = spl_signers.iter().map(|signer| signer.key).collect();
+
+        let top_up_ix = spl_token::instruction::transfer(
+            token_program.key,
+            user_a_token_a_account.key,
+            vault_token_a.key,
+            user_a_account.key,
+            &spl_signer_keys,
+            math::sub(new_amount_a, escrow.amount_a.get())?,
+        )?;
+
+        let mut top_up_accounts = vec![
+            user_a_token_a_account.clone(),
+            vault_token_a.clone(),
+            user_a_account.clone(),
+            token_program.clone(),
+        ];
+        top_up_accounts.extend(spl_signers.iter().cloned());
+
+        invoke(&top_up_ix, &top_up_accounts)?;
+    } else {
+        check_vault_authority(
+            program_id,
+            escrow_account.key,
+            &escrow.vault_pda,
+            escrow.vault_bump,
+            vault_authority,
+        )?;
+        let (vault_pda, vault_bump) = (escrow.vault_pda, escrow.vault_bump);
+
+        let refund_ix = spl_token::instruction::transfer(
+            token_program.key,
+            vault_token_a.key,
+            user_a_token_a_account.key,
+            &vault_pda,
+            &[],
+            math::sub(escrow.amount_a.get(), new_amount_a)?,
+        )?;
+
+        let seeds: &[&[u8]] = &[b"vault", escrow_account.key.as_ref(), &[vault_bump]];
+
+        invoke_signed(
+            &refund_ix,
+            &[
+                vault_token_a.clone(),
+                user_a_token_a_account.clone(),
+                vault_authority.clone(),
+                token_program.clone(),
+            ],
+            &[seeds],
+        )?;
     }
 
     Ok(())
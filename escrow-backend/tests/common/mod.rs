@@ -0,0 +1,781 @@
+//fixtures shared by the integration tests, every test binary pulls them in with `mod common;`
+//and only uses some of them
+#![allow(dead_code)]
+
+use borsh::BorshDeserialize;
+use solana_escrow::{Escrow, instructions::EscrowInstruction, processor::process_instruction};
+use solana_program::program_pack::Pack;
+use solana_program::{
+    hash::Hash,
+    instruction::{AccountMeta, Instruction},
+};
+use solana_program_test::*;
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::Keypair,
+    signer::Signer,
+    transaction::{Transaction, TransactionError},
+};
+use solana_system_interface::instruction as system_instruction;
+use spl_token::state::{Account as TokenAccount, Mint};
+
+pub fn config_pda(program_id: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"config"], program_id).0
+}
+
+pub fn vault_pda(program_id: &Pubkey, escrow_account: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"vault", escrow_account.as_ref()], program_id).0
+}
+
+pub async fn process(
+    banks_client: &BanksClient,
+    payer: &Keypair,
+    recent_blockhash: Hash,
+    instructions: &[Instruction],
+    signers: &[&Keypair],
+) -> Result<(), BanksClientError> {
+    let mut all_signers = vec![payer];
+    all_signers.extend_from_slice(signers);
+
+    let transaction = Transaction::new_signed_with_payer(
+        instructions,
+        Some(&payer.pubkey()),
+        all_signers.as_slice(),
+        recent_blockhash,
+    );
+
+    banks_client.process_transaction(transaction).await
+}
+
+//runs `transaction` right away for its logs and compute units. the banks server reports a
+//transaction processed before it drops that transaction's account locks, so a run straight
+//after one can fail with AccountInUse without ever executing, that is retried
+pub async fn process_with_metadata(
+    banks_client: &BanksClient,
+    transaction: Transaction,
+) -> BanksTransactionResultWithMetadata {
+    for _ in 0..100 {
+        let result = banks_client
+            .process_transaction_with_metadata(transaction.clone())
+            .await
+            .unwrap();
+        if result.result != Err(TransactionError::AccountInUse) {
+            return result;
+        }
+        tokio::task::yield_now().await;
+    }
+    panic!("accounts stayed locked by an earlier transaction");
+}
+
+pub async fn create_mint(
+    banks_client: &BanksClient,
+    payer: &Keypair,
+    recent_blockhash: Hash,
+) -> Pubkey {
+    let mint = Keypair::new();
+    let rent = banks_client.get_rent().await.unwrap();
+
+    let create_mint_ix = system_instruction::create_account(
+        &payer.pubkey(),
+        &mint.pubkey(),
+        rent.minimum_balance(Mint::LEN),
+        Mint::LEN as u64,
+        &spl_token::id(),
+    );
+
+    let init_mint_ix = spl_token::instruction::initialize_mint(
+        &spl_token::id(),
+        &mint.pubkey(),
+        &payer.pubkey(),
+        None,
+        9,
+    )
+    .unwrap();
+
+    process(
+        banks_client,
+        payer,
+        recent_blockhash,
+        &[create_mint_ix, init_mint_ix],
+        &[&mint],
+    )
+    .await
+    .unwrap();
+
+    mint.pubkey()
+}
+
+//creates the owner's ata and mints `amount` into it
+pub async fn create_funded_ata(
+    banks_client: &BanksClient,
+    payer: &Keypair,
+    recent_blockhash: Hash,
+    owner: &Pubkey,
+    mint: &Pubkey,
+    amount: u64,
+) -> Pubkey {
+    let ata = spl_associated_token_account::get_associated_token_address(owner, mint);
+
+    let mut instructions = vec![
+        spl_associated_token_account::instruction::create_associated_token_account_idempotent(
+            &payer.pubkey(),
+            owner,
+            mint,
+            &spl_token::id(),
+        ),
+    ];
+
+    if amount > 0 {
+        instructions.push(
+            spl_token::instruction::mint_to(
+                &spl_token::id(),
+                mint,
+                &ata,
+                &payer.pubkey(),
+                &[],
+                amount,
+            )
+            .unwrap(),
+        );
+    }
+
+    process(banks_client, payer, recent_blockhash, &instructions, &[])
+        .await
+        .unwrap();
+
+    ata
+}
+
+pub async fn token_balance(banks_client: &BanksClient, token_account: Pubkey) -> u64 {
+    let account = banks_client
+        .get_account(token_account)
+        .await
+        .unwrap()
+        .unwrap();
+
+    TokenAccount::unpack(&account.data).unwrap().amount
+}
+
+pub async fn mint_supply(banks_client: &BanksClient, mint: Pubkey) -> u64 {
+    let account = banks_client.get_account(mint).await.unwrap().unwrap();
+
+    Mint::unpack(&account.data).unwrap().supply
+}
+
+pub async fn read_escrow(banks_client: &BanksClient, escrow_account: Pubkey) -> Escrow {
+    let account = banks_client
+        .get_account(escrow_account)
+        .await
+        .unwrap()
+        .unwrap();
+
+    Escrow::try_from_slice(&account.data).unwrap()
+}
+
+//InitEscrow of a plain swap, amount_a token a against amount_b token b
+pub fn swap_terms(
+    token_a_mint: Pubkey,
+    token_b_mint: Pubkey,
+    amount_a: u64,
+    amount_b: u64,
+) -> EscrowInstruction {
+    EscrowInstruction::InitEscrow {
+        token_a_mint,
+        token_b_mint,
+        amount_a,
+        amount_b,
+        hash_lock: None,
+        delegated_source: None,
+        maker_multisig: None,
+        permissionless_settlement: false,
+        keeper_tip: 0,
+        auction: None,
+        oracle: None,
+        condition: None,
+        credential_issuer: None,
+        reference: None,
+    }
+}
+
+pub fn init_escrow_ix(
+    program_id: Pubkey,
+    user_a: Pubkey,
+    escrow_account: Pubkey,
+    init_ix: &EscrowInstruction,
+) -> Instruction {
+    Instruction::new_with_borsh(
+        program_id,
+        init_ix,
+        vec![
+            AccountMeta::new(user_a, true),
+            AccountMeta::new(escrow_account, false),
+            AccountMeta::new_readonly(solana_program::sysvar::rent::id(), false),
+            AccountMeta::new_readonly(config_pda(&program_id), false),
+        ],
+    )
+}
+
+//allocates a program owned escrow account, rent exempt for the escrow layout
+pub fn create_escrow_account_ix(
+    payer: &Pubkey,
+    escrow_account: &Pubkey,
+    program_id: &Pubkey,
+    rent: &solana_program::rent::Rent,
+) -> Instruction {
+    let escrow_size = std::mem::size_of::<Escrow>();
+
+    system_instruction::create_account(
+        payer,
+        escrow_account,
+        rent.minimum_balance(escrow_size),
+        escrow_size as u64,
+        program_id,
+    )
+}
+
+//creates and initializes an escrow account owned by the program
+pub async fn init_escrow(
+    banks_client: &BanksClient,
+    payer: &Keypair,
+    recent_blockhash: Hash,
+    program_id: Pubkey,
+    user_a: &Keypair,
+    init_ix: &EscrowInstruction,
+) -> Pubkey {
+    let escrow_account = Keypair::new();
+    let rent = banks_client.get_rent().await.unwrap();
+
+    process(
+        banks_client,
+        payer,
+        recent_blockhash,
+        &[
+            create_escrow_account_ix(
+                &payer.pubkey(),
+                &escrow_account.pubkey(),
+                &program_id,
+                &rent,
+            ),
+            init_escrow_ix(
+                program_id,
+                user_a.pubkey(),
+                escrow_account.pubkey(),
+                init_ix,
+            ),
+        ],
+        &[user_a, &escrow_account],
+    )
+    .await
+    .unwrap();
+
+    escrow_account.pubkey()
+}
+
+//a deposit that takes whatever amount_a the escrow asks for, see with_expected_terms
+pub fn deposit_ix(
+    program_id: Pubkey,
+    depositor: Pubkey,
+    escrow_account: Pubkey,
+    depositor_token_account: Pubkey,
+    vault_token_account: Pubkey,
+    amount: u64,
+    expected_mint_a: Pubkey,
+) -> Instruction {
+    Instruction::new_with_borsh(
+        program_id,
+        &EscrowInstruction::Deposit {
+            amount,
+            expected_amount_a_min: 0,
+            expected_mint_a,
+            settle: false,
+        },
+        vec![
+            AccountMeta::new(depositor, true),
+            AccountMeta::new(escrow_account, false),
+            AccountMeta::new(depositor_token_account, false),
+            AccountMeta::new(vault_token_account, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(config_pda(&program_id), false),
+        ],
+    )
+}
+
+//replaces the depositor's view of the terms in a deposit instruction
+pub fn with_expected_terms(
+    mut deposit_ix: Instruction,
+    expected_mint_a: Pubkey,
+    expected_amount_a_min: u64,
+) -> Instruction {
+    let EscrowInstruction::Deposit { amount, settle, .. } =
+        EscrowInstruction::try_from_slice(&deposit_ix.data).unwrap()
+    else {
+        panic!("not a deposit instruction");
+    };
+
+    deposit_ix.data = borsh::to_vec(&EscrowInstruction::Deposit {
+        amount,
+        expected_amount_a_min,
+        expected_mint_a,
+        settle,
+    })
+    .unwrap();
+
+    deposit_ix
+}
+
+//makes a deposit settle the swap when it completes it, the payout accounts are
+//vault_authority, the other side's vault token account, user_a_token_b and user_b_token_a
+pub fn with_settlement(mut deposit_ix: Instruction, payout_accounts: [Pubkey; 4]) -> Instruction {
+    let EscrowInstruction::Deposit {
+        amount,
+        expected_amount_a_min,
+        expected_mint_a,
+        ..
+    } = EscrowInstruction::try_from_slice(&deposit_ix.data).unwrap()
+    else {
+        panic!("not a deposit instruction");
+    };
+
+    deposit_ix.data = borsh::to_vec(&EscrowInstruction::Deposit {
+        amount,
+        expected_amount_a_min,
+        expected_mint_a,
+        settle: true,
+    })
+    .unwrap();
+
+    //right after the config account
+    let [vault_authority, other_vault, user_a_token_b, user_b_token_a] = payout_accounts;
+    deposit_ix.accounts.splice(
+        6..6,
+        [
+            AccountMeta::new_readonly(vault_authority, false),
+            AccountMeta::new(other_vault, false),
+            AccountMeta::new(user_a_token_b, false),
+            AccountMeta::new(user_b_token_a, false),
+        ],
+    );
+
+    deposit_ix
+}
+
+//passes what settlement needs to open the payout accounts of `owners` that do not exist yet
+pub fn with_payout_creation(
+    mut settlement_ix: Instruction,
+    owners: &[Pubkey],
+    mints: &[Pubkey],
+) -> Instruction {
+    settlement_ix.accounts.extend(
+        owners
+            .iter()
+            .chain(mints)
+            .chain([
+                &solana_system_interface::program::ID,
+                &spl_associated_token_account::id(),
+            ])
+            .map(|key| AccountMeta::new_readonly(*key, false)),
+    );
+
+    settlement_ix
+}
+
+//CompleteSwap and Cancel take the same accounts
+fn settlement_ix(
+    program_id: Pubkey,
+    instruction: &EscrowInstruction,
+    caller: Pubkey,
+    escrow_account: Pubkey,
+    vault_token_accounts: (Pubkey, Pubkey),
+    user_token_accounts: (Pubkey, Pubkey),
+) -> Instruction {
+    Instruction::new_with_borsh(
+        program_id,
+        instruction,
+        vec![
+            AccountMeta::new(caller, true),
+            AccountMeta::new(escrow_account, false),
+            AccountMeta::new(vault_pda(&program_id, &escrow_account), false),
+            AccountMeta::new(vault_token_accounts.0, false),
+            AccountMeta::new(vault_token_accounts.1, false),
+            AccountMeta::new(user_token_accounts.0, false),
+            AccountMeta::new(user_token_accounts.1, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+        ],
+    )
+}
+
+pub fn cancel_ix(
+    program_id: Pubkey,
+    caller: Pubkey,
+    escrow_account: Pubkey,
+    vault_token_a: Pubkey,
+    vault_token_b: Pubkey,
+    user_a_token_account: Pubkey,
+    user_b_token_account: Pubkey,
+) -> Instruction {
+    settlement_ix(
+        program_id,
+        &EscrowInstruction::Cancel,
+        caller,
+        escrow_account,
+        (vault_token_a, vault_token_b),
+        (user_a_token_account, user_b_token_account),
+    )
+}
+
+pub fn complete_swap_ix(
+    program_id: Pubkey,
+    caller: Pubkey,
+    escrow_account: Pubkey,
+    vault_token_a: Pubkey,
+    vault_token_b: Pubkey,
+    user_a_token_b_account: Pubkey,
+    user_b_token_a_account: Pubkey,
+) -> Instruction {
+    settlement_ix(
+        program_id,
+        &EscrowInstruction::CompleteSwap,
+        caller,
+        escrow_account,
+        (vault_token_a, vault_token_b),
+        (user_a_token_b_account, user_b_token_a_account),
+    )
+}
+
+//a wallet with an ata for both of the fixture's mints
+pub struct User {
+    pub keypair: Keypair,
+    pub token_a: Pubkey,
+    pub token_b: Pubkey,
+}
+
+impl User {
+    pub fn pubkey(&self) -> Pubkey {
+        self.keypair.pubkey()
+    }
+}
+
+//an initialized escrow and its vault's token accounts
+pub struct FixtureEscrow {
+    pub escrow_account: Pubkey,
+    pub vault_pda: Pubkey,
+    pub vault_token_a: Pubkey,
+    pub vault_token_b: Pubkey,
+}
+
+//a new wallet holding `amount_a` token a and `amount_b` token b
+pub async fn create_user(
+    banks_client: &BanksClient,
+    payer: &Keypair,
+    recent_blockhash: Hash,
+    token_a_mint: &Pubkey,
+    token_b_mint: &Pubkey,
+    amount_a: u64,
+    amount_b: u64,
+) -> User {
+    let keypair = Keypair::new();
+    let owner = keypair.pubkey();
+
+    User {
+        keypair,
+        token_a: create_funded_ata(
+            banks_client,
+            payer,
+            recent_blockhash,
+            &owner,
+            token_a_mint,
+            amount_a,
+        )
+        .await,
+        token_b: create_funded_ata(
+            banks_client,
+            payer,
+            recent_blockhash,
+            &owner,
+            token_b_mint,
+            amount_b,
+        )
+        .await,
+    }
+}
+
+//a running program with two mints, a maker (user_a), a taker (user_b) and a stranger who
+//may take the swap in user_b's place
+pub struct SwapFixture {
+    pub banks_client: BanksClient,
+    pub payer: Keypair,
+    pub recent_blockhash: Hash,
+    pub program_id: Pubkey,
+    pub token_a_mint: Pubkey,
+    pub token_b_mint: Pubkey,
+    pub user_a: User,
+    pub user_b: User,
+    pub stranger: User,
+}
+
+impl SwapFixture {
+    //natively processed program, user_a holds `funds_a` token a, user_b and the stranger
+    //`funds_b` token b each
+    pub async fn new(funds_a: u64, funds_b: u64) -> Self {
+        let program_id = Pubkey::new_unique();
+        let program_test =
+            ProgramTest::new("solana_escrow", program_id, processor!(process_instruction));
+
+        Self::start(program_test, program_id, funds_a, funds_b).await
+    }
+
+    pub async fn start(
+        program_test: ProgramTest,
+        program_id: Pubkey,
+        funds_a: u64,
+        funds_b: u64,
+    ) -> Self {
+        let (banks_client, payer, recent_blockhash) = program_test.start().await;
+
+        Self::setup(
+            banks_client,
+            payer,
+            recent_blockhash,
+            program_id,
+            funds_a,
+            funds_b,
+        )
+        .await
+    }
+
+    //start for tests that move the clock, the context shares the fixture's bank
+    pub async fn start_with_context(
+        program_test: ProgramTest,
+        program_id: Pubkey,
+        funds_a: u64,
+        funds_b: u64,
+    ) -> (Self, ProgramTestContext) {
+        let context = program_test.start_with_context().await;
+
+        let fixture = Self::setup(
+            context.banks_client.clone(),
+            context.payer.insecure_clone(),
+            context.last_blockhash,
+            program_id,
+            funds_a,
+            funds_b,
+        )
+        .await;
+
+        (fixture, context)
+    }
+
+    async fn setup(
+        banks_client: BanksClient,
+        payer: Keypair,
+        recent_blockhash: Hash,
+        program_id: Pubkey,
+        funds_a: u64,
+        funds_b: u64,
+    ) -> Self {
+        let token_a_mint = create_mint(&banks_client, &payer, recent_blockhash).await;
+        let token_b_mint = create_mint(&banks_client, &payer, recent_blockhash).await;
+
+        let user_a = create_user(
+            &banks_client,
+            &payer,
+            recent_blockhash,
+            &token_a_mint,
+            &token_b_mint,
+            funds_a,
+            0,
+        )
+        .await;
+        let user_b = create_user(
+            &banks_client,
+            &payer,
+            recent_blockhash,
+            &token_a_mint,
+            &token_b_mint,
+            0,
+            funds_b,
+        )
+        .await;
+        let stranger = create_user(
+            &banks_client,
+            &payer,
+            recent_blockhash,
+            &token_a_mint,
+            &token_b_mint,
+            0,
+            funds_b,
+        )
+        .await;
+
+        SwapFixture {
+            banks_client,
+            payer,
+            recent_blockhash,
+            program_id,
+            token_a_mint,
+            token_b_mint,
+            user_a,
+            user_b,
+            stranger,
+        }
+    }
+
+    //another wallet with atas for both mints
+    pub async fn create_user(&self, amount_a: u64, amount_b: u64) -> User {
+        create_user(
+            &self.banks_client,
+            &self.payer,
+            self.recent_blockhash,
+            &self.token_a_mint,
+            &self.token_b_mint,
+            amount_a,
+            amount_b,
+        )
+        .await
+    }
+
+    pub async fn create_ata(&self, owner: &Pubkey, mint: &Pubkey, amount: u64) -> Pubkey {
+        create_funded_ata(
+            &self.banks_client,
+            &self.payer,
+            self.recent_blockhash,
+            owner,
+            mint,
+            amount,
+        )
+        .await
+    }
+
+    //initializes user_a's escrow from `init_ix` and opens both vault token accounts
+    pub async fn open_escrow(&self, init_ix: &EscrowInstruction) -> FixtureEscrow {
+        self.open_escrow_as(&self.user_a, init_ix).await
+    }
+
+    //open_escrow with another wallet as the maker
+    pub async fn open_escrow_as(&self, maker: &User, init_ix: &EscrowInstruction) -> FixtureEscrow {
+        let escrow_account = init_escrow(
+            &self.banks_client,
+            &self.payer,
+            self.recent_blockhash,
+            self.program_id,
+            &maker.keypair,
+            init_ix,
+        )
+        .await;
+
+        self.open_vault(escrow_account).await
+    }
+
+    pub async fn open_swap(&self, amount_a: u64, amount_b: u64) -> FixtureEscrow {
+        self.open_escrow(&swap_terms(
+            self.token_a_mint,
+            self.token_b_mint,
+            amount_a,
+            amount_b,
+        ))
+        .await
+    }
+
+    pub async fn open_vault(&self, escrow_account: Pubkey) -> FixtureEscrow {
+        let vault_pda = vault_pda(&self.program_id, &escrow_account);
+
+        FixtureEscrow {
+            escrow_account,
+            vault_pda,
+            vault_token_a: self.create_ata(&vault_pda, &self.token_a_mint, 0).await,
+            vault_token_b: self.create_ata(&vault_pda, &self.token_b_mint, 0).await,
+        }
+    }
+
+    //identical transactions are only processed once per blockhash
+    pub async fn refresh_blockhash(&mut self) {
+        self.recent_blockhash = self
+            .banks_client
+            .get_new_latest_blockhash(&self.recent_blockhash)
+            .await
+            .unwrap();
+    }
+
+    pub async fn process(
+        &self,
+        instructions: &[Instruction],
+        signers: &[&Keypair],
+    ) -> Result<(), BanksClientError> {
+        process(
+            &self.banks_client,
+            &self.payer,
+            self.recent_blockhash,
+            instructions,
+            signers,
+        )
+        .await
+    }
+
+    pub async fn balance(&self, token_account: Pubkey) -> u64 {
+        token_balance(&self.banks_client, token_account).await
+    }
+
+    pub async fn escrow(&self, escrow: &FixtureEscrow) -> Escrow {
+        read_escrow(&self.banks_client, escrow.escrow_account).await
+    }
+
+    //user_a funding token a
+    pub fn maker_deposit_ix(&self, escrow: &FixtureEscrow, amount: u64) -> Instruction {
+        deposit_ix(
+            self.program_id,
+            self.user_a.pubkey(),
+            escrow.escrow_account,
+            self.user_a.token_a,
+            escrow.vault_token_a,
+            amount,
+            self.token_a_mint,
+        )
+    }
+
+    //`taker` funding token b
+    pub fn taker_deposit_ix(
+        &self,
+        escrow: &FixtureEscrow,
+        taker: &User,
+        amount: u64,
+    ) -> Instruction {
+        deposit_ix(
+            self.program_id,
+            taker.pubkey(),
+            escrow.escrow_account,
+            taker.token_b,
+            escrow.vault_token_b,
+            amount,
+            self.token_a_mint,
+        )
+    }
+
+    pub fn complete_swap_ix(
+        &self,
+        escrow: &FixtureEscrow,
+        caller: &User,
+        taker: &User,
+    ) -> Instruction {
+        complete_swap_ix(
+            self.program_id,
+            caller.pubkey(),
+            escrow.escrow_account,
+            escrow.vault_token_a,
+            escrow.vault_token_b,
+            self.user_a.token_b,
+            taker.token_a,
+        )
+    }
+
+    pub fn cancel_ix(&self, escrow: &FixtureEscrow, caller: &User, taker: &User) -> Instruction {
+        cancel_ix(
+            self.program_id,
+            caller.pubkey(),
+            escrow.escrow_account,
+            escrow.vault_token_a,
+            escrow.vault_token_b,
+            self.user_a.token_a,
+            taker.token_b,
+        )
+    }
+}
@@ -0,0 +1,262 @@
+//compute unit benchmarks, these need the sbf build since natively processed instructions
+//are not metered, so they are ignored by a plain cargo test. run them with
+//cargo test-sbf --test compute_units -- --ignored, they fail when solana_escrow.so is missing.
+//the numbers are checked against tests/compute_units.baseline and the test fails when an
+//instruction has no baseline or costs more than CU_REGRESSION_THRESHOLD percent (default 5)
+//over it. run with UPDATE_CU_BASELINE=1 to record the current numbers instead.
+//to price a change, record the baseline on the commit before it and run the gate on the
+//commit itself, the table shows the change per instruction
+mod common;
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use common::{
+    FixtureEscrow, SwapFixture, create_escrow_account_ix, init_escrow_ix, process_with_metadata,
+    swap_terms,
+};
+use solana_escrow::EscrowStatus;
+use solana_program::instruction::Instruction;
+use solana_program_test::*;
+use solana_sdk::{pubkey::Pubkey, signature::Keypair, signer::Signer, transaction::Transaction};
+
+fn sbf_program_available() -> bool {
+    let out_dirs = ["SBF_OUT_DIR", "BPF_OUT_DIR"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .map(PathBuf::from)
+        .chain([PathBuf::from("target/deploy")]);
+
+    out_dirs
+        .map(|dir| dir.join("solana_escrow.so"))
+        .any(|program| program.exists())
+}
+
+const BASELINE_FILE: &str = "tests/compute_units.baseline";
+const DEFAULT_THRESHOLD_PERCENT: u64 = 5;
+
+fn baseline_path() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(BASELINE_FILE)
+}
+
+//lines of `<instruction> <units>`, # starts a comment
+fn read_baseline() -> BTreeMap<String, u64> {
+    let contents = std::fs::read_to_string(baseline_path()).unwrap_or_default();
+
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let (instruction, units) = line
+                .rsplit_once(char::is_whitespace)
+                .unwrap_or_else(|| panic!("malformed baseline line: {}", line));
+            let units = units
+                .parse()
+                .unwrap_or_else(|_| panic!("malformed baseline units: {}", line));
+            (instruction.trim().to_string(), units)
+        })
+        .collect()
+}
+
+fn write_baseline(measured: &[(&str, u64)]) {
+    let mut contents = String::from(
+        "#compute units per instruction, recorded by tests/compute_units.rs from the sbf build\n\
+         #regenerate with UPDATE_CU_BASELINE=1 cargo test-sbf --test compute_units -- --ignored\n",
+    );
+    for (instruction, units) in measured {
+        contents.push_str(&format!("{} {}\n", instruction, units));
+    }
+
+    std::fs::write(baseline_path(), contents).unwrap();
+}
+
+fn threshold_percent() -> u64 {
+    std::env::var("CU_REGRESSION_THRESHOLD")
+        .ok()
+        .map(|value| {
+            value
+                .parse()
+                .expect("CU_REGRESSION_THRESHOLD is a percentage")
+        })
+        .unwrap_or(DEFAULT_THRESHOLD_PERCENT)
+}
+
+//prints the table and returns the instructions that went over the threshold or have no
+//baseline yet
+fn compare_to_baseline(measured: &[(&str, u64)], baseline: &BTreeMap<String, u64>) -> Vec<String> {
+    let threshold = threshold_percent();
+    let mut regressions = Vec::new();
+
+    println!(
+        "  {:<16} {:>8} {:>8} {:>8}",
+        "instruction", "units", "baseline", "change"
+    );
+    for (instruction, units) in measured {
+        //an unrecorded instruction would never be gated, record it with UPDATE_CU_BASELINE=1
+        let Some(&expected) = baseline.get(*instruction) else {
+            println!("  {:<16} {:>8} {:>8} {:>8}", instruction, units, "-", "new");
+            regressions.push(format!("{} has no baseline", instruction));
+            continue;
+        };
+
+        let change = *units as i64 - expected as i64;
+        println!(
+            "  {:<16} {:>8} {:>8} {:>+8}",
+            instruction, units, expected, change
+        );
+
+        if *units * 100 > expected * (100 + threshold) {
+            regressions.push(format!(
+                "{} used {} compute units, baseline is {} (+{}%)",
+                instruction,
+                units,
+                expected,
+                (*units - expected) * 100 / expected.max(1)
+            ));
+        }
+    }
+
+    regressions
+}
+
+//runs `instruction` in its own transaction and returns the compute units it used
+async fn measure(fixture: &SwapFixture, instruction: Instruction, signers: &[&Keypair]) -> u64 {
+    let mut all_signers = vec![&fixture.payer];
+    all_signers.extend_from_slice(signers);
+
+    let transaction = Transaction::new_signed_with_payer(
+        &[instruction],
+        Some(&fixture.payer.pubkey()),
+        &all_signers,
+        fixture.recent_blockhash,
+    );
+
+    let result = process_with_metadata(&fixture.banks_client, transaction).await;
+    result.result.unwrap();
+
+    result.metadata.unwrap().compute_units_consumed
+}
+
+async fn assert_status(fixture: &SwapFixture, escrow: &FixtureEscrow, status: EscrowStatus) {
+    assert_eq!(fixture.escrow(escrow).await.status, status);
+}
+
+//allocates an escrow account, measures its InitEscrow and opens the vault token accounts
+async fn create_escrow(fixture: &SwapFixture) -> (FixtureEscrow, u64) {
+    let escrow_account = Keypair::new();
+    let rent = fixture.banks_client.get_rent().await.unwrap();
+    fixture
+        .process(
+            &[create_escrow_account_ix(
+                &fixture.payer.pubkey(),
+                &escrow_account.pubkey(),
+                &fixture.program_id,
+                &rent,
+            )],
+            &[&escrow_account],
+        )
+        .await
+        .unwrap();
+
+    let init_units = measure(
+        fixture,
+        init_escrow_ix(
+            fixture.program_id,
+            fixture.user_a.pubkey(),
+            escrow_account.pubkey(),
+            &swap_terms(fixture.token_a_mint, fixture.token_b_mint, 1000, 2000),
+        ),
+        &[&fixture.user_a.keypair],
+    )
+    .await;
+
+    (
+        fixture.open_vault(escrow_account.pubkey()).await,
+        init_units,
+    )
+}
+
+#[tokio::test]
+#[ignore = "needs the sbf build, run with cargo test-sbf --test compute_units -- --ignored"]
+async fn bench_swap_lifecycle_compute_units() {
+    assert!(
+        sbf_program_available(),
+        "solana_escrow.so not found, run cargo build-sbf first"
+    );
+
+    let program_id = Pubkey::new_unique();
+    let mut program_test = ProgramTest::new("solana_escrow", program_id, None);
+    program_test.prefer_bpf(true);
+    let fixture = SwapFixture::start(program_test, program_id, 2000, 2000).await;
+    let user_a = &fixture.user_a;
+    let user_b = &fixture.user_b;
+
+    let (swap, init_units) = create_escrow(&fixture).await;
+
+    let maker_deposit = measure(
+        &fixture,
+        fixture.maker_deposit_ix(&swap, 1000),
+        &[&user_a.keypair],
+    )
+    .await;
+
+    let taker_deposit = measure(
+        &fixture,
+        fixture.taker_deposit_ix(&swap, user_b, 2000),
+        &[&user_b.keypair],
+    )
+    .await;
+
+    let complete_swap = measure(
+        &fixture,
+        fixture.complete_swap_ix(&swap, user_b, user_b),
+        &[&user_b.keypair],
+    )
+    .await;
+
+    assert_status(&fixture, &swap, EscrowStatus::Completed).await;
+
+    //a second escrow that the maker funds and then cancels, refunding token a
+    let (refunded, _) = create_escrow(&fixture).await;
+
+    fixture
+        .process(
+            &[fixture.maker_deposit_ix(&refunded, 1000)],
+            &[&user_a.keypair],
+        )
+        .await
+        .unwrap();
+
+    let cancel = measure(
+        &fixture,
+        fixture.cancel_ix(&refunded, user_a, user_b),
+        &[&user_a.keypair],
+    )
+    .await;
+
+    assert_status(&fixture, &refunded, EscrowStatus::Cancelled).await;
+
+    let measured = [
+        ("InitEscrow", init_units),
+        ("Deposit (maker)", maker_deposit),
+        ("Deposit (taker)", taker_deposit),
+        ("CompleteSwap", complete_swap),
+        ("Cancel", cancel),
+    ];
+
+    if std::env::var_os("UPDATE_CU_BASELINE").is_some() {
+        write_baseline(&measured);
+        println!("compute unit baseline written to {}", BASELINE_FILE);
+        return;
+    }
+
+    println!("compute units per instruction:");
+    let regressions = compare_to_baseline(&measured, &read_baseline());
+    assert!(
+        regressions.is_empty(),
+        "compute unit regressions over {}% or missing baselines:\n{}",
+        threshold_percent(),
+        regressions.join("\n")
+    );
+}
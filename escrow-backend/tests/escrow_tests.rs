@@ -1,16 +1,35 @@
+mod common;
+
 use std::vec;
 
 use borsh::BorshDeserialize;
-use solana_escrow::EscrowStatus;
-use solana_escrow::{Escrow, instructions::EscrowInstruction, processor::process_instruction};
+use common::{
+    FixtureEscrow, SwapFixture, User, cancel_ix, complete_swap_ix, config_pda,
+    create_escrow_account_ix, create_mint, deposit_ix, init_escrow_ix, process_with_metadata,
+    read_escrow, swap_terms, with_expected_terms, with_payout_creation, with_settlement,
+};
+use solana_escrow::{
+    Auction, AuctionStyle, Bid, Condition, Config, Credential, EscrowKind, EscrowStatus,
+    HashAlgorithm, HashLock, MAX_CONDITION_DATA, MAX_MARKET_OFFERS, MakerAction, MakerActionKind,
+    Market, MarketOffer, OracleSource, PriceOracle, RingTrade,
+};
+use solana_escrow::{
+    Escrow,
+    instructions::{EscrowInstruction, MultisigDescriptor, RingLeg, SignedOffer},
+    processor::process_instruction,
+};
+use solana_loader_v3_interface::{get_program_data_address, state::UpgradeableLoaderState};
 use solana_program::program_pack::Pack;
 use solana_program::{
+    clock::Clock,
     instruction::{AccountMeta, Instruction},
     system_instruction,
 };
 use solana_program_test::*;
-use solana_sdk::{pubkey::Pubkey, signature::Keypair, signer::Signer, transaction::Transaction};
-use spl_associated_token_account;
+use solana_sdk::{
+    account::Account, pubkey::Pubkey, signature::Keypair, signer::Signer, transaction::Transaction,
+};
+use solana_sdk_ids::bpf_loader_upgradeable;
 use spl_token::state::{Account as TokenAccount, Mint};
 
 // fn program_id() -> Pubkey {
@@ -36,12 +55,13 @@ async fn test_escrow_initialization() {
         user_b: Pubkey::default(),
         token_a_mint: Pubkey::new_unique(),
         token_b_mint: Pubkey::new_unique(),
-        amount_a: 1000,
-        amount_b: 2000,
+        amount_a: 1000.into(),
+        amount_b: 2000.into(),
         token_a_deposited: false,
         token_b_deposited: false,
         vault_pda: Pubkey::new_unique(),
         status: solana_escrow::EscrowStatus::Active,
+        ..Default::default()
     };
 
     //escrow account on chain
@@ -61,6 +81,16 @@ async fn test_escrow_initialization() {
         amount_a,
 
         amount_b,
+        hash_lock: None,
+        delegated_source: None,
+        maker_multisig: None,
+        permissionless_settlement: false,
+        keeper_tip: 0,
+        auction: None,
+        oracle: None,
+        condition: None,
+        credential_issuer: None,
+        reference: None,
     };
 
     //escrow account instruction
@@ -80,6 +110,7 @@ async fn test_escrow_initialization() {
             AccountMeta::new(user_a.pubkey(), true),
             AccountMeta::new(escrow_account.pubkey(), false),
             AccountMeta::new_readonly(solana_program::sysvar::rent::id(), false),
+            AccountMeta::new_readonly(config_pda(&program_id), false),
         ],
     );
 
@@ -103,7 +134,7 @@ async fn test_escrow_initialization() {
 
     assert_eq!(escrow.user_a, user_a.pubkey());
     assert_eq!(escrow.token_a_mint, token_a_mint);
-    assert_eq!(escrow.amount_a, amount_a);
+    assert_eq!(escrow.amount_a.get(), amount_a);
     matches!(escrow.status, solana_escrow::EscrowStatus::Active);
 }
 
@@ -132,12 +163,13 @@ async fn test_deposit_tokens() {
         user_b: Pubkey::default(),
         token_a_mint: token_a_mint.pubkey(),
         token_b_mint: token_b_mint.pubkey(),
-        amount_a: 1000,
-        amount_b: 2000,
+        amount_a: 1000.into(),
+        amount_b: 2000.into(),
         token_a_deposited: false,
         token_b_deposited: false,
         vault_pda,
         status: solana_escrow::EscrowStatus::Active,
+        ..Default::default()
     };
 
     //escrow account on chain
@@ -154,6 +186,16 @@ async fn test_deposit_tokens() {
         token_b_mint: token_b_mint.pubkey(),
         amount_a,
         amount_b,
+        hash_lock: None,
+        delegated_source: None,
+        maker_multisig: None,
+        permissionless_settlement: false,
+        keeper_tip: 0,
+        auction: None,
+        oracle: None,
+        condition: None,
+        credential_issuer: None,
+        reference: None,
     };
 
     //escrow account instruction
@@ -173,6 +215,7 @@ async fn test_deposit_tokens() {
             AccountMeta::new(user_a.pubkey(), true),
             AccountMeta::new(escrow_account.pubkey(), false),
             AccountMeta::new_readonly(solana_program::sysvar::rent::id(), false),
+            AccountMeta::new_readonly(config_pda(&program_id), false),
         ],
     );
 
@@ -196,7 +239,9 @@ async fn test_deposit_tokens() {
 
     assert_eq!(escrow.user_a, user_a.pubkey());
     assert_eq!(escrow.token_a_mint, token_a_mint.pubkey());
-    assert_eq!(escrow.amount_a, amount_a);
+    assert_eq!(escrow.amount_a.get(), amount_a);
+    assert_eq!(escrow.vault_pda, vault_pda);
+    assert_eq!(escrow.vault_bump, vault_bump);
     matches!(escrow.status, solana_escrow::EscrowStatus::Active);
 
     //mint account size
@@ -361,7 +406,7 @@ async fn test_deposit_tokens() {
     let mint_to_user_a = spl_token::instruction::mint_to(
         &spl_token::id(),
         &token_a_mint.pubkey(),
-        &&user_a_token_account,
+        &user_a_token_account,
         &payer.pubkey(),
         &[],
         amount_a,
@@ -467,7 +512,12 @@ async fn test_deposit_tokens() {
     assert_eq!(vault_ata_account.owner, spl_token::id());
     println!("✅ Vault token account created");
 
-    let deposit_a_ix = EscrowInstruction::Deposit { amount: (1000) };
+    let deposit_a_ix = EscrowInstruction::Deposit {
+        amount: 1000,
+        expected_amount_a_min: 0,
+        expected_mint_a: token_a_mint.pubkey(),
+        settle: false,
+    };
 
     //deposit instruction
     let deposit_a_instruction = Instruction::new_with_borsh(
@@ -479,6 +529,7 @@ async fn test_deposit_tokens() {
             AccountMeta::new(user_a_token_account, false),    // depositor's token account
             AccountMeta::new(vault_token_a_account, false),   // vault's token account ← FIXED!
             AccountMeta::new_readonly(spl_token::id(), false), // token program
+            AccountMeta::new_readonly(config_pda(&program_id), false),
         ],
     );
 
@@ -503,7 +554,7 @@ async fn test_deposit_tokens() {
         .unwrap();
 
     let escrow_data = Escrow::try_from_slice(&escrow_after_deposit.data).unwrap();
-    assert_eq!(escrow_data.token_a_deposited, true);
+    assert!(escrow_data.token_a_deposited);
     println!("✅ User A has deposited the tokens");
 
     //vault balance
@@ -554,7 +605,12 @@ async fn test_deposit_tokens() {
     assert_eq!(vault_ata_account.owner, spl_token::id());
     println!("✅ Vault token B account created");
 
-    let deposit_b_ix = EscrowInstruction::Deposit { amount: (2000) };
+    let deposit_b_ix = EscrowInstruction::Deposit {
+        amount: 2000,
+        expected_amount_a_min: 0,
+        expected_mint_a: token_a_mint.pubkey(),
+        settle: false,
+    };
 
     //deposit instruction
     let deposit_b_instruction = Instruction::new_with_borsh(
@@ -566,6 +622,7 @@ async fn test_deposit_tokens() {
             AccountMeta::new(user_b_token_account, false),    // depositor's token account
             AccountMeta::new(vault_token_b_account, false),   // vault's token account ← FIXED!
             AccountMeta::new_readonly(spl_token::id(), false), // token program
+            AccountMeta::new_readonly(config_pda(&program_id), false),
         ],
     );
 
@@ -590,7 +647,7 @@ async fn test_deposit_tokens() {
         .unwrap();
 
     let escrow_data = Escrow::try_from_slice(&escrow_after_deposit.data).unwrap();
-    assert_eq!(escrow_data.token_b_deposited, true);
+    assert!(escrow_data.token_b_deposited);
     println!("✅ User B has deposited the tokens");
 
     //vault balance
@@ -775,6 +832,16 @@ async fn test_deposit_tokens() {
         token_b_mint: token_b_mint.pubkey(),
         amount_a: 500,
         amount_b: 1000,
+        hash_lock: None,
+        delegated_source: None,
+        maker_multisig: None,
+        permissionless_settlement: false,
+        keeper_tip: 0,
+        auction: None,
+        oracle: None,
+        condition: None,
+        credential_issuer: None,
+        reference: None,
     };
 
     let cancel_dummy_escrow = Escrow {
@@ -782,12 +849,13 @@ async fn test_deposit_tokens() {
         user_b: Pubkey::default(),
         token_a_mint: token_a_mint.pubkey(),
         token_b_mint: token_b_mint.pubkey(),
-        amount_a: 500,
-        amount_b: 1000,
+        amount_a: 500.into(),
+        amount_b: 1000.into(),
         token_a_deposited: false,
         token_b_deposited: false,
         vault_pda: cancel_vault_pda.0, // Use the new vault PDA
         status: solana_escrow::EscrowStatus::Active,
+        ..Default::default()
     };
 
     //on chain escrow
@@ -810,6 +878,7 @@ async fn test_deposit_tokens() {
             AccountMeta::new(user_a.pubkey(), true),
             AccountMeta::new(cancel_escrow_account.pubkey(), false),
             AccountMeta::new_readonly(solana_program::sysvar::rent::id(), false),
+            AccountMeta::new_readonly(config_pda(&program_id), false),
         ],
     );
 
@@ -836,7 +905,7 @@ async fn test_deposit_tokens() {
     let cancel_escrow = Escrow::try_from_slice(&cancel_escrow_data.data).unwrap();
 
     assert_eq!(cancel_escrow.user_a, user_a.pubkey());
-    assert_eq!(cancel_escrow.amount_a, 500);
+    assert_eq!(cancel_escrow.amount_a.get(), 500);
     matches!(cancel_escrow.status, solana_escrow::EscrowStatus::Active);
     println!("✅ New escrow created for cancel testing");
 
@@ -921,7 +990,12 @@ async fn test_deposit_tokens() {
     );
 
     // Deposit Token A from User A
-    let cancel_deposit_ix = EscrowInstruction::Deposit { amount: 500 };
+    let cancel_deposit_ix = EscrowInstruction::Deposit {
+        amount: 500,
+        expected_amount_a_min: 0,
+        expected_mint_a: token_a_mint.pubkey(),
+        settle: false,
+    };
     let cancel_deposit_instruction = Instruction::new_with_borsh(
         program_id,
         &cancel_deposit_ix,
@@ -931,6 +1005,7 @@ async fn test_deposit_tokens() {
             AccountMeta::new(user_a_token_account, false), // User's Token A ATA
             AccountMeta::new(cancel_vault_token_a_account, false), // Vault's Token A ATA
             AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(config_pda(&program_id), false),
         ],
     );
 
@@ -954,10 +1029,38 @@ async fn test_deposit_tokens() {
         .unwrap();
 
     let cancel_escrow_data = Escrow::try_from_slice(&cancel_escrow_after_deposit.data).unwrap();
-    assert_eq!(cancel_escrow_data.token_a_deposited, true);
-    assert_eq!(cancel_escrow_data.token_b_deposited, false); // Only one deposited
+    assert!(cancel_escrow_data.token_a_deposited);
+    assert!(!cancel_escrow_data.token_b_deposited); // Only one deposited
     println!("✅ One token deposited for cancel test");
 
+    //the vault of another escrow is a valid pda but not the one this escrow's bump rebuilds
+    let wrong_vault_cancel = Instruction::new_with_borsh(
+        program_id,
+        &EscrowInstruction::Cancel,
+        vec![
+            AccountMeta::new(user_a.pubkey(), true),
+            AccountMeta::new(cancel_escrow_account.pubkey(), false),
+            AccountMeta::new(vault_pda, false),
+            AccountMeta::new(cancel_vault_token_a_account, false),
+            AccountMeta::new(cancel_vault_token_b_account, false),
+            AccountMeta::new(user_a_token_account, false),
+            AccountMeta::new(user_b_token_account, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+        ],
+    );
+    let wrong_vault_tx = Transaction::new_signed_with_payer(
+        &[wrong_vault_cancel],
+        Some(&payer.pubkey()),
+        &[&payer, &user_a],
+        recent_blockhash,
+    );
+    assert!(
+        banks_client
+            .process_transaction(wrong_vault_tx)
+            .await
+            .is_err()
+    );
+
     // --- Call Cancel instruction ---
     // Note: Cancel requires 8 accounts (same as CompleteSwap)
     let cancel_ix = EscrowInstruction::Cancel;
@@ -1021,3 +1124,3340 @@ async fn test_deposit_tokens() {
     );
     println!("✅ Cancel verified - refunds processed and status updated");
 }
+
+fn claim_hash_lock_ix(
+    program_id: Pubkey,
+    caller: Pubkey,
+    escrow_account: Pubkey,
+    vault_token_a: Pubkey,
+    user_b_token_a_account: Pubkey,
+    preimage: [u8; 32],
+) -> Instruction {
+    let (vault_pda, _) =
+        Pubkey::find_program_address(&[b"vault", escrow_account.as_ref()], &program_id);
+
+    Instruction::new_with_borsh(
+        program_id,
+        &EscrowInstruction::ClaimHashLock { preimage },
+        vec![
+            AccountMeta::new(caller, true),
+            AccountMeta::new(escrow_account, false),
+            AccountMeta::new(vault_pda, false),
+            AccountMeta::new(vault_token_a, false),
+            AccountMeta::new(user_b_token_a_account, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+        ],
+    )
+}
+
+//stand-in for the token program that accepts every instruction without moving anything
+fn accept_anything(
+    _program_id: &Pubkey,
+    _accounts: &[solana_program::account_info::AccountInfo],
+    _data: &[u8],
+) -> solana_program::entrypoint::ProgramResult {
+    Ok(())
+}
+
+//user_a funds a hash locked escrow of 1000 token a for user_b
+async fn open_hash_lock_escrow(
+    fixture: &SwapFixture,
+    algorithm: HashAlgorithm,
+    hash: [u8; 32],
+    timeout: i64,
+) -> FixtureEscrow {
+    let escrow = fixture
+        .open_escrow(&EscrowInstruction::InitEscrow {
+            token_a_mint: fixture.token_a_mint,
+            token_b_mint: fixture.token_b_mint,
+            amount_a: 1000,
+            amount_b: 0,
+            hash_lock: Some(HashLock {
+                recipient: fixture.user_b.pubkey(),
+                hash,
+                algorithm,
+                timeout: timeout.into(),
+            }),
+            delegated_source: None,
+            maker_multisig: None,
+            permissionless_settlement: false,
+            keeper_tip: 0,
+            auction: None,
+            oracle: None,
+            condition: None,
+            credential_issuer: None,
+            reference: None,
+        })
+        .await;
+
+    fixture
+        .process(
+            &[fixture.maker_deposit_ix(&escrow, 1000)],
+            &[&fixture.user_a.keypair],
+        )
+        .await
+        .unwrap();
+
+    escrow
+}
+
+#[tokio::test]
+async fn test_hash_lock_claim_with_preimage() {
+    let program_id = Pubkey::new_unique();
+    let fake_token_program = Pubkey::new_unique();
+    let mut program_test =
+        ProgramTest::new("solana_escrow", program_id, processor!(process_instruction));
+    program_test.add_program(
+        "fake_token",
+        fake_token_program,
+        processor!(accept_anything),
+    );
+    let (fixture, _context) =
+        SwapFixture::start_with_context(program_test, program_id, 1000, 0).await;
+
+    let preimage = [7u8; 32];
+    let hash = solana_program::hash::hash(&preimage).to_bytes();
+
+    let clock: Clock = fixture.banks_client.get_sysvar().await.unwrap();
+    let timeout = clock.unix_timestamp + 3600;
+
+    let escrow = open_hash_lock_escrow(&fixture, HashAlgorithm::Sha256, hash, timeout).await;
+    let claim_ix = |preimage| {
+        claim_hash_lock_ix(
+            program_id,
+            fixture.payer.pubkey(),
+            escrow.escrow_account,
+            escrow.vault_token_a,
+            fixture.user_b.token_a,
+            preimage,
+        )
+    };
+
+    let state = fixture.escrow(&escrow).await;
+    assert_eq!(state.kind, EscrowKind::HashLock);
+    assert_eq!(state.user_b, fixture.user_b.pubkey());
+    assert!(state.token_a_deposited);
+
+    //wrong preimage is rejected
+    assert!(fixture.process(&[claim_ix([8u8; 32])], &[]).await.is_err());
+
+    //user_a cannot pull the funds back before the timeout
+    let early_cancel = fixture.cancel_ix(&escrow, &fixture.user_a, &fixture.user_b);
+    assert!(
+        fixture
+            .process(&[early_cancel], &[&fixture.user_a.keypair])
+            .await
+            .is_err()
+    );
+
+    //once the preimage is public a stranger could claim through a token program that moves
+    //nothing, the escrow would be completed without paying user_b
+    let mut fake_claim = claim_ix(preimage);
+    fake_claim.accounts[5] = AccountMeta::new_readonly(fake_token_program, false);
+    assert!(fixture.process(&[fake_claim], &[]).await.is_err());
+
+    let state = fixture.escrow(&escrow).await;
+    assert_eq!(state.status, EscrowStatus::Active);
+    assert!(!state.settlement_locked);
+
+    //anyone can submit the preimage, user_b gets paid
+    fixture.process(&[claim_ix(preimage)], &[]).await.unwrap();
+
+    assert_eq!(fixture.balance(fixture.user_b.token_a).await, 1000);
+    assert_eq!(fixture.balance(escrow.vault_token_a).await, 0);
+
+    let state = fixture.escrow(&escrow).await;
+    assert_eq!(state.status, EscrowStatus::Completed);
+}
+
+#[tokio::test]
+async fn test_hash_lock_refund_after_timeout() {
+    let program_id = Pubkey::new_unique();
+    let program_test =
+        ProgramTest::new("solana_escrow", program_id, processor!(process_instruction));
+    let (fixture, context) =
+        SwapFixture::start_with_context(program_test, program_id, 1000, 0).await;
+
+    let preimage = [9u8; 32];
+    let hash = solana_program::keccak::hash(&preimage).to_bytes();
+
+    let mut clock: Clock = fixture.banks_client.get_sysvar().await.unwrap();
+    let timeout = clock.unix_timestamp + 60;
+
+    let escrow = open_hash_lock_escrow(&fixture, HashAlgorithm::Keccak256, hash, timeout).await;
+
+    clock.unix_timestamp = timeout;
+    context.set_sysvar(&clock);
+
+    //the preimage is useless once the timeout has passed
+    let late_claim = claim_hash_lock_ix(
+        program_id,
+        fixture.payer.pubkey(),
+        escrow.escrow_account,
+        escrow.vault_token_a,
+        fixture.user_b.token_a,
+        preimage,
+    );
+    assert!(fixture.process(&[late_claim], &[]).await.is_err());
+
+    //user_b cannot cancel an htlc
+    let user_b_cancel = fixture.cancel_ix(&escrow, &fixture.user_b, &fixture.user_b);
+    assert!(
+        fixture
+            .process(&[user_b_cancel], &[&fixture.user_b.keypair])
+            .await
+            .is_err()
+    );
+
+    let refund = fixture.cancel_ix(&escrow, &fixture.user_a, &fixture.user_b);
+    fixture
+        .process(&[refund], &[&fixture.user_a.keypair])
+        .await
+        .unwrap();
+
+    assert_eq!(fixture.balance(fixture.user_a.token_a).await, 1000);
+    assert_eq!(fixture.balance(fixture.user_b.token_a).await, 0);
+
+    let state = fixture.escrow(&escrow).await;
+    assert_eq!(state.status, EscrowStatus::Cancelled);
+}
+
+//ed25519 precompile instruction with signature, key and message inlined
+fn ed25519_verify_ix(signer: &Keypair, message: &[u8]) -> Instruction {
+    let signature = signer.sign_message(message);
+    let public_key_offset: u16 = 16;
+    let signature_offset = public_key_offset + 32;
+    let message_offset = signature_offset + 64;
+
+    let mut data = vec![1u8, 0];
+    for value in [
+        signature_offset,
+        u16::MAX,
+        public_key_offset,
+        u16::MAX,
+        message_offset,
+        message.len() as u16,
+        u16::MAX,
+    ] {
+        data.extend_from_slice(&value.to_le_bytes());
+    }
+    data.extend_from_slice(signer.pubkey().as_ref());
+    data.extend_from_slice(signature.as_ref());
+    data.extend_from_slice(message);
+
+    Instruction {
+        program_id: solana_program::ed25519_program::id(),
+        accounts: vec![],
+        data,
+    }
+}
+
+fn fill_signed_offer_ix(
+    program_id: Pubkey,
+    taker: Pubkey,
+    offer: &SignedOffer,
+    maker_token_a: Pubkey,
+    maker_token_b: Pubkey,
+    taker_token_a: Pubkey,
+    taker_token_b: Pubkey,
+) -> Instruction {
+    let (delegate_pda, _) = Pubkey::find_program_address(&[b"delegate"], &program_id);
+    let (offer_record, _) = Pubkey::find_program_address(
+        &[b"offer", offer.maker.as_ref(), &offer.nonce.to_le_bytes()],
+        &program_id,
+    );
+
+    Instruction::new_with_borsh(
+        program_id,
+        &EscrowInstruction::FillSignedOffer {
+            offer: offer.clone(),
+        },
+        vec![
+            AccountMeta::new(taker, true),
+            AccountMeta::new(maker_token_a, false),
+            AccountMeta::new(maker_token_b, false),
+            AccountMeta::new(taker_token_a, false),
+            AccountMeta::new(taker_token_b, false),
+            AccountMeta::new_readonly(delegate_pda, false),
+            AccountMeta::new(offer_record, false),
+            AccountMeta::new_readonly(solana_program::sysvar::instructions::id(), false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(solana_program::system_program::id(), false),
+            AccountMeta::new_readonly(config_pda(&program_id), false),
+        ],
+    )
+}
+
+#[tokio::test]
+async fn test_fill_signed_offer() {
+    let program_id = Pubkey::new_unique();
+    let fake_token_program = Pubkey::new_unique();
+    let mut program_test =
+        ProgramTest::new("solana_escrow", program_id, processor!(process_instruction));
+    program_test.add_program(
+        "fake_token",
+        fake_token_program,
+        processor!(accept_anything),
+    );
+    let mut fixture = SwapFixture::start(program_test, program_id, 1000, 2000).await;
+    let maker = &fixture.user_a;
+    let taker = &fixture.user_b;
+
+    //maker approves the program delegate once, offers are then quoted off-chain
+    let (delegate_pda, _) = Pubkey::find_program_address(&[b"delegate"], &program_id);
+    let approve_ix = spl_token::instruction::approve(
+        &spl_token::id(),
+        &maker.token_a,
+        &delegate_pda,
+        &maker.pubkey(),
+        &[],
+        1000,
+    )
+    .unwrap();
+
+    //taker pays rent for the offer record
+    let fund_taker_ix =
+        system_instruction::transfer(&fixture.payer.pubkey(), &taker.pubkey(), 1_000_000_000);
+
+    fixture
+        .process(&[approve_ix, fund_taker_ix], &[&maker.keypair])
+        .await
+        .unwrap();
+
+    let clock: Clock = fixture.banks_client.get_sysvar().await.unwrap();
+    let offer = SignedOffer {
+        program_id,
+        maker: maker.pubkey(),
+        token_a_mint: fixture.token_a_mint,
+        token_b_mint: fixture.token_b_mint,
+        amount_a: 1000,
+        amount_b: 2000,
+        taker: Pubkey::default(),
+        expiry: clock.unix_timestamp + 3600,
+        nonce: 1,
+    };
+    let message = borsh::to_vec(&offer).unwrap();
+
+    //lamports sent to the offer record's address must not make the offer unfillable
+    let (offer_record, _) = Pubkey::find_program_address(
+        &[
+            b"offer",
+            maker.pubkey().as_ref(),
+            &offer.nonce.to_le_bytes(),
+        ],
+        &program_id,
+    );
+    fixture
+        .process(
+            &[system_instruction::transfer(
+                &fixture.payer.pubkey(),
+                &offer_record,
+                fixture
+                    .banks_client
+                    .get_rent()
+                    .await
+                    .unwrap()
+                    .minimum_balance(0),
+            )],
+            &[],
+        )
+        .await
+        .unwrap();
+
+    let fill_ix = fill_signed_offer_ix(
+        program_id,
+        taker.pubkey(),
+        &offer,
+        maker.token_a,
+        maker.token_b,
+        taker.token_a,
+        taker.token_b,
+    );
+
+    //without the signature verification the fill is rejected
+    assert!(
+        fixture
+            .process(std::slice::from_ref(&fill_ix), &[&taker.keypair])
+            .await
+            .is_err()
+    );
+
+    //a signature by someone other than the maker is rejected
+    let impostor = Keypair::new();
+    assert!(
+        fixture
+            .process(
+                &[ed25519_verify_ix(&impostor, &message), fill_ix.clone()],
+                &[&taker.keypair],
+            )
+            .await
+            .is_err()
+    );
+
+    //a token program that moves nothing must not burn the maker's nonce
+    let mut fake_fill_ix = fill_ix.clone();
+    fake_fill_ix.accounts[8] = AccountMeta::new_readonly(fake_token_program, false);
+    assert!(
+        fixture
+            .process(
+                &[ed25519_verify_ix(&maker.keypair, &message), fake_fill_ix],
+                &[&taker.keypair],
+            )
+            .await
+            .is_err()
+    );
+
+    fixture
+        .process(
+            &[ed25519_verify_ix(&maker.keypair, &message), fill_ix.clone()],
+            &[&taker.keypair],
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(fixture.balance(maker.token_a).await, 0);
+    assert_eq!(fixture.balance(maker.token_b).await, 2000);
+    assert_eq!(fixture.balance(taker.token_a).await, 1000);
+    assert_eq!(fixture.balance(taker.token_b).await, 0);
+
+    //the same offer cannot be filled twice
+    let verify_ix = ed25519_verify_ix(&maker.keypair, &message);
+    fixture.refresh_blockhash().await;
+    assert!(
+        fixture
+            .process(&[verify_ix, fill_ix], &[&fixture.user_b.keypair])
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn test_approval_escrow_pulls_tokens_at_settlement() {
+    let fixture = SwapFixture::new(1000, 2000).await;
+    let (user_a, user_b) = (&fixture.user_a, &fixture.user_b);
+
+    let escrow = fixture
+        .open_escrow(&EscrowInstruction::InitEscrow {
+            token_a_mint: fixture.token_a_mint,
+            token_b_mint: fixture.token_b_mint,
+            amount_a: 1000,
+            amount_b: 2000,
+            hash_lock: None,
+            delegated_source: Some(user_a.token_a),
+            maker_multisig: None,
+            permissionless_settlement: false,
+            keeper_tip: 0,
+            auction: None,
+            oracle: None,
+            condition: None,
+            credential_issuer: None,
+            reference: None,
+        })
+        .await;
+
+    let state = fixture.escrow(&escrow).await;
+    assert_eq!(state.kind, EscrowKind::Approval);
+    assert_eq!(state.delegated_source, user_a.token_a);
+
+    //user_a approves the vault pda instead of depositing
+    let approve_ix = spl_token::instruction::approve(
+        &spl_token::id(),
+        &user_a.token_a,
+        &escrow.vault_pda,
+        &user_a.pubkey(),
+        &[],
+        1000,
+    )
+    .unwrap();
+    fixture
+        .process(&[approve_ix], &[&user_a.keypair])
+        .await
+        .unwrap();
+
+    fixture
+        .process(
+            &[fixture.taker_deposit_ix(&escrow, user_b, 2000)],
+            &[&user_b.keypair],
+        )
+        .await
+        .unwrap();
+
+    //user_a still holds their tokens until the trade happens
+    assert_eq!(fixture.balance(user_a.token_a).await, 1000);
+
+    fixture
+        .process(
+            &[complete_swap_ix(
+                fixture.program_id,
+                user_b.pubkey(),
+                escrow.escrow_account,
+                user_a.token_a,
+                escrow.vault_token_b,
+                user_a.token_b,
+                user_b.token_a,
+            )],
+            &[&user_b.keypair],
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(fixture.balance(user_a.token_a).await, 0);
+    assert_eq!(fixture.balance(user_a.token_b).await, 2000);
+    assert_eq!(fixture.balance(user_b.token_a).await, 1000);
+    assert_eq!(fixture.balance(user_b.token_b).await, 0);
+
+    let state = fixture.escrow(&escrow).await;
+    assert_eq!(state.status, EscrowStatus::Completed);
+}
+
+//UpdateTerms / AcceptCounter, the vault accounts are used when user_a already deposited
+fn maker_terms_ix(
+    program_id: Pubkey,
+    instruction: &EscrowInstruction,
+    user_a: Pubkey,
+    escrow_account: Pubkey,
+    vault_token_a: Pubkey,
+    user_a_token_a_account: Pubkey,
+) -> Instruction {
+    let (vault_pda, _) =
+        Pubkey::find_program_address(&[b"vault", escrow_account.as_ref()], &program_id);
+
+    Instruction::new_with_borsh(
+        program_id,
+        instruction,
+        vec![
+            AccountMeta::new(user_a, true),
+            AccountMeta::new(escrow_account, false),
+            AccountMeta::new_readonly(vault_pda, false),
+            AccountMeta::new(vault_token_a, false),
+            AccountMeta::new(user_a_token_a_account, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+        ],
+    )
+}
+
+#[tokio::test]
+async fn test_update_terms_and_counter_offer() {
+    let mut fixture = SwapFixture::new(1500, 2000).await;
+    let escrow = fixture.open_swap(1000, 2000).await;
+    let terms_ix = |fixture: &SwapFixture, instruction| {
+        maker_terms_ix(
+            fixture.program_id,
+            &instruction,
+            fixture.user_a.pubkey(),
+            escrow.escrow_account,
+            escrow.vault_token_a,
+            fixture.user_a.token_a,
+        )
+    };
+    let counter_ix = |fixture: &SwapFixture, proposer: &User, amount_a, amount_b| {
+        Instruction::new_with_borsh(
+            fixture.program_id,
+            &EscrowInstruction::ProposeCounter { amount_a, amount_b },
+            vec![
+                AccountMeta::new(proposer.pubkey(), true),
+                AccountMeta::new(escrow.escrow_account, false),
+            ],
+        )
+    };
+
+    fixture
+        .process(
+            &[fixture.maker_deposit_ix(&escrow, 1000)],
+            &[&fixture.user_a.keypair],
+        )
+        .await
+        .unwrap();
+
+    //raising amount_a after depositing tops up the vault
+    let update_ix = terms_ix(
+        &fixture,
+        EscrowInstruction::UpdateTerms {
+            amount_a: 1500,
+            amount_b: 2000,
+        },
+    );
+    fixture
+        .process(&[update_ix], &[&fixture.user_a.keypair])
+        .await
+        .unwrap();
+
+    assert_eq!(fixture.balance(escrow.vault_token_a).await, 1500);
+    assert_eq!(fixture.balance(fixture.user_a.token_a).await, 0);
+
+    //user_b counters with less token a for less token b
+    let propose_ix = counter_ix(&fixture, &fixture.user_b, 1000, 1800);
+    fixture
+        .process(
+            std::slice::from_ref(&propose_ix),
+            &[&fixture.user_b.keypair],
+        )
+        .await
+        .unwrap();
+
+    let state = fixture.escrow(&escrow).await;
+    assert_eq!(state.counter_proposer, fixture.user_b.pubkey());
+    assert_eq!(state.counter_amount_b.get(), 1800);
+
+    //user_a accepts the counter it saw
+    let accept_ix = terms_ix(
+        &fixture,
+        EscrowInstruction::AcceptCounter {
+            proposer: fixture.user_b.pubkey(),
+            amount_a: 1000,
+            amount_b: 1800,
+        },
+    );
+
+    //the stranger replaces the counter with a giveaway before the accept lands
+    let front_run_ix = counter_ix(&fixture, &fixture.stranger, 1500, 1);
+    fixture
+        .process(&[front_run_ix], &[&fixture.stranger.keypair])
+        .await
+        .unwrap();
+
+    assert!(
+        fixture
+            .process(std::slice::from_ref(&accept_ix), &[&fixture.user_a.keypair],)
+            .await
+            .is_err()
+    );
+
+    let state = fixture.escrow(&escrow).await;
+    assert_eq!(state.user_b, Pubkey::default());
+    assert_eq!(state.amount_a.get(), 1500);
+    assert_eq!(state.amount_b.get(), 2000);
+
+    //user_b proposes again and the accept goes through
+    fixture.refresh_blockhash().await;
+    fixture
+        .process(&[propose_ix], &[&fixture.user_b.keypair])
+        .await
+        .unwrap();
+    fixture
+        .process(&[accept_ix], &[&fixture.user_a.keypair])
+        .await
+        .unwrap();
+
+    //the surplus token a went back to user_a
+    assert_eq!(fixture.balance(escrow.vault_token_a).await, 1000);
+    assert_eq!(fixture.balance(fixture.user_a.token_a).await, 500);
+
+    let state = fixture.escrow(&escrow).await;
+    assert_eq!(state.amount_a.get(), 1000);
+    assert_eq!(state.amount_b.get(), 1800);
+    assert_eq!(state.user_b, fixture.user_b.pubkey());
+    assert_eq!(state.counter_proposer, Pubkey::default());
+
+    //the escrow is now reserved for user_b
+    assert!(
+        fixture
+            .process(
+                &[fixture.taker_deposit_ix(&escrow, &fixture.stranger, 1800)],
+                &[&fixture.stranger.keypair],
+            )
+            .await
+            .is_err()
+    );
+
+    fixture
+        .process(
+            &[fixture.taker_deposit_ix(&escrow, &fixture.user_b, 1800)],
+            &[&fixture.user_b.keypair],
+        )
+        .await
+        .unwrap();
+
+    //terms are locked once the taker has deposited
+    let late_update_ix = terms_ix(
+        &fixture,
+        EscrowInstruction::UpdateTerms {
+            amount_a: 1000,
+            amount_b: 2000,
+        },
+    );
+    assert!(
+        fixture
+            .process(&[late_update_ix], &[&fixture.user_a.keypair])
+            .await
+            .is_err()
+    );
+
+    fixture
+        .process(
+            &[fixture.complete_swap_ix(&escrow, &fixture.user_a, &fixture.user_b)],
+            &[&fixture.user_a.keypair],
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(fixture.balance(fixture.user_a.token_b).await, 1800);
+    assert_eq!(fixture.balance(fixture.user_b.token_a).await, 1000);
+    assert_eq!(fixture.balance(fixture.user_b.token_b).await, 200);
+}
+
+fn set_paused_ix(program_id: Pubkey, admin: Pubkey, paused: bool) -> Instruction {
+    Instruction::new_with_borsh(
+        program_id,
+        &EscrowInstruction::SetPaused { paused },
+        vec![
+            AccountMeta::new(admin, true),
+            AccountMeta::new(config_pda(&program_id), false),
+        ],
+    )
+}
+
+#[tokio::test]
+async fn test_pause_blocks_new_positions_but_allows_refunds() {
+    let program_id = Pubkey::new_unique();
+    let mut program_test =
+        ProgramTest::new("solana_escrow", program_id, processor!(process_instruction));
+
+    //the deployer, recorded as upgrade authority in the programdata account
+    let admin = Keypair::new();
+    program_test.add_account(
+        get_program_data_address(&program_id),
+        Account::new_data(
+            1_000_000_000,
+            &UpgradeableLoaderState::ProgramData {
+                slot: 0,
+                upgrade_authority_address: Some(admin.pubkey()),
+            },
+            &bpf_loader_upgradeable::id(),
+        )
+        .unwrap(),
+    );
+    program_test.add_account(
+        admin.pubkey(),
+        Account::new(1_000_000_000, 0, &solana_program::system_program::id()),
+    );
+
+    //a config lookalike owned by the program that names an impostor as admin
+    let impostor = Keypair::new();
+    let decoy_config = Pubkey::new_unique();
+    program_test.add_account(
+        decoy_config,
+        Account {
+            lamports: 1_000_000_000,
+            data: borsh::to_vec(&Config {
+                admin: impostor.pubkey(),
+                paused: false,
+            })
+            .unwrap(),
+            owner: program_id,
+            ..Account::default()
+        },
+    );
+    let fixture = SwapFixture::start(program_test, program_id, 1000, 2000).await;
+    let (payer, user_a, user_b) = (&fixture.payer, &fixture.user_a, &fixture.user_b);
+
+    let init_config_ix = |admin: Pubkey| {
+        Instruction::new_with_borsh(
+            program_id,
+            &EscrowInstruction::InitConfig,
+            vec![
+                AccountMeta::new(admin, true),
+                AccountMeta::new(config_pda(&program_id), false),
+                AccountMeta::new_readonly(solana_program::system_program::id(), false),
+                AccountMeta::new_readonly(get_program_data_address(&program_id), false),
+            ],
+        )
+    };
+
+    //whoever gets there first does not become admin, only the upgrade authority can
+    assert!(
+        fixture
+            .process(&[init_config_ix(payer.pubkey())], &[])
+            .await
+            .is_err()
+    );
+
+    //lamports sent to the config address ahead of the deployer must not block the kill switch
+    let rent = fixture.banks_client.get_rent().await.unwrap();
+    fixture
+        .process(
+            &[
+                system_instruction::transfer(
+                    &payer.pubkey(),
+                    &config_pda(&program_id),
+                    rent.minimum_balance(0),
+                ),
+                init_config_ix(admin.pubkey()),
+            ],
+            &[&admin],
+        )
+        .await
+        .unwrap();
+
+    let init_ix = swap_terms(fixture.token_a_mint, fixture.token_b_mint, 1000, 2000);
+    let escrow = fixture.open_escrow(&init_ix).await;
+
+    fixture
+        .process(
+            &[fixture.maker_deposit_ix(&escrow, 1000)],
+            &[&user_a.keypair],
+        )
+        .await
+        .unwrap();
+
+    //only the admin can flip the switch
+    assert!(
+        fixture
+            .process(
+                &[set_paused_ix(program_id, user_a.pubkey(), true)],
+                &[&user_a.keypair],
+            )
+            .await
+            .is_err()
+    );
+
+    //only the [b"config"] pda holds the switch
+    let mut decoy_pause_ix = set_paused_ix(program_id, impostor.pubkey(), true);
+    decoy_pause_ix.accounts[1] = AccountMeta::new(decoy_config, false);
+    assert!(
+        fixture
+            .process(&[decoy_pause_ix], &[&impostor])
+            .await
+            .is_err()
+    );
+
+    fixture
+        .process(
+            &[set_paused_ix(program_id, admin.pubkey(), true)],
+            &[&admin],
+        )
+        .await
+        .unwrap();
+
+    //no new deposits while paused
+    assert!(
+        fixture
+            .process(
+                &[fixture.taker_deposit_ix(&escrow, user_b, 2000)],
+                &[&user_b.keypair],
+            )
+            .await
+            .is_err()
+    );
+
+    //no new escrows while paused
+    let paused_escrow = Keypair::new();
+    let escrow_size = borsh::to_vec(&Escrow::default()).unwrap().len();
+    let paused_init_ix = Instruction::new_with_borsh(
+        program_id,
+        &init_ix,
+        vec![
+            AccountMeta::new(user_a.pubkey(), true),
+            AccountMeta::new(paused_escrow.pubkey(), false),
+            AccountMeta::new_readonly(solana_program::sysvar::rent::id(), false),
+            AccountMeta::new_readonly(config_pda(&program_id), false),
+        ],
+    );
+    assert!(
+        fixture
+            .process(
+                &[
+                    system_instruction::create_account(
+                        &payer.pubkey(),
+                        &paused_escrow.pubkey(),
+                        rent.minimum_balance(escrow_size),
+                        escrow_size as u64,
+                        &program_id,
+                    ),
+                    paused_init_ix,
+                ],
+                &[&user_a.keypair, &paused_escrow],
+            )
+            .await
+            .is_err()
+    );
+
+    //refunds still go through
+    fixture
+        .process(
+            &[fixture.cancel_ix(&escrow, user_a, user_b)],
+            &[&user_a.keypair],
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(fixture.balance(user_a.token_a).await, 1000);
+
+    fixture
+        .process(
+            &[set_paused_ix(program_id, admin.pubkey(), false)],
+            &[&admin],
+        )
+        .await
+        .unwrap();
+
+    fixture.open_escrow(&init_ix).await;
+}
+
+fn approve_ix(
+    program_id: Pubkey,
+    signer: Pubkey,
+    escrow_account: Pubkey,
+    kind: MakerActionKind,
+) -> Instruction {
+    Instruction::new_with_borsh(
+        program_id,
+        &EscrowInstruction::Approve {
+            action: MakerAction::new(kind),
+        },
+        vec![
+            AccountMeta::new_readonly(signer, true),
+            AccountMeta::new(escrow_account, false),
+        ],
+    )
+}
+
+#[tokio::test]
+async fn test_multisig_maker_with_spl_multisig_treasury() {
+    let mut fixture = SwapFixture::new(0, 0).await;
+    let program_id = fixture.program_id;
+
+    let signer_1 = Keypair::new();
+    let signer_2 = Keypair::new();
+    let signer_3 = Keypair::new();
+    let signer_keys = [signer_1.pubkey(), signer_2.pubkey(), signer_3.pubkey()];
+
+    //2-of-3 spl multisig owning the treasury token account
+    let treasury = Keypair::new();
+    let rent = fixture.banks_client.get_rent().await.unwrap();
+    let create_treasury_ix = system_instruction::create_account(
+        &fixture.payer.pubkey(),
+        &treasury.pubkey(),
+        rent.minimum_balance(spl_token::state::Multisig::LEN),
+        spl_token::state::Multisig::LEN as u64,
+        &spl_token::id(),
+    );
+    let init_treasury_ix = spl_token::instruction::initialize_multisig(
+        &spl_token::id(),
+        &treasury.pubkey(),
+        &signer_keys.iter().collect::<Vec<_>>(),
+        2,
+    )
+    .unwrap();
+    fixture
+        .process(&[create_treasury_ix, init_treasury_ix], &[&treasury])
+        .await
+        .unwrap();
+
+    let treasury_token_a = fixture
+        .create_ata(&treasury.pubkey(), &fixture.token_a_mint, 1000)
+        .await;
+    let treasury_token_b = fixture
+        .create_ata(&treasury.pubkey(), &fixture.token_b_mint, 0)
+        .await;
+
+    //signer_1 creates the escrow on behalf of the treasury
+    let escrow_keypair = Keypair::new();
+    let escrow_account = escrow_keypair.pubkey();
+    let escrow_size = borsh::to_vec(&Escrow::default()).unwrap().len();
+    let init_ix = |user_a: Pubkey| {
+        Instruction::new_with_borsh(
+            program_id,
+            &EscrowInstruction::InitEscrow {
+                token_a_mint: fixture.token_a_mint,
+                token_b_mint: fixture.token_b_mint,
+                amount_a: 1000,
+                amount_b: 2000,
+                hash_lock: None,
+                delegated_source: None,
+                maker_multisig: Some(MultisigDescriptor {
+                    signers: signer_keys.to_vec(),
+                    threshold: 2,
+                }),
+                permissionless_settlement: false,
+                keeper_tip: 0,
+                auction: None,
+                oracle: None,
+                condition: None,
+                credential_issuer: None,
+                reference: None,
+            },
+            vec![
+                AccountMeta::new_readonly(user_a, false),
+                AccountMeta::new(escrow_account, false),
+                AccountMeta::new_readonly(solana_program::sysvar::rent::id(), false),
+                AccountMeta::new_readonly(config_pda(&program_id), false),
+                AccountMeta::new_readonly(signer_1.pubkey(), true),
+            ],
+        )
+    };
+    let create_escrow_ix = system_instruction::create_account(
+        &fixture.payer.pubkey(),
+        &escrow_account,
+        rent.minimum_balance(escrow_size),
+        escrow_size as u64,
+        &program_id,
+    );
+
+    //a listed signer cannot make someone else's wallet or token account user_a
+    let victim = Keypair::new();
+    for user_a in [victim.pubkey(), treasury_token_a] {
+        assert!(
+            fixture
+                .process(
+                    &[create_escrow_ix.clone(), init_ix(user_a)],
+                    &[&escrow_keypair, &signer_1],
+                )
+                .await
+                .is_err()
+        );
+    }
+
+    fixture
+        .process(
+            &[create_escrow_ix, init_ix(treasury.pubkey())],
+            &[&escrow_keypair, &signer_1],
+        )
+        .await
+        .unwrap();
+
+    let escrow = fixture.open_vault(escrow_account).await;
+    let state = fixture.escrow(&escrow).await;
+    assert_eq!(state.user_a, treasury.pubkey());
+    assert_eq!(state.multisig.threshold, 2);
+    assert_eq!(state.multisig.signer_count, 3);
+
+    //the spl multisig co-signers are appended after the config account
+    let treasury_deposit_ix = |co_signers: &[&Keypair]| {
+        let mut ix = deposit_ix(
+            program_id,
+            treasury.pubkey(),
+            escrow_account,
+            treasury_token_a,
+            escrow.vault_token_a,
+            1000,
+            fixture.token_a_mint,
+        );
+        ix.accounts[0] = AccountMeta::new_readonly(treasury.pubkey(), false);
+        for co_signer in co_signers {
+            ix.accounts
+                .push(AccountMeta::new_readonly(co_signer.pubkey(), true));
+        }
+        ix
+    };
+    let approve_as = |signer: &Keypair, kind: MakerActionKind| {
+        approve_ix(program_id, signer.pubkey(), escrow_account, kind)
+    };
+
+    //the token program would accept two co-signers, the escrow still wants its approvals
+    assert!(
+        fixture
+            .process(
+                &[treasury_deposit_ix(&[&signer_1, &signer_2])],
+                &[&signer_1, &signer_2],
+            )
+            .await
+            .is_err()
+    );
+
+    for signer in [&signer_1, &signer_3] {
+        fixture
+            .process(&[approve_as(signer, MakerActionKind::Deposit)], &[signer])
+            .await
+            .unwrap();
+    }
+
+    fixture
+        .process(
+            &[treasury_deposit_ix(&[&signer_1, &signer_3])],
+            &[&signer_1, &signer_3],
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(fixture.balance(escrow.vault_token_a).await, 1000);
+    let state = fixture.escrow(&escrow).await;
+    assert!(state.token_a_deposited);
+    assert_eq!(
+        state
+            .multisig
+            .approval_count(&MakerAction::new(MakerActionKind::Deposit)),
+        0
+    );
+
+    //one approval is not enough to cancel
+    fixture
+        .process(
+            &[approve_as(&signer_2, MakerActionKind::Cancel)],
+            &[&signer_2],
+        )
+        .await
+        .unwrap();
+
+    //a signer sitting on another action neither blocks nor wipes the cancel approvals
+    let cancel_action = MakerAction::new(MakerActionKind::Cancel);
+    let update_action = MakerAction::new(MakerActionKind::UpdateTerms);
+    fixture
+        .process(
+            &[approve_as(&signer_1, MakerActionKind::UpdateTerms)],
+            &[&signer_1],
+        )
+        .await
+        .unwrap();
+    let state = fixture.escrow(&escrow).await;
+    assert_eq!(state.multisig.approval_count(&cancel_action), 1);
+    assert_eq!(state.multisig.approval_count(&update_action), 1);
+
+    //MakerActionKind::None withdraws the signer's approval
+    fixture
+        .process(
+            &[approve_as(&signer_1, MakerActionKind::None)],
+            &[&signer_1],
+        )
+        .await
+        .unwrap();
+    let state = fixture.escrow(&escrow).await;
+    assert_eq!(state.multisig.approval_count(&cancel_action), 1);
+    assert_eq!(state.multisig.approval_count(&update_action), 0);
+
+    let mut treasury_cancel_ix = cancel_ix(
+        program_id,
+        treasury.pubkey(),
+        escrow_account,
+        escrow.vault_token_a,
+        escrow.vault_token_b,
+        treasury_token_a,
+        treasury_token_b,
+    );
+    treasury_cancel_ix.accounts[0] = AccountMeta::new_readonly(treasury.pubkey(), false);
+
+    assert!(
+        fixture
+            .process(std::slice::from_ref(&treasury_cancel_ix), &[])
+            .await
+            .is_err()
+    );
+
+    fixture
+        .process(
+            &[approve_as(&signer_3, MakerActionKind::Cancel)],
+            &[&signer_3],
+        )
+        .await
+        .unwrap();
+
+    fixture.refresh_blockhash().await;
+    fixture.process(&[treasury_cancel_ix], &[]).await.unwrap();
+
+    assert_eq!(fixture.balance(treasury_token_a).await, 1000);
+    let state = fixture.escrow(&escrow).await;
+    assert_eq!(state.status, EscrowStatus::Cancelled);
+}
+
+//accounts after the caller and token program repeat per escrow
+fn complete_swap_batch_ix(
+    program_id: Pubkey,
+    caller: Pubkey,
+    settlements: &[[Pubkey; 5]],
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new(caller, true),
+        AccountMeta::new_readonly(spl_token::id(), false),
+    ];
+
+    for [
+        escrow_account,
+        vault_token_a,
+        vault_token_b,
+        user_a_token_b,
+        user_b_token_a,
+    ] in settlements
+    {
+        let (vault_pda, _) =
+            Pubkey::find_program_address(&[b"vault", escrow_account.as_ref()], &program_id);
+
+        accounts.extend([
+            AccountMeta::new(*escrow_account, false),
+            AccountMeta::new(vault_pda, false),
+            AccountMeta::new(*vault_token_a, false),
+            AccountMeta::new(*vault_token_b, false),
+            AccountMeta::new(*user_a_token_b, false),
+            AccountMeta::new(*user_b_token_a, false),
+            //none of these escrows is listed on a market
+            AccountMeta::new_readonly(program_id, false),
+        ]);
+    }
+
+    Instruction::new_with_borsh(program_id, &EscrowInstruction::CompleteSwapBatch, accounts)
+}
+
+#[tokio::test]
+async fn test_complete_swap_batch_skips_unready_escrows() {
+    let mut fixture = SwapFixture::new(0, 6000).await;
+    let program_id = fixture.program_id;
+    let user_b = &fixture.user_b;
+
+    //three makers, user_b takes the first two and leaves the last unfunded
+    let mut settlements = vec![];
+    for index in 0..3 {
+        let user_a = fixture.create_user(1000, 0).await;
+        let escrow = fixture
+            .open_escrow_as(
+                &user_a,
+                &swap_terms(fixture.token_a_mint, fixture.token_b_mint, 1000, 2000),
+            )
+            .await;
+
+        fixture
+            .process(
+                &[deposit_ix(
+                    program_id,
+                    user_a.pubkey(),
+                    escrow.escrow_account,
+                    user_a.token_a,
+                    escrow.vault_token_a,
+                    1000,
+                    fixture.token_a_mint,
+                )],
+                &[&user_a.keypair],
+            )
+            .await
+            .unwrap();
+
+        if index < 2 {
+            fixture
+                .process(
+                    &[fixture.taker_deposit_ix(&escrow, user_b, 2000)],
+                    &[&user_b.keypair],
+                )
+                .await
+                .unwrap();
+        }
+
+        settlements.push([
+            escrow.escrow_account,
+            escrow.vault_token_a,
+            escrow.vault_token_b,
+            user_a.token_b,
+            user_b.token_a,
+        ]);
+    }
+
+    //uneven account groups are rejected
+    let mut malformed_ix = complete_swap_batch_ix(program_id, user_b.pubkey(), &settlements);
+    malformed_ix.accounts.pop();
+    assert!(
+        fixture
+            .process(&[malformed_ix], &[&user_b.keypair])
+            .await
+            .is_err()
+    );
+
+    let batch_ix = complete_swap_batch_ix(program_id, user_b.pubkey(), &settlements);
+    fixture
+        .process(std::slice::from_ref(&batch_ix), &[&user_b.keypair])
+        .await
+        .unwrap();
+
+    for [escrow_account, vault_token_a, _, user_a_token_b, _] in &settlements[..2] {
+        let escrow = read_escrow(&fixture.banks_client, *escrow_account).await;
+        assert_eq!(escrow.status, EscrowStatus::Completed);
+        assert_eq!(fixture.balance(*vault_token_a).await, 0);
+        assert_eq!(fixture.balance(*user_a_token_b).await, 2000);
+    }
+
+    //the unfunded escrow was skipped without failing the batch
+    let [escrow_account, vault_token_a, _, user_a_token_b, _] = settlements[2];
+    let escrow = read_escrow(&fixture.banks_client, escrow_account).await;
+    assert_eq!(escrow.status, EscrowStatus::Active);
+    assert_eq!(fixture.balance(vault_token_a).await, 1000);
+    assert_eq!(fixture.balance(user_a_token_b).await, 0);
+
+    assert_eq!(fixture.balance(user_b.token_a).await, 2000);
+    assert_eq!(fixture.balance(user_b.token_b).await, 2000);
+
+    //settled escrows are skipped on a second run
+    fixture.refresh_blockhash().await;
+    fixture
+        .process(&[batch_ix], &[&fixture.user_b.keypair])
+        .await
+        .unwrap();
+    assert_eq!(fixture.balance(fixture.user_b.token_a).await, 2000);
+}
+
+#[tokio::test]
+async fn test_keeper_settles_permissionless_escrow_for_tip() {
+    let mut fixture = SwapFixture::new(1000, 2000).await;
+    let program_id = fixture.program_id;
+    let (payer, user_a, user_b) = (&fixture.payer, &fixture.user_a, &fixture.user_b);
+    let keeper = Keypair::new();
+    let keeper_tip = 50_000;
+
+    fixture
+        .process(
+            &[
+                system_instruction::transfer(&payer.pubkey(), &user_a.pubkey(), 1_000_000_000),
+                system_instruction::transfer(&payer.pubkey(), &keeper.pubkey(), 1_000_000_000),
+            ],
+            &[],
+        )
+        .await
+        .unwrap();
+
+    let escrow_keypair = Keypair::new();
+    let escrow_account = escrow_keypair.pubkey();
+    let rent = fixture.banks_client.get_rent().await.unwrap();
+    let escrow_size = borsh::to_vec(&Escrow::default()).unwrap().len();
+    let escrow_rent = rent.minimum_balance(escrow_size);
+
+    //the tip is moved from user_a's wallet, so the system program is passed along
+    let init_ix = Instruction::new_with_borsh(
+        program_id,
+        &EscrowInstruction::InitEscrow {
+            token_a_mint: fixture.token_a_mint,
+            token_b_mint: fixture.token_b_mint,
+            amount_a: 1000,
+            amount_b: 2000,
+            hash_lock: None,
+            delegated_source: None,
+            maker_multisig: None,
+            permissionless_settlement: true,
+            keeper_tip,
+            auction: None,
+            oracle: None,
+            condition: None,
+            credential_issuer: None,
+            reference: None,
+        },
+        vec![
+            AccountMeta::new(user_a.pubkey(), true),
+            AccountMeta::new(escrow_account, false),
+            AccountMeta::new_readonly(solana_program::sysvar::rent::id(), false),
+            AccountMeta::new_readonly(config_pda(&program_id), false),
+            AccountMeta::new_readonly(solana_program::system_program::id(), false),
+        ],
+    );
+    fixture
+        .process(
+            &[
+                system_instruction::create_account(
+                    &payer.pubkey(),
+                    &escrow_account,
+                    escrow_rent,
+                    escrow_size as u64,
+                    &program_id,
+                ),
+                init_ix,
+            ],
+            &[&user_a.keypair, &escrow_keypair],
+        )
+        .await
+        .unwrap();
+
+    let escrow = fixture.open_vault(escrow_account).await;
+    let state = fixture.escrow(&escrow).await;
+    assert!(state.permissionless_settlement);
+    assert_eq!(state.keeper_tip.get(), keeper_tip);
+    let escrow_lamports = fixture
+        .banks_client
+        .get_balance(escrow_account)
+        .await
+        .unwrap();
+    assert_eq!(escrow_lamports, escrow_rent + keeper_tip);
+
+    fixture
+        .process(
+            &[fixture.maker_deposit_ix(&escrow, 1000)],
+            &[&user_a.keypair],
+        )
+        .await
+        .unwrap();
+
+    let keeper_settle_ix = complete_swap_ix(
+        program_id,
+        keeper.pubkey(),
+        escrow_account,
+        escrow.vault_token_a,
+        escrow.vault_token_b,
+        user_a.token_b,
+        user_b.token_a,
+    );
+
+    //the keeper can only settle once both sides are funded
+    assert!(
+        fixture
+            .process(std::slice::from_ref(&keeper_settle_ix), &[&keeper])
+            .await
+            .is_err()
+    );
+
+    fixture
+        .process(
+            &[fixture.taker_deposit_ix(&escrow, user_b, 2000)],
+            &[&user_b.keypair],
+        )
+        .await
+        .unwrap();
+
+    let keeper_lamports = fixture
+        .banks_client
+        .get_balance(keeper.pubkey())
+        .await
+        .unwrap();
+
+    fixture.refresh_blockhash().await;
+    fixture
+        .process(&[keeper_settle_ix], &[&keeper])
+        .await
+        .unwrap();
+
+    assert_eq!(fixture.balance(fixture.user_a.token_b).await, 2000);
+    assert_eq!(fixture.balance(fixture.user_b.token_a).await, 1000);
+
+    let state = fixture.escrow(&escrow).await;
+    assert_eq!(state.status, EscrowStatus::Completed);
+    assert_eq!(state.keeper_tip.get(), 0);
+
+    assert_eq!(
+        fixture
+            .banks_client
+            .get_balance(keeper.pubkey())
+            .await
+            .unwrap(),
+        keeper_lamports + keeper_tip
+    );
+    assert_eq!(
+        fixture
+            .banks_client
+            .get_balance(escrow_account)
+            .await
+            .unwrap(),
+        escrow_rent
+    );
+}
+
+#[tokio::test]
+async fn test_completing_deposit_settles_swap() {
+    let fixture = SwapFixture::new(1000, 2000).await;
+    let (user_a, user_b) = (&fixture.user_a, &fixture.user_b);
+    let escrow = fixture.open_swap(1000, 2000).await;
+
+    //the first deposit leaves the escrow open even with payout accounts passed
+    let maker_deposit_ix = with_settlement(
+        fixture.maker_deposit_ix(&escrow, 1000),
+        [
+            escrow.vault_pda,
+            escrow.vault_token_b,
+            user_a.token_b,
+            user_b.token_a,
+        ],
+    );
+    fixture
+        .process(&[maker_deposit_ix], &[&user_a.keypair])
+        .await
+        .unwrap();
+
+    let state = fixture.escrow(&escrow).await;
+    assert_eq!(state.status, EscrowStatus::Active);
+    assert_eq!(fixture.balance(escrow.vault_token_a).await, 1000);
+
+    //the taker's single deposit finishes the trade
+    let taker_deposit_ix = with_settlement(
+        fixture.taker_deposit_ix(&escrow, user_b, 2000),
+        [
+            escrow.vault_pda,
+            escrow.vault_token_a,
+            user_a.token_b,
+            user_b.token_a,
+        ],
+    );
+    fixture
+        .process(&[taker_deposit_ix], &[&user_b.keypair])
+        .await
+        .unwrap();
+
+    assert_eq!(fixture.balance(escrow.vault_token_a).await, 0);
+    assert_eq!(fixture.balance(escrow.vault_token_b).await, 0);
+    assert_eq!(fixture.balance(user_a.token_b).await, 2000);
+    assert_eq!(fixture.balance(user_b.token_a).await, 1000);
+
+    let state = fixture.escrow(&escrow).await;
+    assert_eq!(state.status, EscrowStatus::Completed);
+}
+
+//caller, ring, vault_authority, token_program, then a token account pair per leg
+fn ring_settlement_ix(
+    program_id: Pubkey,
+    instruction: &EscrowInstruction,
+    caller: Pubkey,
+    ring_account: Pubkey,
+    leg_accounts: &[(Pubkey, Pubkey)],
+) -> Instruction {
+    let (vault_pda, _) =
+        Pubkey::find_program_address(&[b"vault", ring_account.as_ref()], &program_id);
+
+    let mut accounts = vec![
+        AccountMeta::new(caller, true),
+        AccountMeta::new(ring_account, false),
+        AccountMeta::new_readonly(vault_pda, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+    ];
+    for (vault_token_account, counterpart_token_account) in leg_accounts {
+        accounts.push(AccountMeta::new(*vault_token_account, false));
+        accounts.push(AccountMeta::new(*counterpart_token_account, false));
+    }
+
+    Instruction::new_with_borsh(program_id, instruction, accounts)
+}
+
+#[tokio::test]
+async fn test_ring_trade_settles_every_leg() {
+    let fixture = SwapFixture::new(0, 0).await;
+    let (program_id, payer) = (fixture.program_id, &fixture.payer);
+
+    let parties = [Keypair::new(), Keypair::new(), Keypair::new()];
+    let amounts = [100, 200, 300];
+    let mints = [
+        fixture.token_a_mint,
+        fixture.token_b_mint,
+        create_mint(&fixture.banks_client, payer, fixture.recent_blockhash).await,
+    ];
+
+    //token_accounts[i][j] is party i's account for mint j, everyone starts with their own asset
+    let mut token_accounts = vec![];
+    for (i, party) in parties.iter().enumerate() {
+        let mut accounts = vec![];
+        for (j, mint) in mints.iter().enumerate() {
+            let amount = if i == j { amounts[i] } else { 0 };
+            accounts.push(fixture.create_ata(&party.pubkey(), mint, amount).await);
+        }
+        token_accounts.push(accounts);
+    }
+
+    let ring_account = Keypair::new();
+    let rent = fixture.banks_client.get_rent().await.unwrap();
+    let ring_size = borsh::to_vec(&RingTrade::default()).unwrap().len();
+
+    let participants = parties
+        .iter()
+        .enumerate()
+        .map(|(i, party)| RingLeg {
+            owner: party.pubkey(),
+            mint: mints[i],
+            amount: amounts[i],
+        })
+        .collect();
+
+    let init_ring_ix = Instruction::new_with_borsh(
+        program_id,
+        &EscrowInstruction::InitRing { participants },
+        vec![
+            AccountMeta::new(parties[0].pubkey(), true),
+            AccountMeta::new(ring_account.pubkey(), false),
+            AccountMeta::new_readonly(solana_program::sysvar::rent::id(), false),
+            AccountMeta::new_readonly(config_pda(&program_id), false),
+        ],
+    );
+    fixture
+        .process(
+            &[
+                system_instruction::create_account(
+                    &payer.pubkey(),
+                    &ring_account.pubkey(),
+                    rent.minimum_balance(ring_size),
+                    ring_size as u64,
+                    &program_id,
+                ),
+                init_ring_ix,
+            ],
+            &[&parties[0], &ring_account],
+        )
+        .await
+        .unwrap();
+    let ring_account = ring_account.pubkey();
+    let (vault_pda, _) =
+        Pubkey::find_program_address(&[b"vault", ring_account.as_ref()], &program_id);
+
+    let mut vault_token_accounts = vec![];
+    for mint in &mints {
+        vault_token_accounts.push(fixture.create_ata(&vault_pda, mint, 0).await);
+    }
+
+    //leg i pays party i + 1
+    let payout_accounts: Vec<(Pubkey, Pubkey)> = (0..parties.len())
+        .map(|i| {
+            (
+                vault_token_accounts[i],
+                token_accounts[(i + 1) % parties.len()][i],
+            )
+        })
+        .collect();
+
+    for (i, party) in parties.iter().enumerate() {
+        let deposit_ring_ix = Instruction::new_with_borsh(
+            program_id,
+            &EscrowInstruction::DepositRing { amount: amounts[i] },
+            vec![
+                AccountMeta::new(party.pubkey(), true),
+                AccountMeta::new(ring_account, false),
+                AccountMeta::new(token_accounts[i][i], false),
+                AccountMeta::new(vault_token_accounts[i], false),
+                AccountMeta::new_readonly(spl_token::id(), false),
+                AccountMeta::new_readonly(config_pda(&program_id), false),
+            ],
+        );
+        fixture.process(&[deposit_ring_ix], &[party]).await.unwrap();
+
+        //nothing moves until the last leg is funded
+        if i == 0 {
+            assert!(
+                fixture
+                    .process(
+                        &[ring_settlement_ix(
+                            program_id,
+                            &EscrowInstruction::CompleteRing,
+                            party.pubkey(),
+                            ring_account,
+                            &payout_accounts,
+                        )],
+                        &[party],
+                    )
+                    .await
+                    .is_err()
+            );
+        }
+    }
+
+    fixture
+        .process(
+            &[ring_settlement_ix(
+                program_id,
+                &EscrowInstruction::CompleteRing,
+                parties[1].pubkey(),
+                ring_account,
+                &payout_accounts,
+            )],
+            &[&parties[1]],
+        )
+        .await
+        .unwrap();
+
+    for (i, party_accounts) in token_accounts.iter().enumerate() {
+        let previous = (i + parties.len() - 1) % parties.len();
+        assert_eq!(fixture.balance(party_accounts[i]).await, 0);
+        assert_eq!(
+            fixture.balance(party_accounts[previous]).await,
+            amounts[previous]
+        );
+    }
+
+    let ring_data = fixture
+        .banks_client
+        .get_account(ring_account)
+        .await
+        .unwrap()
+        .unwrap();
+    let ring = RingTrade::try_from_slice(&ring_data.data).unwrap();
+    assert_eq!(ring.status, EscrowStatus::Completed);
+}
+
+#[tokio::test]
+async fn test_ring_trade_cancel_refunds_funded_legs() {
+    let fixture = SwapFixture::new(0, 0).await;
+    let (program_id, payer) = (fixture.program_id, &fixture.payer);
+
+    let parties = [
+        fixture.create_user(500, 0).await,
+        fixture.create_user(500, 0).await,
+        fixture.create_user(500, 0).await,
+    ];
+    let mint = fixture.token_a_mint;
+
+    let ring_account = Keypair::new();
+    let rent = fixture.banks_client.get_rent().await.unwrap();
+    let ring_size = borsh::to_vec(&RingTrade::default()).unwrap().len();
+
+    let participants = parties
+        .iter()
+        .map(|party| RingLeg {
+            owner: party.pubkey(),
+            mint,
+            amount: 500,
+        })
+        .collect();
+
+    fixture
+        .process(
+            &[
+                system_instruction::create_account(
+                    &payer.pubkey(),
+                    &ring_account.pubkey(),
+                    rent.minimum_balance(ring_size),
+                    ring_size as u64,
+                    &program_id,
+                ),
+                Instruction::new_with_borsh(
+                    program_id,
+                    &EscrowInstruction::InitRing { participants },
+                    vec![
+                        AccountMeta::new(parties[2].pubkey(), true),
+                        AccountMeta::new(ring_account.pubkey(), false),
+                        AccountMeta::new_readonly(solana_program::sysvar::rent::id(), false),
+                        AccountMeta::new_readonly(config_pda(&program_id), false),
+                    ],
+                ),
+            ],
+            &[&parties[2].keypair, &ring_account],
+        )
+        .await
+        .unwrap();
+    let ring_account = ring_account.pubkey();
+    let (vault_pda, _) =
+        Pubkey::find_program_address(&[b"vault", ring_account.as_ref()], &program_id);
+    let vault_token_account = fixture.create_ata(&vault_pda, &mint, 0).await;
+
+    //only the first party funds their leg
+    fixture
+        .process(
+            &[Instruction::new_with_borsh(
+                program_id,
+                &EscrowInstruction::DepositRing { amount: 500 },
+                vec![
+                    AccountMeta::new(parties[0].pubkey(), true),
+                    AccountMeta::new(ring_account, false),
+                    AccountMeta::new(parties[0].token_a, false),
+                    AccountMeta::new(vault_token_account, false),
+                    AccountMeta::new_readonly(spl_token::id(), false),
+                    AccountMeta::new_readonly(config_pda(&program_id), false),
+                ],
+            )],
+            &[&parties[0].keypair],
+        )
+        .await
+        .unwrap();
+
+    let refund_accounts: Vec<(Pubkey, Pubkey)> = parties
+        .iter()
+        .map(|party| (vault_token_account, party.token_a))
+        .collect();
+
+    //a stranger cannot cancel someone else's ring
+    let stranger = &fixture.stranger;
+    assert!(
+        fixture
+            .process(
+                &[ring_settlement_ix(
+                    program_id,
+                    &EscrowInstruction::CancelRing,
+                    stranger.pubkey(),
+                    ring_account,
+                    &refund_accounts,
+                )],
+                &[&stranger.keypair],
+            )
+            .await
+            .is_err()
+    );
+
+    fixture
+        .process(
+            &[ring_settlement_ix(
+                program_id,
+                &EscrowInstruction::CancelRing,
+                parties[1].pubkey(),
+                ring_account,
+                &refund_accounts,
+            )],
+            &[&parties[1].keypair],
+        )
+        .await
+        .unwrap();
+
+    for party in &parties {
+        assert_eq!(fixture.balance(party.token_a).await, 500);
+    }
+    assert_eq!(fixture.balance(vault_token_account).await, 0);
+
+    let ring_data = fixture
+        .banks_client
+        .get_account(ring_account)
+        .await
+        .unwrap()
+        .unwrap();
+    let ring = RingTrade::try_from_slice(&ring_data.data).unwrap();
+    assert_eq!(ring.status, EscrowStatus::Cancelled);
+}
+
+fn place_bid_ix(
+    program_id: Pubkey,
+    bidder: Pubkey,
+    escrow_account: Pubkey,
+    bidder_token_b: Pubkey,
+    vault_token_b: Pubkey,
+    amount: u64,
+) -> Instruction {
+    let (bid_pda, _) = Pubkey::find_program_address(
+        &[b"bid", escrow_account.as_ref(), bidder.as_ref()],
+        &program_id,
+    );
+
+    Instruction::new_with_borsh(
+        program_id,
+        &EscrowInstruction::PlaceBid { amount },
+        vec![
+            AccountMeta::new(bidder, true),
+            AccountMeta::new(escrow_account, false),
+            AccountMeta::new(bid_pda, false),
+            AccountMeta::new(bidder_token_b, false),
+            AccountMeta::new(vault_token_b, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(solana_program::system_program::id(), false),
+            AccountMeta::new_readonly(config_pda(&program_id), false),
+        ],
+    )
+}
+
+fn refund_bid_ix(
+    program_id: Pubkey,
+    bidder: Pubkey,
+    escrow_account: Pubkey,
+    vault_token_b: Pubkey,
+    bidder_token_b: Pubkey,
+) -> Instruction {
+    let (bid_pda, _) = Pubkey::find_program_address(
+        &[b"bid", escrow_account.as_ref(), bidder.as_ref()],
+        &program_id,
+    );
+    let (vault_pda, _) =
+        Pubkey::find_program_address(&[b"vault", escrow_account.as_ref()], &program_id);
+
+    Instruction::new_with_borsh(
+        program_id,
+        &EscrowInstruction::RefundBid,
+        vec![
+            AccountMeta::new(bidder, true),
+            AccountMeta::new_readonly(escrow_account, false),
+            AccountMeta::new(bid_pda, false),
+            AccountMeta::new_readonly(vault_pda, false),
+            AccountMeta::new(vault_token_b, false),
+            AccountMeta::new(bidder_token_b, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+        ],
+    )
+}
+
+#[tokio::test]
+async fn test_english_auction_refunds_outbid_bidders() {
+    let program_id = Pubkey::new_unique();
+    let program_test =
+        ProgramTest::new("solana_escrow", program_id, processor!(process_instruction));
+    let (mut fixture, context) =
+        SwapFixture::start_with_context(program_test, program_id, 1000, 500).await;
+    let payer = &fixture.payer;
+    let (seller, bidder_1, bidder_2) = (&fixture.user_a, &fixture.stranger, &fixture.user_b);
+
+    //bidders pay rent for their bid records
+    fixture
+        .process(
+            &[
+                system_instruction::transfer(&payer.pubkey(), &bidder_1.pubkey(), 1_000_000_000),
+                system_instruction::transfer(&payer.pubkey(), &bidder_2.pubkey(), 1_000_000_000),
+            ],
+            &[],
+        )
+        .await
+        .unwrap();
+
+    let mut clock: Clock = fixture.banks_client.get_sysvar().await.unwrap();
+    let auction = Auction {
+        style: AuctionStyle::English,
+        start_price: 100.into(),
+        end_price: 0.into(),
+        min_increment: 10.into(),
+        start_time: clock.unix_timestamp.into(),
+        end_time: (clock.unix_timestamp + 100).into(),
+    };
+
+    let escrow = fixture
+        .open_escrow(&EscrowInstruction::InitEscrow {
+            token_a_mint: fixture.token_a_mint,
+            token_b_mint: fixture.token_b_mint,
+            amount_a: 1000,
+            amount_b: 0,
+            hash_lock: None,
+            delegated_source: None,
+            maker_multisig: None,
+            permissionless_settlement: false,
+            keeper_tip: 0,
+            auction: Some(auction),
+            oracle: None,
+            condition: None,
+            credential_issuer: None,
+            reference: None,
+        })
+        .await;
+    let escrow_account = escrow.escrow_account;
+
+    let state = fixture.escrow(&escrow).await;
+    assert_eq!(state.kind, EscrowKind::EnglishAuction);
+    assert_eq!(state.auction, auction);
+
+    fixture
+        .process(
+            &[fixture.maker_deposit_ix(&escrow, 1000)],
+            &[&seller.keypair],
+        )
+        .await
+        .unwrap();
+
+    let bid_ix = |bidder: &User, amount| {
+        place_bid_ix(
+            program_id,
+            bidder.pubkey(),
+            escrow_account,
+            bidder.token_b,
+            escrow.vault_token_b,
+            amount,
+        )
+    };
+    let refund_ix = |bidder: &User| {
+        refund_bid_ix(
+            program_id,
+            bidder.pubkey(),
+            escrow_account,
+            escrow.vault_token_b,
+            bidder.token_b,
+        )
+    };
+
+    //lamports sent to a bid record's address must not keep its bidder out
+    let (bid_1, _) = Pubkey::find_program_address(
+        &[b"bid", escrow_account.as_ref(), bidder_1.pubkey().as_ref()],
+        &program_id,
+    );
+    let rent = fixture.banks_client.get_rent().await.unwrap();
+    fixture
+        .process(
+            &[
+                system_instruction::transfer(&payer.pubkey(), &bid_1, rent.minimum_balance(0)),
+                bid_ix(bidder_1, 100),
+            ],
+            &[&bidder_1.keypair],
+        )
+        .await
+        .unwrap();
+
+    //a raise below min_increment is rejected
+    assert!(
+        fixture
+            .process(&[bid_ix(bidder_2, 105)], &[&bidder_2.keypair])
+            .await
+            .is_err()
+    );
+
+    fixture
+        .process(&[bid_ix(bidder_2, 150)], &[&bidder_2.keypair])
+        .await
+        .unwrap();
+
+    let state = fixture.escrow(&escrow).await;
+    assert_eq!(state.user_b, bidder_2.pubkey());
+    assert_eq!(state.amount_b.get(), 150);
+    assert_eq!(fixture.balance(escrow.vault_token_b).await, 250);
+
+    //the leading bid is locked, the outbid one comes back
+    assert!(
+        fixture
+            .process(&[refund_ix(bidder_2)], &[&bidder_2.keypair])
+            .await
+            .is_err()
+    );
+
+    fixture
+        .process(&[refund_ix(bidder_1)], &[&bidder_1.keypair])
+        .await
+        .unwrap();
+    assert_eq!(fixture.balance(bidder_1.token_b).await, 500);
+
+    let bid_data = fixture
+        .banks_client
+        .get_account(bid_1)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(Bid::try_from_slice(&bid_data.data).unwrap().amount, 0);
+
+    let settle_ix = fixture.complete_swap_ix(&escrow, seller, bidder_2);
+
+    //bidding is still open
+    assert!(
+        fixture
+            .process(std::slice::from_ref(&settle_ix), &[&seller.keypair])
+            .await
+            .is_err()
+    );
+
+    clock.unix_timestamp = auction.end_time.get();
+    context.set_sysvar(&clock);
+
+    //no more bids once the auction closed
+    assert!(
+        fixture
+            .process(&[bid_ix(bidder_1, 200)], &[&bidder_1.keypair])
+            .await
+            .is_err()
+    );
+
+    fixture.refresh_blockhash().await;
+    fixture
+        .process(&[settle_ix], &[&fixture.user_a.keypair])
+        .await
+        .unwrap();
+
+    assert_eq!(fixture.balance(fixture.user_a.token_b).await, 150);
+    assert_eq!(fixture.balance(fixture.user_b.token_a).await, 1000);
+    assert_eq!(fixture.balance(fixture.user_b.token_b).await, 350);
+
+    let state = fixture.escrow(&escrow).await;
+    assert_eq!(state.status, EscrowStatus::Completed);
+}
+
+#[tokio::test]
+async fn test_dutch_auction_sells_at_decayed_price() {
+    let program_id = Pubkey::new_unique();
+    let program_test =
+        ProgramTest::new("solana_escrow", program_id, processor!(process_instruction));
+    let (fixture, context) =
+        SwapFixture::start_with_context(program_test, program_id, 1000, 1000).await;
+    let (seller, buyer) = (&fixture.user_a, &fixture.user_b);
+
+    let mut clock: Clock = fixture.banks_client.get_sysvar().await.unwrap();
+    let auction = Auction {
+        style: AuctionStyle::Dutch,
+        start_price: 1000.into(),
+        end_price: 200.into(),
+        min_increment: 0.into(),
+        start_time: clock.unix_timestamp.into(),
+        end_time: (clock.unix_timestamp + 100).into(),
+    };
+
+    let escrow = fixture
+        .open_escrow(&EscrowInstruction::InitEscrow {
+            token_a_mint: fixture.token_a_mint,
+            token_b_mint: fixture.token_b_mint,
+            amount_a: 1000,
+            amount_b: 0,
+            hash_lock: None,
+            delegated_source: None,
+            maker_multisig: None,
+            permissionless_settlement: false,
+            keeper_tip: 0,
+            auction: Some(auction),
+            oracle: None,
+            condition: None,
+            credential_issuer: None,
+            reference: None,
+        })
+        .await;
+
+    fixture
+        .process(
+            &[fixture.maker_deposit_ix(&escrow, 1000)],
+            &[&seller.keypair],
+        )
+        .await
+        .unwrap();
+
+    //halfway through the price is 600
+    clock.unix_timestamp = auction.start_time.get() + 50;
+    context.set_sysvar(&clock);
+    assert_eq!(auction.dutch_price(clock.unix_timestamp).unwrap(), 600);
+
+    assert!(
+        fixture
+            .process(
+                &[fixture.taker_deposit_ix(&escrow, buyer, 500)],
+                &[&buyer.keypair],
+            )
+            .await
+            .is_err()
+    );
+
+    //the buyer's amount is a ceiling, only the current price is taken
+    fixture
+        .process(
+            &[fixture.taker_deposit_ix(&escrow, buyer, 700)],
+            &[&buyer.keypair],
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(fixture.balance(buyer.token_b).await, 400);
+    let state = fixture.escrow(&escrow).await;
+    assert_eq!(state.amount_b.get(), 600);
+    assert_eq!(state.user_b, buyer.pubkey());
+
+    fixture
+        .process(
+            &[fixture.complete_swap_ix(&escrow, buyer, buyer)],
+            &[&buyer.keypair],
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(fixture.balance(seller.token_b).await, 600);
+    assert_eq!(fixture.balance(buyer.token_a).await, 1000);
+}
+
+fn market_pda(program_id: &Pubkey, token_a_mint: &Pubkey, token_b_mint: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"market", token_a_mint.as_ref(), token_b_mint.as_ref()],
+        program_id,
+    )
+    .0
+}
+
+async fn read_market(banks_client: &BanksClient, market: Pubkey) -> Market {
+    let account = banks_client.get_account(market).await.unwrap().unwrap();
+
+    Market::try_from_slice(&account.data).unwrap()
+}
+
+#[tokio::test]
+async fn test_market_keeps_open_offers_sorted_by_price() {
+    let fixture = SwapFixture::new(0, 0).await;
+    let (program_id, payer) = (fixture.program_id, &fixture.payer);
+    let (token_a_mint, token_b_mint) = (fixture.token_a_mint, fixture.token_b_mint);
+    let market = market_pda(&program_id, &token_a_mint, &token_b_mint);
+    let rent = fixture.banks_client.get_rent().await.unwrap();
+
+    //lamports sent to the market address first must not keep the pair from getting a market
+    fixture
+        .process(
+            &[
+                system_instruction::transfer(&payer.pubkey(), &market, rent.minimum_balance(0)),
+                Instruction::new_with_borsh(
+                    program_id,
+                    &EscrowInstruction::InitMarket {
+                        token_a_mint,
+                        token_b_mint,
+                    },
+                    vec![
+                        AccountMeta::new(payer.pubkey(), true),
+                        AccountMeta::new(market, false),
+                        AccountMeta::new_readonly(solana_program::system_program::id(), false),
+                    ],
+                ),
+            ],
+            &[],
+        )
+        .await
+        .unwrap();
+
+    //prices of 3, 2 and 2 token b per token a
+    let terms = [(100, 300), (100, 200), (50, 100)];
+    let mut makers = vec![];
+    let mut escrows = vec![];
+    for (amount_a, amount_b) in terms {
+        let user_a = fixture.create_user(amount_a, 0).await;
+        let escrow_account = Keypair::new();
+
+        let init_ix = |with_market: bool| {
+            let mut ix = init_escrow_ix(
+                program_id,
+                user_a.pubkey(),
+                escrow_account.pubkey(),
+                &swap_terms(token_a_mint, token_b_mint, amount_a, amount_b),
+            );
+            if with_market {
+                ix.accounts.push(AccountMeta::new(market, false));
+            }
+            ix
+        };
+        let create_escrow_ix = create_escrow_account_ix(
+            &payer.pubkey(),
+            &escrow_account.pubkey(),
+            &program_id,
+            &rent,
+        );
+
+        //an unfunded swap cannot be listed
+        assert!(
+            fixture
+                .process(
+                    &[create_escrow_ix.clone(), init_ix(true)],
+                    &[&user_a.keypair, &escrow_account],
+                )
+                .await
+                .is_err()
+        );
+        fixture
+            .process(
+                &[create_escrow_ix, init_ix(false)],
+                &[&user_a.keypair, &escrow_account],
+            )
+            .await
+            .unwrap();
+
+        let escrow = fixture.open_vault(escrow_account.pubkey()).await;
+        assert!(!fixture.escrow(&escrow).await.listed);
+
+        //user_a's deposit puts the offer on the book
+        let mut deposit = deposit_ix(
+            program_id,
+            user_a.pubkey(),
+            escrow.escrow_account,
+            user_a.token_a,
+            escrow.vault_token_a,
+            amount_a,
+            token_a_mint,
+        );
+        deposit.accounts.push(AccountMeta::new(market, false));
+        fixture
+            .process(&[deposit], &[&user_a.keypair])
+            .await
+            .unwrap();
+
+        assert!(fixture.escrow(&escrow).await.listed);
+        makers.push(user_a);
+        escrows.push(escrow);
+    }
+
+    //best price first, equal prices keep their listing order
+    let book: Vec<Pubkey> = read_market(&fixture.banks_client, market)
+        .await
+        .offers()
+        .iter()
+        .map(|offer| offer.escrow)
+        .collect();
+    assert_eq!(
+        book,
+        vec![
+            escrows[1].escrow_account,
+            escrows[2].escrow_account,
+            escrows[0].escrow_account,
+        ]
+    );
+
+    //a listed escrow cannot leave without updating the book
+    let mut cancel = cancel_ix(
+        program_id,
+        makers[1].pubkey(),
+        escrows[1].escrow_account,
+        escrows[1].vault_token_a,
+        escrows[1].vault_token_b,
+        makers[1].token_a,
+        makers[1].token_b,
+    );
+    assert!(
+        fixture
+            .process(std::slice::from_ref(&cancel), &[&makers[1].keypair])
+            .await
+            .is_err()
+    );
+
+    cancel.accounts.push(AccountMeta::new(market, false));
+    fixture
+        .process(&[cancel], &[&makers[1].keypair])
+        .await
+        .unwrap();
+    assert_eq!(fixture.balance(makers[1].token_a).await, 100);
+
+    let market_state = read_market(&fixture.banks_client, market).await;
+    assert_eq!(market_state.offer_count, 2);
+    assert_eq!(market_state.offers()[0].escrow, escrows[2].escrow_account);
+    assert_eq!(market_state.offers()[1].escrow, escrows[0].escrow_account);
+
+    let escrow = fixture.escrow(&escrows[1]).await;
+    assert_eq!(escrow.status, EscrowStatus::Cancelled);
+    assert!(!escrow.listed);
+}
+
+#[test]
+fn test_full_market_evicts_its_worst_offer_for_a_better_one() {
+    let offer = |amount_b: u64| MarketOffer {
+        escrow: Pubkey::new_unique(),
+        amount_a: 1,
+        amount_b,
+    };
+
+    //a book stuffed with dust offers at the worst possible price
+    let mut market = Market::default();
+    for _ in 0..MAX_MARKET_OFFERS {
+        assert!(market.insert(offer(u64::MAX)));
+    }
+    let worst = market.offers()[MAX_MARKET_OFFERS - 1].escrow;
+
+    //an offer no better than the worst one still finds the book full
+    assert!(!market.insert(offer(u64::MAX)));
+
+    let better = offer(2);
+    assert!(market.insert(better));
+    assert_eq!(market.offers().len(), MAX_MARKET_OFFERS);
+    assert_eq!(market.offers()[0], better);
+    assert!(market.offers().iter().all(|listed| listed.escrow != worst));
+
+    //removing the evicted escrow is a no-op
+    assert!(!market.remove(&worst));
+    assert_eq!(market.offers().len(), MAX_MARKET_OFFERS);
+}
+
+//minimal pyth v2 price account with a trading aggregate
+fn mock_pyth_price_account(price: i64, expo: i32, publish_time: i64) -> Vec<u8> {
+    let mut data = vec![0u8; 240];
+    data[0..4].copy_from_slice(&0xa1b2_c3d4u32.to_le_bytes());
+    data[4..8].copy_from_slice(&2u32.to_le_bytes());
+    data[8..12].copy_from_slice(&3u32.to_le_bytes());
+    data[20..24].copy_from_slice(&expo.to_le_bytes());
+    data[96..104].copy_from_slice(&publish_time.to_le_bytes());
+    data[208..216].copy_from_slice(&price.to_le_bytes());
+    data[224..228].copy_from_slice(&1u32.to_le_bytes());
+    data
+}
+
+#[tokio::test]
+async fn test_oracle_priced_escrow_checks_staleness_and_band() {
+    let program_id = Pubkey::new_unique();
+    let mut program_test =
+        ProgramTest::new("solana_escrow", program_id, processor!(process_instruction));
+
+    //2.5 token b per token a, published at 1_000_000
+    let feed = Pubkey::new_unique();
+    let publish_time = 1_000_000;
+    program_test.add_account(
+        feed,
+        solana_sdk::account::Account {
+            lamports: 1_000_000_000,
+            data: mock_pyth_price_account(250_000_000, -8, publish_time),
+            owner: Pubkey::new_unique(),
+            executable: false,
+            rent_epoch: 0,
+        },
+    );
+
+    let (mut fixture, context) =
+        SwapFixture::start_with_context(program_test, program_id, 0, 10_000).await;
+
+    let mut clock: Clock = fixture.banks_client.get_sysvar().await.unwrap();
+    clock.unix_timestamp = publish_time + 30;
+    context.set_sysvar(&clock);
+
+    //the first band contains the price, the second one tops out below it
+    let mut escrows = vec![];
+    for max_price in [3_000_000_000, 2_000_000_000] {
+        let user_a = fixture.create_user(1000, 0).await;
+
+        let mut terms = swap_terms(fixture.token_a_mint, fixture.token_b_mint, 1000, 0);
+        if let EscrowInstruction::InitEscrow { oracle, .. } = &mut terms {
+            *oracle = Some(Box::new(PriceOracle {
+                feed,
+                source: OracleSource::Pyth,
+                spread_bps: 100.into(),
+                max_staleness: 60.into(),
+                min_price: 1_000_000_000.into(),
+                max_price: max_price.into(),
+                token_a_decimals: 9,
+                token_b_decimals: 9,
+            }));
+        }
+        let escrow = fixture.open_escrow_as(&user_a, &terms).await;
+        assert_eq!(fixture.escrow(&escrow).await.kind, EscrowKind::OraclePriced);
+
+        fixture
+            .process(
+                &[deposit_ix(
+                    program_id,
+                    user_a.pubkey(),
+                    escrow.escrow_account,
+                    user_a.token_a,
+                    escrow.vault_token_a,
+                    1000,
+                    fixture.token_a_mint,
+                )],
+                &[&user_a.keypair],
+            )
+            .await
+            .unwrap();
+
+        escrows.push((escrow, user_a));
+    }
+
+    let taker_deposit_ix = |fixture: &SwapFixture, index: usize, with_payout: bool| {
+        let (escrow, user_a) = &escrows[index];
+        let user_b = &fixture.user_b;
+        let mut ix = fixture.taker_deposit_ix(escrow, user_b, 3000);
+        if with_payout {
+            ix = with_settlement(
+                ix,
+                [
+                    escrow.vault_pda,
+                    escrow.vault_token_a,
+                    user_a.token_b,
+                    user_b.token_a,
+                ],
+            );
+        }
+        ix.accounts.push(AccountMeta::new_readonly(feed, false));
+        ix
+    };
+
+    //the price is only used when the swap settles in the same instruction
+    assert!(
+        fixture
+            .process(
+                &[taker_deposit_ix(&fixture, 0, false)],
+                &[&fixture.user_b.keypair],
+            )
+            .await
+            .is_err()
+    );
+
+    //2.5 is above the second maker's band
+    assert!(
+        fixture
+            .process(
+                &[taker_deposit_ix(&fixture, 1, true)],
+                &[&fixture.user_b.keypair],
+            )
+            .await
+            .is_err()
+    );
+
+    //a price older than max_staleness is refused
+    clock.unix_timestamp = publish_time + 61;
+    context.set_sysvar(&clock);
+    assert!(
+        fixture
+            .process(
+                &[taker_deposit_ix(&fixture, 0, true)],
+                &[&fixture.user_b.keypair],
+            )
+            .await
+            .is_err()
+    );
+
+    clock.unix_timestamp = publish_time + 30;
+    context.set_sysvar(&clock);
+    fixture.refresh_blockhash().await;
+    fixture
+        .process(
+            &[taker_deposit_ix(&fixture, 0, true)],
+            &[&fixture.user_b.keypair],
+        )
+        .await
+        .unwrap();
+
+    //1000 * 2.5 plus the 1% spread
+    let (escrow, user_a) = &escrows[0];
+    assert_eq!(fixture.balance(user_a.token_b).await, 2525);
+    assert_eq!(fixture.balance(fixture.user_b.token_a).await, 1000);
+    assert_eq!(fixture.balance(fixture.user_b.token_b).await, 10_000 - 2525);
+
+    let state = fixture.escrow(escrow).await;
+    assert_eq!(state.status, EscrowStatus::Completed);
+    assert_eq!(state.amount_b.get(), 2525);
+}
+
+#[tokio::test]
+async fn test_oracle_escrow_rejects_unsupported_decimals() {
+    let fixture = SwapFixture::new(1000, 0).await;
+    let user_a = &fixture.user_a;
+    let rent = fixture.banks_client.get_rent().await.unwrap();
+
+    //past 18 decimals the powers of ten in the taker amount no longer fit
+    for (token_a_decimals, token_b_decimals) in [(19, 9), (9, 19), (u8::MAX, u8::MAX)] {
+        let mut terms = swap_terms(fixture.token_a_mint, fixture.token_b_mint, 1000, 0);
+        if let EscrowInstruction::InitEscrow { oracle, .. } = &mut terms {
+            *oracle = Some(Box::new(PriceOracle {
+                feed: Pubkey::new_unique(),
+                source: OracleSource::Pyth,
+                spread_bps: 0.into(),
+                max_staleness: 60.into(),
+                min_price: 1.into(),
+                max_price: u64::MAX.into(),
+                token_a_decimals,
+                token_b_decimals,
+            }));
+        }
+
+        let escrow_account = Keypair::new();
+        assert!(
+            fixture
+                .process(
+                    &[
+                        create_escrow_account_ix(
+                            &fixture.payer.pubkey(),
+                            &escrow_account.pubkey(),
+                            &fixture.program_id,
+                            &rent,
+                        ),
+                        init_escrow_ix(
+                            fixture.program_id,
+                            user_a.pubkey(),
+                            escrow_account.pubkey(),
+                            &terms,
+                        ),
+                    ],
+                    &[&user_a.keypair, &escrow_account],
+                )
+                .await
+                .is_err()
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_deposit_rejects_changed_terms() {
+    let fixture = SwapFixture::new(0, 2000).await;
+    let swap = fixture.open_swap(1000, 2000).await;
+
+    //the maker cuts amount_a before the taker's deposit lands
+    let update_ix = maker_terms_ix(
+        fixture.program_id,
+        &EscrowInstruction::UpdateTerms {
+            amount_a: 500,
+            amount_b: 2000,
+        },
+        fixture.user_a.pubkey(),
+        swap.escrow_account,
+        swap.vault_token_a,
+        fixture.user_a.token_a,
+    );
+    fixture
+        .process(&[update_ix], &[&fixture.user_a.keypair])
+        .await
+        .unwrap();
+
+    let taker_deposit_ix = |expected_mint_a, expected_amount_a_min| {
+        with_expected_terms(
+            fixture.taker_deposit_ix(&swap, &fixture.user_b, 2000),
+            expected_mint_a,
+            expected_amount_a_min,
+        )
+    };
+
+    assert!(
+        fixture
+            .process(
+                &[taker_deposit_ix(fixture.token_a_mint, 1000)],
+                &[&fixture.user_b.keypair],
+            )
+            .await
+            .is_err()
+    );
+
+    //a different token a than the taker expects
+    assert!(
+        fixture
+            .process(
+                &[taker_deposit_ix(fixture.token_b_mint, 500)],
+                &[&fixture.user_b.keypair],
+            )
+            .await
+            .is_err()
+    );
+
+    assert_eq!(fixture.balance(fixture.user_b.token_b).await, 2000);
+
+    fixture
+        .process(
+            &[taker_deposit_ix(fixture.token_a_mint, 500)],
+            &[&fixture.user_b.keypair],
+        )
+        .await
+        .unwrap();
+
+    let escrow = fixture.escrow(&swap).await;
+    assert!(escrow.token_b_deposited);
+    assert_eq!(fixture.balance(swap.vault_token_b).await, 2000);
+}
+
+#[tokio::test]
+async fn test_cancelled_escrow_takes_no_deposits() {
+    let mut fixture = SwapFixture::new(2000, 2000).await;
+    let swap = fixture.open_swap(1000, 2000).await;
+
+    fixture
+        .process(
+            &[fixture.maker_deposit_ix(&swap, 1000)],
+            &[&fixture.user_a.keypair],
+        )
+        .await
+        .unwrap();
+    fixture
+        .process(
+            &[fixture.cancel_ix(&swap, &fixture.user_a, &fixture.user_b)],
+            &[&fixture.user_a.keypair],
+        )
+        .await
+        .unwrap();
+    assert_eq!(fixture.escrow(&swap).await.status, EscrowStatus::Cancelled);
+
+    //the refund cleared the deposit flags, nothing could ever take these tokens out again
+    fixture.refresh_blockhash().await;
+    assert!(
+        fixture
+            .process(
+                &[fixture.maker_deposit_ix(&swap, 1000)],
+                &[&fixture.user_a.keypair],
+            )
+            .await
+            .is_err()
+    );
+    assert!(
+        fixture
+            .process(
+                &[fixture.taker_deposit_ix(&swap, &fixture.user_b, 2000)],
+                &[&fixture.user_b.keypair],
+            )
+            .await
+            .is_err()
+    );
+
+    assert_eq!(fixture.balance(fixture.user_a.token_a).await, 2000);
+    assert_eq!(fixture.balance(fixture.user_b.token_b).await, 2000);
+    assert_eq!(fixture.balance(swap.vault_token_a).await, 0);
+    assert_eq!(fixture.balance(swap.vault_token_b).await, 0);
+}
+
+#[tokio::test]
+async fn test_funded_escrow_keeps_its_vault_maker_and_refund_account() {
+    let fixture = SwapFixture::new(1000, 0).await;
+    let user_a = &fixture.user_a;
+    let attacker = &fixture.stranger;
+    let swap = fixture.open_swap(1000, 2000).await;
+
+    //a deposit into anything but the vault does not count
+    let misdirected_deposit = deposit_ix(
+        fixture.program_id,
+        user_a.pubkey(),
+        swap.escrow_account,
+        user_a.token_a,
+        attacker.token_a,
+        1000,
+        fixture.token_a_mint,
+    );
+    assert!(
+        fixture
+            .process(&[misdirected_deposit], &[&user_a.keypair])
+            .await
+            .is_err()
+    );
+
+    fixture
+        .process(&[fixture.maker_deposit_ix(&swap, 1000)], &[&user_a.keypair])
+        .await
+        .unwrap();
+
+    //initializing the funded escrow again would hand its vault to the attacker
+    let reinit_ix = init_escrow_ix(
+        fixture.program_id,
+        attacker.pubkey(),
+        swap.escrow_account,
+        &swap_terms(fixture.token_a_mint, fixture.token_b_mint, 1000, 2000),
+    );
+    assert!(
+        fixture
+            .process(&[reinit_ix], &[&attacker.keypair])
+            .await
+            .is_err()
+    );
+    assert_eq!(fixture.escrow(&swap).await.user_a, user_a.pubkey());
+
+    //the refund only goes back to user_a's own token account
+    let misdirected_cancel = cancel_ix(
+        fixture.program_id,
+        user_a.pubkey(),
+        swap.escrow_account,
+        swap.vault_token_a,
+        swap.vault_token_b,
+        attacker.token_a,
+        attacker.token_b,
+    );
+    assert!(
+        fixture
+            .process(&[misdirected_cancel], &[&user_a.keypair])
+            .await
+            .is_err()
+    );
+    //a rejected refund leaves the escrow unlocked, the real one below still goes through
+    assert!(!fixture.escrow(&swap).await.settlement_locked);
+
+    fixture
+        .process(
+            &[fixture.cancel_ix(&swap, user_a, &fixture.user_b)],
+            &[&user_a.keypair],
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(fixture.balance(user_a.token_a).await, 1000);
+    assert_eq!(fixture.balance(attacker.token_a).await, 0);
+    assert_eq!(fixture.escrow(&swap).await.status, EscrowStatus::Cancelled);
+}
+
+#[tokio::test]
+async fn test_settlement_and_refund_cannot_be_replayed() {
+    let mut fixture = SwapFixture::new(2000, 2000).await;
+    let user_a = &fixture.user_a.keypair;
+    let user_b = &fixture.user_b.keypair;
+
+    let swap = fixture.open_swap(1000, 2000).await;
+    fixture
+        .process(&[fixture.maker_deposit_ix(&swap, 1000)], &[user_a])
+        .await
+        .unwrap();
+    fixture
+        .process(
+            &[fixture.taker_deposit_ix(&swap, &fixture.user_b, 2000)],
+            &[user_b],
+        )
+        .await
+        .unwrap();
+
+    let complete_ix = fixture.complete_swap_ix(&swap, &fixture.user_b, &fixture.user_b);
+    fixture
+        .process(std::slice::from_ref(&complete_ix), &[user_b])
+        .await
+        .unwrap();
+
+    let escrow = fixture.escrow(&swap).await;
+    assert_eq!(escrow.status, EscrowStatus::Completed);
+    assert!(escrow.settlement_locked);
+    assert!(!escrow.token_a_deposited && !escrow.token_b_deposited);
+
+    //somebody refills the vault, the settled escrow must not pay out of it again
+    fixture
+        .create_ata(&swap.vault_pda, &fixture.token_a_mint, 1000)
+        .await;
+    fixture
+        .create_ata(&swap.vault_pda, &fixture.token_b_mint, 2000)
+        .await;
+
+    fixture.refresh_blockhash().await;
+    let user_a = &fixture.user_a.keypair;
+    let user_b = &fixture.user_b.keypair;
+    assert!(fixture.process(&[complete_ix], &[user_b]).await.is_err());
+    assert!(
+        fixture
+            .process(
+                &[fixture.cancel_ix(&swap, &fixture.user_a, &fixture.user_b)],
+                &[user_a],
+            )
+            .await
+            .is_err()
+    );
+    assert!(
+        fixture
+            .process(&[fixture.maker_deposit_ix(&swap, 1000)], &[user_a])
+            .await
+            .is_err()
+    );
+
+    assert_eq!(fixture.balance(swap.vault_token_a).await, 1000);
+    assert_eq!(fixture.balance(swap.vault_token_b).await, 2000);
+    assert_eq!(fixture.balance(fixture.user_b.token_a).await, 1000);
+    assert_eq!(fixture.balance(fixture.user_a.token_b).await, 2000);
+
+    //a refunded escrow is just as final
+    let refunded = fixture.open_swap(1000, 2000).await;
+    fixture
+        .process(&[fixture.maker_deposit_ix(&refunded, 1000)], &[user_a])
+        .await
+        .unwrap();
+
+    let cancel_ix = fixture.cancel_ix(&refunded, &fixture.user_a, &fixture.user_b);
+    fixture
+        .process(std::slice::from_ref(&cancel_ix), &[user_a])
+        .await
+        .unwrap();
+    assert!(fixture.escrow(&refunded).await.settlement_locked);
+    assert_eq!(fixture.balance(fixture.user_a.token_a).await, 1000);
+
+    fixture
+        .create_ata(&refunded.vault_pda, &fixture.token_a_mint, 1000)
+        .await;
+
+    fixture.refresh_blockhash().await;
+    let user_a = &fixture.user_a.keypair;
+    assert!(fixture.process(&[cancel_ix], &[user_a]).await.is_err());
+
+    assert_eq!(fixture.balance(refunded.vault_token_a).await, 1000);
+    assert_eq!(fixture.balance(fixture.user_a.token_a).await, 1000);
+}
+
+#[tokio::test]
+async fn test_payouts_only_go_through_the_token_program() {
+    let program_id = Pubkey::new_unique();
+    let fake_token_program = Pubkey::new_unique();
+    let mut program_test =
+        ProgramTest::new("solana_escrow", program_id, processor!(process_instruction));
+    program_test.add_program(
+        "fake_token",
+        fake_token_program,
+        processor!(accept_anything),
+    );
+    let fixture = SwapFixture::start(program_test, program_id, 2000, 2000).await;
+    let user_a = &fixture.user_a.keypair;
+    let user_b = &fixture.user_b.keypair;
+    let fake_token = AccountMeta::new_readonly(fake_token_program, false);
+
+    //a deposit the fake program only pretends to make does not count
+    let swap = fixture.open_swap(1000, 2000).await;
+    let mut fake_deposit_ix = fixture.maker_deposit_ix(&swap, 1000);
+    fake_deposit_ix.accounts[4] = fake_token.clone();
+    assert!(
+        fixture
+            .process(&[fake_deposit_ix], &[user_a])
+            .await
+            .is_err()
+    );
+    assert!(!fixture.escrow(&swap).await.token_a_deposited);
+
+    fixture
+        .process(&[fixture.maker_deposit_ix(&swap, 1000)], &[user_a])
+        .await
+        .unwrap();
+
+    //neither a refund nor a payout may claim a transfer the fake program never made
+    let mut fake_cancel_ix = fixture.cancel_ix(&swap, &fixture.user_a, &fixture.user_b);
+    fake_cancel_ix.accounts[7] = fake_token.clone();
+    assert!(fixture.process(&[fake_cancel_ix], &[user_a]).await.is_err());
+
+    fixture
+        .process(
+            &[fixture.taker_deposit_ix(&swap, &fixture.user_b, 2000)],
+            &[user_b],
+        )
+        .await
+        .unwrap();
+
+    let mut fake_complete_ix = fixture.complete_swap_ix(&swap, &fixture.user_b, &fixture.user_b);
+    fake_complete_ix.accounts[7] = fake_token.clone();
+    assert!(
+        fixture
+            .process(&[fake_complete_ix], &[user_b])
+            .await
+            .is_err()
+    );
+
+    let escrow = fixture.escrow(&swap).await;
+    assert_eq!(escrow.status, EscrowStatus::Active);
+    assert!(!escrow.settlement_locked);
+    assert_eq!(fixture.balance(swap.vault_token_a).await, 1000);
+    assert_eq!(fixture.balance(swap.vault_token_b).await, 2000);
+
+    fixture
+        .process(
+            &[fixture.complete_swap_ix(&swap, &fixture.user_b, &fixture.user_b)],
+            &[user_b],
+        )
+        .await
+        .unwrap();
+    assert_eq!(fixture.balance(fixture.user_b.token_a).await, 1000);
+    assert_eq!(fixture.balance(fixture.user_a.token_b).await, 2000);
+}
+
+#[tokio::test]
+async fn test_complete_swap_creates_missing_payout_accounts() {
+    let fixture = SwapFixture::new(1000, 0).await;
+    let user_a = &fixture.user_a;
+
+    //a taker who never held token a, and a maker who closed their empty token b account
+    let taker_keypair = Keypair::new();
+    let taker = User {
+        token_a: spl_associated_token_account::get_associated_token_address(
+            &taker_keypair.pubkey(),
+            &fixture.token_a_mint,
+        ),
+        token_b: fixture
+            .create_ata(&taker_keypair.pubkey(), &fixture.token_b_mint, 2000)
+            .await,
+        keypair: taker_keypair,
+    };
+    fixture
+        .process(
+            &[
+                spl_token::instruction::close_account(
+                    &spl_token::id(),
+                    &user_a.token_b,
+                    &user_a.pubkey(),
+                    &user_a.pubkey(),
+                    &[],
+                )
+                .unwrap(),
+                system_instruction::transfer(&fixture.payer.pubkey(), &taker.pubkey(), 100_000_000),
+            ],
+            &[&user_a.keypair],
+        )
+        .await
+        .unwrap();
+
+    let swap = fixture.open_swap(1000, 2000).await;
+    fixture
+        .process(&[fixture.maker_deposit_ix(&swap, 1000)], &[&user_a.keypair])
+        .await
+        .unwrap();
+    fixture
+        .process(
+            &[fixture.taker_deposit_ix(&swap, &taker, 2000)],
+            &[&taker.keypair],
+        )
+        .await
+        .unwrap();
+
+    let complete_ix = fixture.complete_swap_ix(&swap, &taker, &taker);
+
+    //without the owners, mints and programs the accounts cannot be opened
+    assert!(
+        fixture
+            .process(std::slice::from_ref(&complete_ix), &[&taker.keypair])
+            .await
+            .is_err()
+    );
+
+    let complete_ix = with_payout_creation(
+        complete_ix,
+        &[user_a.pubkey(), taker.pubkey()],
+        &[fixture.token_a_mint, fixture.token_b_mint],
+    );
+    fixture
+        .process(&[complete_ix], &[&taker.keypair])
+        .await
+        .unwrap();
+
+    assert_eq!(fixture.escrow(&swap).await.status, EscrowStatus::Completed);
+    assert_eq!(fixture.balance(taker.token_a).await, 1000);
+    assert_eq!(fixture.balance(user_a.token_b).await, 2000);
+    assert_eq!(fixture.balance(swap.vault_token_a).await, 0);
+    assert_eq!(fixture.balance(swap.vault_token_b).await, 0);
+}
+
+#[tokio::test]
+async fn test_complete_swap_only_pays_into_associated_token_accounts() {
+    let fixture = SwapFixture::new(1000, 2000).await;
+    let user_a = &fixture.user_a;
+    let user_b = &fixture.user_b;
+
+    let swap = fixture.open_swap(1000, 2000).await;
+    fixture
+        .process(&[fixture.maker_deposit_ix(&swap, 1000)], &[&user_a.keypair])
+        .await
+        .unwrap();
+    fixture
+        .process(
+            &[fixture.taker_deposit_ix(&swap, user_b, 2000)],
+            &[&user_b.keypair],
+        )
+        .await
+        .unwrap();
+
+    //user_b's own token a account, but not the associated one
+    let other_account = Keypair::new();
+    let rent = fixture.banks_client.get_rent().await.unwrap();
+    fixture
+        .process(
+            &[
+                system_instruction::create_account(
+                    &fixture.payer.pubkey(),
+                    &other_account.pubkey(),
+                    rent.minimum_balance(TokenAccount::LEN),
+                    TokenAccount::LEN as u64,
+                    &spl_token::id(),
+                ),
+                spl_token::instruction::initialize_account3(
+                    &spl_token::id(),
+                    &other_account.pubkey(),
+                    &fixture.token_a_mint,
+                    &user_b.pubkey(),
+                )
+                .unwrap(),
+            ],
+            &[&other_account],
+        )
+        .await
+        .unwrap();
+
+    let misdirected = complete_swap_ix(
+        fixture.program_id,
+        user_b.pubkey(),
+        swap.escrow_account,
+        swap.vault_token_a,
+        swap.vault_token_b,
+        user_a.token_b,
+        other_account.pubkey(),
+    );
+    assert!(
+        fixture
+            .process(&[misdirected], &[&user_b.keypair])
+            .await
+            .is_err()
+    );
+
+    fixture
+        .process(
+            &[fixture.complete_swap_ix(&swap, user_b, user_b)],
+            &[&user_b.keypair],
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(fixture.balance(other_account.pubkey()).await, 0);
+    assert_eq!(fixture.balance(user_b.token_a).await, 1000);
+}
+
+//stand-in condition program: accepts when the first trailing account's first byte matches
+//the condition data, like a game result program comparing the recorded outcome
+fn accept_matching_result(
+    _program_id: &Pubkey,
+    accounts: &[solana_program::account_info::AccountInfo],
+    data: &[u8],
+) -> solana_program::entrypoint::ProgramResult {
+    let result = accounts
+        .get(2)
+        .ok_or(solana_program::program_error::ProgramError::NotEnoughAccountKeys)?;
+    if result.data.borrow().first() != data.first() {
+        return Err(solana_program::program_error::ProgramError::Custom(7));
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_conditional_escrow_settles_only_when_condition_passes() {
+    let program_id = Pubkey::new_unique();
+    let condition_program = Pubkey::new_unique();
+    let winning_result = Pubkey::new_unique();
+    let losing_result = Pubkey::new_unique();
+
+    let mut program_test =
+        ProgramTest::new("solana_escrow", program_id, processor!(process_instruction));
+    program_test.add_program(
+        "condition_program",
+        condition_program,
+        processor!(accept_matching_result),
+    );
+    for (result_account, outcome) in [(winning_result, 7), (losing_result, 3)] {
+        program_test.add_account(
+            result_account,
+            solana_sdk::account::Account {
+                lamports: 1_000_000,
+                data: vec![outcome],
+                owner: condition_program,
+                executable: false,
+                rent_epoch: 0,
+            },
+        );
+    }
+    let fixture = SwapFixture::start(program_test, program_id, 1000, 2000).await;
+    let (user_a, user_b) = (&fixture.user_a, &fixture.user_b);
+
+    let mut data = [0u8; MAX_CONDITION_DATA];
+    data[0] = 7;
+    let mut terms = swap_terms(fixture.token_a_mint, fixture.token_b_mint, 1000, 2000);
+    if let EscrowInstruction::InitEscrow { condition, .. } = &mut terms {
+        *condition = Some(Box::new(Condition {
+            program: condition_program,
+            data,
+            data_len: 1,
+        }));
+    }
+    let escrow = fixture.open_escrow(&terms).await;
+
+    let state = fixture.escrow(&escrow).await;
+    assert_eq!(state.kind, EscrowKind::Conditional);
+    assert_eq!(state.condition.data(), &[7]);
+
+    fixture
+        .process(
+            &[
+                fixture.maker_deposit_ix(&escrow, 1000),
+                fixture.taker_deposit_ix(&escrow, user_b, 2000),
+            ],
+            &[&user_a.keypair, &user_b.keypair],
+        )
+        .await
+        .unwrap();
+
+    let settle_ix = fixture.complete_swap_ix(&escrow, user_b, user_b);
+
+    //without the condition program the escrow cannot settle
+    assert!(
+        fixture
+            .process(std::slice::from_ref(&settle_ix), &[&user_b.keypair])
+            .await
+            .is_err()
+    );
+
+    //the condition program rejects a result that does not match
+    let mut losing_ix = settle_ix.clone();
+    losing_ix.accounts.extend([
+        AccountMeta::new_readonly(condition_program, false),
+        AccountMeta::new_readonly(losing_result, false),
+    ]);
+    assert!(
+        fixture
+            .process(&[losing_ix], &[&user_b.keypair])
+            .await
+            .is_err()
+    );
+    assert_eq!(fixture.balance(escrow.vault_token_a).await, 1000);
+
+    let mut winning_ix = settle_ix;
+    winning_ix.accounts.extend([
+        AccountMeta::new_readonly(condition_program, false),
+        AccountMeta::new_readonly(winning_result, false),
+    ]);
+    fixture
+        .process(&[winning_ix], &[&user_b.keypair])
+        .await
+        .unwrap();
+
+    assert_eq!(fixture.balance(user_a.token_b).await, 2000);
+    assert_eq!(fixture.balance(user_b.token_a).await, 1000);
+
+    let state = fixture.escrow(&escrow).await;
+    assert_eq!(state.status, EscrowStatus::Completed);
+}
+
+fn issue_credential_ix(
+    program_id: Pubkey,
+    issuer: Pubkey,
+    subject: Pubkey,
+    expires_at: i64,
+) -> Instruction {
+    Instruction::new_with_borsh(
+        program_id,
+        &EscrowInstruction::IssueCredential {
+            subject,
+            expires_at,
+        },
+        vec![
+            AccountMeta::new(issuer, true),
+            AccountMeta::new(credential_pda(&program_id, &issuer, &subject), false),
+            AccountMeta::new_readonly(solana_program::system_program::id(), false),
+        ],
+    )
+}
+
+fn credential_pda(program_id: &Pubkey, issuer: &Pubkey, subject: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"credential", issuer.as_ref(), subject.as_ref()],
+        program_id,
+    )
+    .0
+}
+
+#[tokio::test]
+async fn test_credential_gated_escrow_needs_a_live_credential() {
+    let mut fixture = SwapFixture::new(1000, 2000).await;
+    let program_id = fixture.program_id;
+    let (payer, user_a, user_b) = (&fixture.payer, &fixture.user_a, &fixture.user_b);
+    let issuer = Keypair::new();
+
+    fixture
+        .process(
+            &[system_instruction::transfer(
+                &payer.pubkey(),
+                &issuer.pubkey(),
+                1_000_000_000,
+            )],
+            &[],
+        )
+        .await
+        .unwrap();
+
+    let mut terms = swap_terms(fixture.token_a_mint, fixture.token_b_mint, 1000, 2000);
+    if let EscrowInstruction::InitEscrow {
+        credential_issuer, ..
+    } = &mut terms
+    {
+        *credential_issuer = Some(issuer.pubkey());
+    }
+    let escrow = fixture.open_escrow(&terms).await;
+
+    let state = fixture.escrow(&escrow).await;
+    assert_eq!(state.credential_issuer, issuer.pubkey());
+
+    //the maker is not gated
+    fixture
+        .process(
+            &[fixture.maker_deposit_ix(&escrow, 1000)],
+            &[&user_a.keypair],
+        )
+        .await
+        .unwrap();
+
+    let credential = credential_pda(&program_id, &issuer.pubkey(), &user_b.pubkey());
+    let taker_deposit_ix = fixture.taker_deposit_ix(&escrow, user_b, 2000);
+    let mut credentialed_deposit_ix = taker_deposit_ix.clone();
+    credentialed_deposit_ix
+        .accounts
+        .push(AccountMeta::new_readonly(credential, false));
+
+    //a taker without a credential is turned away
+    assert!(
+        fixture
+            .process(&[taker_deposit_ix], &[&user_b.keypair])
+            .await
+            .is_err()
+    );
+
+    let clock: Clock = fixture.banks_client.get_sysvar().await.unwrap();
+    let rent = fixture.banks_client.get_rent().await.unwrap();
+
+    //an expired credential is not enough either. lamports sent to the credential address
+    //beforehand must not stop the issuer
+    fixture
+        .process(
+            &[
+                system_instruction::transfer(&payer.pubkey(), &credential, rent.minimum_balance(0)),
+                issue_credential_ix(
+                    program_id,
+                    issuer.pubkey(),
+                    user_b.pubkey(),
+                    clock.unix_timestamp - 1,
+                ),
+            ],
+            &[&issuer],
+        )
+        .await
+        .unwrap();
+    assert!(
+        fixture
+            .process(
+                std::slice::from_ref(&credentialed_deposit_ix),
+                &[&user_b.keypair],
+            )
+            .await
+            .is_err()
+    );
+
+    //renewing the credential lets the taker in
+    fixture
+        .process(
+            &[issue_credential_ix(
+                program_id,
+                issuer.pubkey(),
+                user_b.pubkey(),
+                clock.unix_timestamp + 3600,
+            )],
+            &[&issuer],
+        )
+        .await
+        .unwrap();
+
+    let credential_account = fixture
+        .banks_client
+        .get_account(credential)
+        .await
+        .unwrap()
+        .unwrap();
+    let stored = Credential::try_from_slice(&credential_account.data).unwrap();
+    assert_eq!(stored.issuer, issuer.pubkey());
+    assert_eq!(stored.subject, user_b.pubkey());
+    assert_eq!(stored.expires_at, clock.unix_timestamp + 3600);
+
+    fixture.refresh_blockhash().await;
+    fixture
+        .process(&[credentialed_deposit_ix], &[&fixture.user_b.keypair])
+        .await
+        .unwrap();
+
+    let state = fixture.escrow(&escrow).await;
+    assert!(state.token_b_deposited);
+    assert_eq!(state.user_b, fixture.user_b.pubkey());
+}
+
+#[tokio::test]
+async fn test_reference_is_logged_and_written_as_settlement_memo() {
+    let fixture = SwapFixture::new(1000, 2000).await;
+    let (user_a, user_b) = (&fixture.user_a, &fixture.user_b);
+
+    let mut reference = [0u8; 32];
+    reference[..12].copy_from_slice(b"INV-2024-042");
+    let reference_hex: String = reference.iter().map(|b| format!("{:02x}", b)).collect();
+
+    let mut terms = swap_terms(fixture.token_a_mint, fixture.token_b_mint, 1000, 2000);
+    if let EscrowInstruction::InitEscrow {
+        reference: terms_reference,
+        ..
+    } = &mut terms
+    {
+        *terms_reference = Some(reference);
+    }
+    let escrow = fixture.open_escrow(&terms).await;
+
+    let state = fixture.escrow(&escrow).await;
+    assert_eq!(state.reference, reference);
+
+    fixture
+        .process(
+            &[
+                fixture.maker_deposit_ix(&escrow, 1000),
+                fixture.taker_deposit_ix(&escrow, user_b, 2000),
+            ],
+            &[&user_a.keypair, &user_b.keypair],
+        )
+        .await
+        .unwrap();
+
+    let mut settle_ix = fixture.complete_swap_ix(&escrow, user_b, user_b);
+    settle_ix
+        .accounts
+        .push(AccountMeta::new_readonly(spl_memo::id(), false));
+
+    let transaction = Transaction::new_signed_with_payer(
+        &[settle_ix],
+        Some(&fixture.payer.pubkey()),
+        &[&fixture.payer, &user_b.keypair],
+        fixture.recent_blockhash,
+    );
+    let result = process_with_metadata(&fixture.banks_client, transaction).await;
+    assert!(result.result.is_ok());
+
+    //the natively processed program's own msg! output never reaches the transaction logs,
+    //the memo program's does
+    let logs = result.metadata.unwrap().log_messages;
+    assert!(logs.iter().any(|log| log.contains(&format!(
+        "escrow {} ref {}",
+        escrow.escrow_account, reference_hex
+    ))));
+
+    assert_eq!(fixture.balance(user_a.token_b).await, 2000);
+    assert_eq!(fixture.balance(user_b.token_a).await, 1000);
+}
+
+//the zero-copy layout has to stay byte for byte what borsh writes, off-chain readers decode
+//it with borsh. accounts from before a layout change are shorter than Escrow::LEN and do not load
+#[test]
+fn test_escrow_layout_matches_borsh_encoding() {
+    let mut escrow = Escrow {
+        user_a: Pubkey::new_unique(),
+        token_a_mint: Pubkey::new_unique(),
+        amount_a: 1000.into(),
+        amount_b: 2000.into(),
+        token_a_deposited: true,
+        status: EscrowStatus::Cancelled,
+        kind: EscrowKind::Conditional,
+        keeper_tip: 50_000.into(),
+        listed: true,
+        reference: [7; 32],
+        settlement_locked: true,
+        ..Escrow::default()
+    };
+    escrow.auction.end_time = (-1).into();
+    escrow.multisig.approve(
+        2,
+        MakerAction::with_amounts(MakerActionKind::UpdateTerms, 1, 2),
+    );
+
+    let encoded = borsh::to_vec(&escrow).unwrap();
+    assert_eq!(encoded.len(), Escrow::LEN);
+    assert_eq!(encoded.as_slice(), bytemuck::bytes_of(&escrow));
+    assert_eq!(*Escrow::load(&encoded).unwrap(), escrow);
+
+    //status lives right after the vault pda, an unknown variant is refused
+    let mut corrupted = encoded.clone();
+    corrupted[178] = 9;
+    assert!(Escrow::load(&corrupted).is_err());
+
+    //so is a settlement lock that is neither true nor false
+    let mut corrupted = encoded;
+    corrupted[Escrow::LEN - 1] = 2;
+    assert!(Escrow::load(&corrupted).is_err());
+}
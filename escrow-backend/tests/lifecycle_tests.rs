@@ -0,0 +1,303 @@
+//random swap lifecycles run against a model of the escrow. after every step the program must
+//have accepted or rejected it like the model did, both tokens must be conserved and the
+//escrow's state must match the balances it claims to hold
+mod common;
+
+use common::{FixtureEscrow, SwapFixture, User, mint_supply};
+use proptest::prelude::*;
+use proptest::test_runner::TestCaseError;
+use solana_escrow::EscrowStatus;
+use solana_program::instruction::Instruction;
+use solana_sdk::pubkey::Pubkey;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Party {
+    Maker,
+    Taker,
+    Stranger,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Step {
+    //an exact deposit is the amount the escrow asks for, otherwise it is one token more
+    Deposit { party: Party, exact: bool },
+    CompleteSwap { caller: Party },
+    Cancel { caller: Party },
+}
+
+fn party() -> impl Strategy<Value = Party> {
+    prop_oneof![
+        Just(Party::Maker),
+        Just(Party::Taker),
+        Just(Party::Stranger)
+    ]
+}
+
+fn step() -> impl Strategy<Value = Step> {
+    prop_oneof![
+        3 => (party(), prop::bool::weighted(0.9))
+            .prop_map(|(party, exact)| Step::Deposit { party, exact }),
+        1 => party().prop_map(|caller| Step::CompleteSwap { caller }),
+        1 => party().prop_map(|caller| Step::Cancel { caller }),
+    ]
+}
+
+//balance slots, one per party and the vault
+const VAULT: usize = 3;
+
+fn slot(party: Party) -> usize {
+    match party {
+        Party::Maker => 0,
+        Party::Taker => 1,
+        Party::Stranger => 2,
+    }
+}
+
+//what the program should have done with the steps so far
+struct Model {
+    amount_a: u64,
+    amount_b: u64,
+    user_b: Option<Party>,
+    token_a_deposited: bool,
+    token_b_deposited: bool,
+    status: EscrowStatus,
+    token_a: [u64; 4],
+    token_b: [u64; 4],
+}
+
+fn move_tokens(balances: &mut [u64; 4], from: usize, to: usize, amount: u64) {
+    balances[from] -= amount;
+    balances[to] += amount;
+}
+
+impl Model {
+    fn new(amount_a: u64, amount_b: u64, funds_a: u64, funds_b: u64) -> Self {
+        Model {
+            amount_a,
+            amount_b,
+            user_b: None,
+            token_a_deposited: false,
+            token_b_deposited: false,
+            status: EscrowStatus::Active,
+            token_a: [funds_a, 0, 0, 0],
+            token_b: [0, funds_b, funds_b, 0],
+        }
+    }
+
+    //user_b's token accounts belong to whoever deposited token b, before that to the taker
+    fn taker(&self) -> Party {
+        self.user_b.unwrap_or(Party::Taker)
+    }
+
+    fn is_party(&self, caller: Party) -> bool {
+        caller == Party::Maker || self.user_b == Some(caller)
+    }
+
+    //applies `step` when the program should accept it and tells whether it should
+    fn apply(&mut self, step: Step) -> bool {
+        if self.status != EscrowStatus::Active {
+            return false;
+        }
+
+        let taker = slot(self.taker());
+
+        match step {
+            Step::Deposit {
+                party: Party::Maker,
+                exact,
+            } => {
+                if !exact || self.token_a_deposited {
+                    return false;
+                }
+                move_tokens(&mut self.token_a, slot(Party::Maker), VAULT, self.amount_a);
+                self.token_a_deposited = true;
+            }
+            Step::Deposit { party, exact } => {
+                if !exact || self.token_b_deposited || self.user_b.is_some_and(|b| b != party) {
+                    return false;
+                }
+                move_tokens(&mut self.token_b, slot(party), VAULT, self.amount_b);
+                self.token_b_deposited = true;
+                self.user_b = Some(party);
+            }
+            Step::CompleteSwap { caller } => {
+                if !self.is_party(caller) || !self.token_a_deposited || !self.token_b_deposited {
+                    return false;
+                }
+                move_tokens(&mut self.token_a, VAULT, taker, self.amount_a);
+                move_tokens(&mut self.token_b, VAULT, slot(Party::Maker), self.amount_b);
+                self.token_a_deposited = false;
+                self.token_b_deposited = false;
+                self.status = EscrowStatus::Completed;
+            }
+            Step::Cancel { caller } => {
+                if !self.is_party(caller) || (self.token_a_deposited && self.token_b_deposited) {
+                    return false;
+                }
+                if self.token_a_deposited {
+                    move_tokens(&mut self.token_a, VAULT, slot(Party::Maker), self.amount_a);
+                }
+                if self.token_b_deposited {
+                    move_tokens(&mut self.token_b, VAULT, taker, self.amount_b);
+                }
+                self.token_a_deposited = false;
+                self.token_b_deposited = false;
+                self.status = EscrowStatus::Cancelled;
+            }
+        }
+
+        true
+    }
+}
+
+fn user(fixture: &SwapFixture, party: Party) -> &User {
+    match party {
+        Party::Maker => &fixture.user_a,
+        Party::Taker => &fixture.user_b,
+        Party::Stranger => &fixture.stranger,
+    }
+}
+
+fn step_ix(fixture: &SwapFixture, swap: &FixtureEscrow, model: &Model, step: Step) -> Instruction {
+    let taker = user(fixture, model.taker());
+
+    match step {
+        Step::Deposit {
+            party: Party::Maker,
+            exact,
+        } => fixture.maker_deposit_ix(swap, model.amount_a + u64::from(!exact)),
+        Step::Deposit { party, exact } => fixture.taker_deposit_ix(
+            swap,
+            user(fixture, party),
+            model.amount_b + u64::from(!exact),
+        ),
+        Step::CompleteSwap { caller } => {
+            fixture.complete_swap_ix(swap, user(fixture, caller), taker)
+        }
+        Step::Cancel { caller } => fixture.cancel_ix(swap, user(fixture, caller), taker),
+    }
+}
+
+fn signer(step: Step) -> Party {
+    match step {
+        Step::Deposit { party, .. } => party,
+        Step::CompleteSwap { caller } | Step::Cancel { caller } => caller,
+    }
+}
+
+async fn check_state(
+    fixture: &SwapFixture,
+    swap: &FixtureEscrow,
+    model: &Model,
+) -> Result<(), TestCaseError> {
+    let escrow = fixture.escrow(swap).await;
+    prop_assert_eq!(&escrow.status, &model.status);
+    prop_assert_eq!(escrow.token_a_deposited, model.token_a_deposited);
+    prop_assert_eq!(escrow.token_b_deposited, model.token_b_deposited);
+    prop_assert_eq!(
+        escrow.settlement_locked,
+        model.status != EscrowStatus::Active
+    );
+    prop_assert_eq!(escrow.amount_a.get(), model.amount_a);
+    prop_assert_eq!(escrow.amount_b.get(), model.amount_b);
+    prop_assert_eq!(
+        escrow.user_b,
+        model
+            .user_b
+            .map_or(Pubkey::default(), |party| user(fixture, party).pubkey())
+    );
+
+    let token_a = [
+        fixture.user_a.token_a,
+        fixture.user_b.token_a,
+        fixture.stranger.token_a,
+        swap.vault_token_a,
+    ];
+    let token_b = [
+        fixture.user_a.token_b,
+        fixture.user_b.token_b,
+        fixture.stranger.token_b,
+        swap.vault_token_b,
+    ];
+
+    let mut balances_a = [0; 4];
+    let mut balances_b = [0; 4];
+    for (balance, account) in balances_a.iter_mut().zip(token_a) {
+        *balance = fixture.balance(account).await;
+    }
+    for (balance, account) in balances_b.iter_mut().zip(token_b) {
+        *balance = fixture.balance(account).await;
+    }
+    prop_assert_eq!(balances_a, model.token_a);
+    prop_assert_eq!(balances_b, model.token_b);
+
+    //nothing minted, burned or moved outside the swap
+    let supply_a = mint_supply(&fixture.banks_client, fixture.token_a_mint).await;
+    let supply_b = mint_supply(&fixture.banks_client, fixture.token_b_mint).await;
+    prop_assert_eq!(balances_a.iter().sum::<u64>(), supply_a);
+    prop_assert_eq!(balances_b.iter().sum::<u64>(), supply_b);
+
+    //the vault holds exactly what an active escrow says was deposited, and nothing after
+    let active = escrow.status == EscrowStatus::Active;
+    let expected_vault_a = if active && escrow.token_a_deposited {
+        model.amount_a
+    } else {
+        0
+    };
+    let expected_vault_b = if active && escrow.token_b_deposited {
+        model.amount_b
+    } else {
+        0
+    };
+    prop_assert_eq!(balances_a[VAULT], expected_vault_a);
+    prop_assert_eq!(balances_b[VAULT], expected_vault_b);
+
+    Ok(())
+}
+
+//every step is sent `repeat` times in a row, each time in a new transaction
+async fn run_lifecycle(
+    amount_a: u64,
+    amount_b: u64,
+    steps: Vec<(Step, usize)>,
+) -> Result<(), TestCaseError> {
+    //enough for a deposit one token over the amount to fail on the amount, not the balance
+    let (funds_a, funds_b) = (amount_a * 2, amount_b * 2);
+    let mut fixture = SwapFixture::new(funds_a, funds_b).await;
+    let swap = fixture.open_swap(amount_a, amount_b).await;
+    let mut model = Model::new(amount_a, amount_b, funds_a, funds_b);
+
+    check_state(&fixture, &swap, &model).await?;
+
+    for (step, repeat) in steps {
+        for _ in 0..repeat {
+            fixture.refresh_blockhash().await;
+
+            let instruction = step_ix(&fixture, &swap, &model, step);
+            let signer = &user(&fixture, signer(step)).keypair;
+            let accepted = fixture.process(&[instruction], &[signer]).await.is_ok();
+
+            prop_assert_eq!(accepted, model.apply(step), "{:?}", step);
+            check_state(&fixture, &swap, &model).await?;
+        }
+    }
+
+    Ok(())
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(32))]
+
+    #[test]
+    fn random_lifecycles_match_the_model(
+        amount_a in 1..=1_000_000_000u64,
+        amount_b in 1..=1_000_000_000u64,
+        steps in prop::collection::vec((step(), 1..=2usize), 1..10),
+    ) {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(run_lifecycle(amount_a, amount_b, steps))?;
+    }
+}
@@ -0,0 +1,109 @@
+use proptest::prelude::*;
+use solana_escrow::PriceOracle;
+use solana_escrow::errors::EscrowError;
+use solana_escrow::math::{self, Rounding};
+use solana_escrow::oracle::{self, MAX_TOKEN_DECIMALS};
+
+fn oracle_terms(spread_bps: u16, token_a_decimals: u8, token_b_decimals: u8) -> PriceOracle {
+    PriceOracle {
+        spread_bps: spread_bps.into(),
+        token_a_decimals,
+        token_b_decimals,
+        ..PriceOracle::default()
+    }
+}
+
+proptest! {
+    //the rounded result brackets the exact quotient, and the two modes differ by at most one
+    #[test]
+    fn mul_div_brackets_exact_quotient(a in any::<u64>(), b in any::<u64>(), c in 1..=u64::MAX) {
+        let exact = a as u128 * b as u128;
+        let down = math::mul_div(a, b, c, Rounding::Down);
+        let up = math::mul_div(a, b, c, Rounding::Up);
+
+        if exact / c as u128 > u64::MAX as u128 {
+            prop_assert_eq!(down, Err(EscrowError::MathOverflow));
+        } else {
+            let down = down.unwrap();
+            prop_assert!(down as u128 * c as u128 <= exact);
+            prop_assert!((down as u128 + 1) * c as u128 > exact);
+        }
+
+        if let Ok(up) = up {
+            prop_assert!(up as u128 * c as u128 >= exact);
+            if let Ok(down) = math::mul_div(a, b, c, Rounding::Down) {
+                prop_assert!(up - down <= 1);
+                prop_assert_eq!(up == down, exact.is_multiple_of(c as u128));
+            }
+        }
+    }
+
+    #[test]
+    fn mul_div_by_zero_is_an_error(a in any::<u64>(), b in any::<u64>()) {
+        prop_assert_eq!(math::mul_div(a, b, 0, Rounding::Down), Err(EscrowError::MathOverflow));
+        prop_assert_eq!(math::mul_div(a, b, 0, Rounding::Up), Err(EscrowError::MathOverflow));
+    }
+
+    #[test]
+    fn mul_div_u128_never_panics(a in any::<u128>(), b in any::<u128>(), c in any::<u128>()) {
+        let _ = math::mul_div_u128(a, b, c, Rounding::Down);
+        let _ = math::mul_div_u128(a, b, c, Rounding::Up);
+    }
+
+    #[test]
+    fn add_and_sub_round_trip(a in any::<u64>(), b in any::<u64>()) {
+        match math::add(a, b) {
+            Ok(sum) => prop_assert_eq!(math::sub(sum, b), Ok(a)),
+            Err(error) => {
+                prop_assert_eq!(error, EscrowError::MathOverflow);
+                prop_assert!(a.checked_add(b).is_none());
+            }
+        }
+        prop_assert_eq!(math::sub(a, b).is_ok(), a >= b);
+    }
+
+    //any decimals, amount and price give an amount or an error, never a panic
+    #[test]
+    fn taker_amount_never_panics(
+        amount_a in any::<u64>(),
+        price in any::<u64>(),
+        spread_bps in any::<u16>(),
+        token_a_decimals in any::<u8>(),
+        token_b_decimals in any::<u8>(),
+    ) {
+        let terms = oracle_terms(spread_bps, token_a_decimals, token_b_decimals);
+        let _ = oracle::taker_amount(&terms, amount_a, price);
+    }
+
+    //with matching decimals and no spread the taker pays the price rounded up, whatever the
+    //decimals are
+    #[test]
+    fn taker_amount_is_the_rounded_up_price(
+        amount_a in any::<u64>(),
+        price in any::<u64>(),
+        decimals in 0..=MAX_TOKEN_DECIMALS,
+    ) {
+        let expected = math::mul_div(amount_a, price, 1_000_000_000, Rounding::Up);
+        prop_assert_eq!(oracle::taker_amount(&oracle_terms(0, decimals, decimals), amount_a, price), expected);
+    }
+
+    //the spread only ever adds to what the taker pays, and a token b with more decimals
+    //scales the amount by exactly that power of ten
+    #[test]
+    fn taker_amount_scales_with_spread_and_decimals(
+        amount_a in 0..=1_000_000_000u64,
+        price in 0..=1_000_000_000_000u64,
+        spread_bps in any::<u16>(),
+        token_a_decimals in 0..=MAX_TOKEN_DECIMALS,
+        extra_decimals in 0..=6u8,
+    ) {
+        let token_b_decimals = token_a_decimals.saturating_add(extra_decimals).min(MAX_TOKEN_DECIMALS);
+        let base = oracle::taker_amount(&oracle_terms(0, token_a_decimals, token_a_decimals), amount_a, price).unwrap();
+        let spread = oracle::taker_amount(&oracle_terms(spread_bps, token_a_decimals, token_a_decimals), amount_a, price).unwrap();
+        prop_assert!(spread >= base);
+
+        let scale = 10u64.pow((token_b_decimals - token_a_decimals) as u32);
+        let scaled = oracle::taker_amount(&oracle_terms(0, token_a_decimals, token_b_decimals), amount_a, price).unwrap();
+        prop_assert_eq!(scaled, math::mul_div(amount_a, price * scale, 1_000_000_000, Rounding::Up).unwrap());
+    }
+}
@@ -224,6 +224,8 @@ export function EscrowInterface() {
         escrowAccount: escrowAccountPubkey,
         tokenMint,
         amount,
+        expectedMintA: escrowData.token_a_mint,
+        expectedAmountAMin: escrowData.amount_a,
       });
 
       depositForm.reset();
@@ -16,6 +16,15 @@ export const ESCROW_PROGRAM_ID = new PublicKey(
   process.env.NEXT_PUBLIC_PROGRAM_ID!
 );
 
+//global config pda, required by instructions that can be paused
+export const CONFIG_PDA = PublicKey.findProgramAddressSync(
+  [Buffer.from('config')],
+  ESCROW_PROGRAM_ID
+)[0];
+
+//borsh size of the on-chain Escrow struct
+export const ESCROW_ACCOUNT_SIZE = 1117;
+
 //instruction data for init escrow
 function initEscrowData(
   tokenAMint: PublicKey,
@@ -23,7 +32,7 @@ function initEscrowData(
   amountA: bigint,
   amountB: bigint
 ): Buffer {
-  const data = Buffer.alloc(1 + 32 + 32 + 8 + 8);
+  const data = Buffer.alloc(1 + 32 + 32 + 8 + 8 + 1 + 1 + 1 + 1 + 8 + 1 + 1 + 1 + 1 + 1);
   let offset = 0;
 
   data.writeUInt8(0, offset);
@@ -41,6 +50,46 @@ function initEscrowData(
   data.writeBigUInt64LE(amountB, offset);
   offset += 8;
 
+  //hash_lock: None
+  data.writeUInt8(0, offset);
+  offset += 1;
+
+  //delegated_source: None
+  data.writeUInt8(0, offset);
+  offset += 1;
+
+  //maker_multisig: None
+  data.writeUInt8(0, offset);
+  offset += 1;
+
+  //permissionless_settlement: false
+  data.writeUInt8(0, offset);
+  offset += 1;
+
+  //keeper_tip: 0
+  data.writeBigUInt64LE(BigInt(0), offset);
+  offset += 8;
+
+  //auction: None
+  data.writeUInt8(0, offset);
+  offset += 1;
+
+  //oracle: None
+  data.writeUInt8(0, offset);
+  offset += 1;
+
+  //condition: None
+  data.writeUInt8(0, offset);
+  offset += 1;
+
+  //credential_issuer: None
+  data.writeUInt8(0, offset);
+  offset += 1;
+
+  //reference: None
+  data.writeUInt8(0, offset);
+  offset += 1;
+
   return data;
 }
 
@@ -65,13 +114,15 @@ export async function initializeEscrow(params: {
 
   //new escrow account
   const escrowAccount = Keypair.generate();
-  const rentExemption = await connection.getMinimumBalanceForRentExemption(200); //TODO
+  const rentExemption = await connection.getMinimumBalanceForRentExemption(
+    ESCROW_ACCOUNT_SIZE
+  );
 
   const createAccountTx = SystemProgram.createAccount({
     fromPubkey: userPublicKey,
     newAccountPubkey: escrowAccount.publicKey,
     lamports: rentExemption,
-    space: 200, //TODO
+    space: ESCROW_ACCOUNT_SIZE,
     programId: ESCROW_PROGRAM_ID,
   });
 
@@ -102,6 +153,7 @@ export async function initializeEscrow(params: {
       { pubkey: userPublicKey, isSigner: true, isWritable: true },
       { pubkey: escrowAccount.publicKey, isSigner: false, isWritable: true },
       { pubkey: SYSVAR_RENT_PUBKEY, isSigner: false, isWritable: false },
+      { pubkey: CONFIG_PDA, isSigner: false, isWritable: false },
     ],
     programId: ESCROW_PROGRAM_ID,
     data: instructionData,
@@ -132,8 +184,13 @@ export async function initializeEscrow(params: {
   };
 }
 
-function depositEscrowData(amount: bigint): Buffer {
-  const data = Buffer.alloc(1 + 8);
+//expected_* are the terms the depositor saw, the program rejects the deposit if they changed
+function depositEscrowData(
+  amount: bigint,
+  expectedAmountAMin: bigint,
+  expectedMintA: PublicKey
+): Buffer {
+  const data = Buffer.alloc(1 + 8 + 8 + 32 + 1);
   let offset = 0;
 
   data.writeUInt8(1, offset);
@@ -142,6 +199,16 @@ function depositEscrowData(amount: bigint): Buffer {
   data.writeBigUInt64LE(amount, offset);
   offset += 8;
 
+  data.writeBigUInt64LE(expectedAmountAMin, offset);
+  offset += 8;
+
+  expectedMintA.toBuffer().copy(data, offset);
+  offset += 32;
+
+  //settle: false, the swap is completed in its own transaction
+  data.writeUInt8(0, offset);
+  offset += 1;
+
   return data;
 }
 
@@ -151,8 +218,18 @@ export async function depositEscrow(params: {
   escrowAccount: PublicKey;
   tokenMint: PublicKey;
   amount: bigint;
+  expectedMintA: PublicKey;
+  expectedAmountAMin: bigint;
 }) {
-  const { connection, userWallet, escrowAccount, tokenMint, amount } = params;
+  const {
+    connection,
+    userWallet,
+    escrowAccount,
+    tokenMint,
+    amount,
+    expectedMintA,
+    expectedAmountAMin,
+  } = params;
 
   if (!userWallet.publicKey || !userWallet.signTransaction) {
     toast.error('Wallet not connected');
@@ -190,9 +267,10 @@ export async function depositEscrow(params: {
       { pubkey: userTokenAccount, isSigner: false, isWritable: true },
       { pubkey: vaultTokenAccount, isSigner: false, isWritable: true },
       { pubkey: TOKEN_PROGRAM_ID, isSigner: false, isWritable: false },
+      { pubkey: CONFIG_PDA, isSigner: false, isWritable: false },
     ],
     programId: ESCROW_PROGRAM_ID,
-    data: depositEscrowData(amount),
+    data: depositEscrowData(amount, expectedAmountAMin, expectedMintA),
   });
 
   transaction.add(depositIx);